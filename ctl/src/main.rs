@@ -13,6 +13,8 @@ pub enum IpcCommand {
     Set {
         monitor: Option<String>,
         path: PathBuf,
+        #[serde(default)]
+        transition_pos: Option<String>,
     },
     Next {
         monitor: Option<String>,
@@ -30,6 +32,19 @@ pub enum IpcCommand {
     Resume {
         monitor: Option<String>,
     },
+    Stats {
+        monitor: Option<String>,
+    },
+    ExplainConfig {
+        monitor: String,
+    },
+    ValidateConfig {
+        path: Option<PathBuf>,
+    },
+    SetProfile {
+        name: Option<String>,
+    },
+    ListProfiles,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,8 +52,16 @@ pub enum IpcCommand {
 pub enum IpcResponse {
     Ok { message: Option<String> },
     Error { message: String },
-    Status { monitors: Vec<MonitorStatus> },
+    Status {
+        monitors: Vec<MonitorStatus>,
+        #[serde(default)]
+        gpu_memory: u64,
+    },
     Wallpaper { path: Option<PathBuf> },
+    Stats { monitors: Vec<MonitorStats> },
+    ConfigExplain { monitor: String, values: Vec<ConfigValue> },
+    ConfigCheck { path: PathBuf, diagnostics: Vec<Diagnostic> },
+    Profiles { active: Option<String>, profiles: Vec<String> },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,6 +71,54 @@ pub struct MonitorStatus {
     pub workspace: Option<i32>,
     pub slideshow_active: bool,
     pub slideshow_paused: bool,
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub gpu_memory: u64,
+    #[serde(default)]
+    pub cpu_memory: u64,
+    #[serde(default)]
+    pub schedule_entry: Option<String>,
+    #[serde(default)]
+    pub daylight: Option<f32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MonitorStats {
+    pub name: String,
+    pub frames: u64,
+    pub late_frames: u64,
+    pub dropped_frames: u64,
+    pub refresh_ms: Option<f64>,
+    pub presentation_feedback: bool,
+    pub frame_time: TimingStats,
+    pub transitions: TimingStats,
+    pub uploads: TimingStats,
+    pub decodes: TimingStats,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TimingStats {
+    pub count: u64,
+    pub last_ms: f64,
+    pub avg_ms: f64,
+    pub p95_ms: f64,
+    pub max_ms: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigValue {
+    pub key: String,
+    pub value: Option<String>,
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub severity: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
 }
 
 /// Control tool for Canviz wallpaper daemon
@@ -72,6 +143,11 @@ enum Commands {
         /// Monitor name (all monitors if not specified)
         #[arg(short, long)]
         monitor: Option<String>,
+
+        /// Transition origin: "x,y" fractions from the top-left, a named
+        /// position (center, top-left, ...) or "cursor"
+        #[arg(short = 'p', long)]
+        transition_pos: Option<String>,
     },
 
     /// Switch to next wallpaper in slideshow
@@ -111,6 +187,50 @@ enum Commands {
         #[arg(short, long)]
         monitor: Option<String>,
     },
+
+    /// Show frame timing statistics
+    Stats {
+        /// Monitor name (all monitors if not specified)
+        #[arg(short, long)]
+        monitor: Option<String>,
+
+        /// Print the statistics as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Switch profiles, or list them without a name
+    Profile {
+        /// Profile to switch to
+        name: Option<String>,
+
+        /// Switch back to no profile
+        #[arg(long, conflicts_with = "name")]
+        none: bool,
+    },
+
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommands {
+    /// Show a monitor's effective settings and which layer each comes from
+    Explain {
+        /// Monitor name
+        #[arg(short, long)]
+        monitor: String,
+    },
+
+    /// Check a config file against the running daemon's monitors, exits
+    /// non-zero if it has errors
+    Check {
+        /// Config file [default: the one the daemon loaded]
+        path: Option<PathBuf>,
+    },
 }
 
 fn socket_path() -> Result<PathBuf> {
@@ -141,20 +261,20 @@ async fn send_command(command: IpcCommand) -> Result<IpcResponse> {
         .await
         .wrap_err("Failed to send command")?;
 
-    // Read response
-    let mut buf = vec![0u8; 8192];
-    let n = stream
-        .read(&mut buf)
+    // Read response, until the daemon closes the connection
+    let mut buf = Vec::new();
+    stream
+        .read_to_end(&mut buf)
         .await
         .wrap_err("Failed to read response")?;
 
     let response: IpcResponse =
-        serde_json::from_slice(&buf[..n]).wrap_err("Failed to parse response")?;
+        serde_json::from_slice(&buf).wrap_err("Failed to parse response")?;
 
     Ok(response)
 }
 
-fn print_status(monitors: &[MonitorStatus]) {
+fn print_status(monitors: &[MonitorStatus], gpu_memory: u64) {
     println!("Canviz Status");
     println!("{}", "=".repeat(60));
 
@@ -171,6 +291,12 @@ fn print_status(monitors: &[MonitorStatus]) {
         if let Some(ws) = monitor.workspace {
             println!("  Workspace: {}", ws);
         }
+        if let Some(entry) = &monitor.schedule_entry {
+            println!("  Schedule: {}", entry);
+        }
+        if let Some(daylight) = monitor.daylight {
+            println!("  Daylight: {:.0}% day", daylight * 100.0);
+        }
         println!(
             "  Slideshow: {}",
             if monitor.slideshow_active {
@@ -183,7 +309,100 @@ fn print_status(monitors: &[MonitorStatus]) {
                 "disabled"
             }
         );
+        println!(
+            "  Memory: {:.1} MiB GPU, {:.1} MiB shared",
+            monitor.gpu_memory as f64 / (1024.0 * 1024.0),
+            monitor.cpu_memory as f64 / (1024.0 * 1024.0)
+        );
+        if let Some(error) = &monitor.error {
+            println!("  Error: {}", error);
+        }
+    }
+
+    // Monitors showing the same image share its texture
+    println!(
+        "\nTotal GPU memory: {:.1} MiB",
+        gpu_memory as f64 / (1024.0 * 1024.0)
+    );
+}
+
+fn print_stats(monitors: &[MonitorStats]) {
+    println!("Canviz Frame Stats");
+    println!("{}", "=".repeat(60));
+
+    for monitor in monitors {
+        println!("\nMonitor: {}", monitor.name);
+        let refresh = monitor
+            .refresh_ms
+            .map(|ms| format!("{:.2} ms", ms))
+            .unwrap_or_else(|| "unknown".to_string());
+        let source = if monitor.presentation_feedback {
+            "presentation feedback"
+        } else {
+            "draw times"
+        };
+        println!("  Refresh: {} (timed from {})", refresh, source);
+        println!(
+            "  Frames: {} drawn, {} late, {} dropped",
+            monitor.frames, monitor.late_frames, monitor.dropped_frames
+        );
+        println!(
+            "  {:<12} {:>7} {:>9} {:>9} {:>9} {:>9}",
+            "", "count", "last ms", "avg ms", "p95 ms", "max ms"
+        );
+        for (label, timing) in [
+            ("Frame time", &monitor.frame_time),
+            ("Transitions", &monitor.transitions),
+            ("Uploads", &monitor.uploads),
+            ("Decodes", &monitor.decodes),
+        ] {
+            println!(
+                "  {:<12} {:>7} {:>9.2} {:>9.2} {:>9.2} {:>9.2}",
+                label, timing.count, timing.last_ms, timing.avg_ms, timing.p95_ms, timing.max_ms
+            );
+        }
+    }
+}
+
+fn print_config_explain(monitor: &str, values: &[ConfigValue]) {
+    println!("Effective config of {}", monitor);
+    println!("{}", "=".repeat(60));
+
+    let width = values.iter().map(|v| v.key.len()).max().unwrap_or(0);
+    for value in values {
+        match (&value.value, &value.source) {
+            (Some(v), Some(source)) => {
+                println!("  {:<width$}  {}  (from {})", value.key, v, source, width = width)
+            }
+            (Some(v), None) => println!("  {:<width$}  {}", value.key, v, width = width),
+            (None, _) => println!("  {:<width$}  (unset)", value.key, width = width),
+        }
+    }
+}
+
+/// Print config problems, returning whether there were errors
+fn print_config_check(path: &std::path::Path, diagnostics: &[Diagnostic]) -> bool {
+    for diagnostic in diagnostics {
+        match (diagnostic.line, diagnostic.column) {
+            (Some(line), Some(column)) => println!(
+                "{}:{}:{}: {}: {}",
+                path.display(),
+                line,
+                column,
+                diagnostic.severity,
+                diagnostic.message
+            ),
+            _ => println!("{}: {}: {}", path.display(), diagnostic.severity, diagnostic.message),
+        }
     }
+
+    let errors = diagnostics.iter().filter(|d| d.severity == "error").count();
+    if diagnostics.is_empty() {
+        println!("{}: OK", path.display());
+    } else {
+        println!("{} error(s), {} warning(s)", errors, diagnostics.len() - errors);
+    }
+    errors > 0
 }
 
 #[tokio::main]
@@ -191,12 +410,18 @@ async fn main() -> Result<()> {
     color_eyre::install()?;
 
     let args = Args::parse();
+    let json = matches!(args.command, Commands::Stats { json: true, .. });
 
     let command = match args.command {
         Commands::Status => IpcCommand::Status,
-        Commands::Set { path, monitor } => IpcCommand::Set {
+        Commands::Set {
+            path,
+            monitor,
+            transition_pos,
+        } => IpcCommand::Set {
             monitor,
             path: path.canonicalize().unwrap_or(path),
+            transition_pos,
         },
         Commands::Next { monitor } => IpcCommand::Next { monitor },
         Commands::Previous { monitor } => IpcCommand::Previous { monitor },
@@ -204,6 +429,17 @@ async fn main() -> Result<()> {
         Commands::Get { monitor } => IpcCommand::GetWallpaper { monitor },
         Commands::Pause { monitor } => IpcCommand::Pause { monitor },
         Commands::Resume { monitor } => IpcCommand::Resume { monitor },
+        Commands::Stats { monitor, .. } => IpcCommand::Stats { monitor },
+        Commands::Profile { name: None, none: false } => IpcCommand::ListProfiles,
+        Commands::Profile { name, .. } => IpcCommand::SetProfile { name },
+        Commands::Config {
+            command: ConfigCommands::Explain { monitor },
+        } => IpcCommand::ExplainConfig { monitor },
+        Commands::Config {
+            command: ConfigCommands::Check { path },
+        } => IpcCommand::ValidateConfig {
+            path: path.map(|p| p.canonicalize().unwrap_or(p)),
+        },
     };
 
     let response = send_command(command).await?;
@@ -220,8 +456,8 @@ async fn main() -> Result<()> {
             eprintln!("Error: {}", message);
             std::process::exit(1);
         }
-        IpcResponse::Status { monitors } => {
-            print_status(&monitors);
+        IpcResponse::Status { monitors, gpu_memory } => {
+            print_status(&monitors, gpu_memory);
         }
         IpcResponse::Wallpaper { path } => {
             if let Some(p) = path {
@@ -230,6 +466,30 @@ async fn main() -> Result<()> {
                 println!("No wallpaper set");
             }
         }
+        IpcResponse::Stats { monitors } => {
+            if json {
+                println!("{}", serde_json::to_string_pretty(&monitors)?);
+            } else {
+                print_stats(&monitors);
+            }
+        }
+        IpcResponse::ConfigExplain { monitor, values } => {
+            print_config_explain(&monitor, &values);
+        }
+        IpcResponse::ConfigCheck { path, diagnostics } => {
+            if print_config_check(&path, &diagnostics) {
+                std::process::exit(1);
+            }
+        }
+        IpcResponse::Profiles { active, profiles } => {
+            if profiles.is_empty() {
+                println!("No profiles configured");
+            }
+            for profile in profiles {
+                let marker = if active.as_ref() == Some(&profile) { "*" } else { " " };
+                println!("{} {}", marker, profile);
+            }
+        }
     }
 
     Ok(())
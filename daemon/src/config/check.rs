@@ -0,0 +1,545 @@
+use super::{
+    expand_path, Config, ConfigError, DaylightConfig, DefaultConfig, MonitorConfig, MotionConfig,
+    ParallaxConfig, TransitionOptions,
+};
+use crate::image::gnome::{self, Timeline};
+use crate::image::{kde, ImagePicker};
+use crate::output::OutputIdentity;
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use toml_edit::{ImDocument, Item, TableLike};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// One problem found in a config file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// 1-based line and column, when the problem has a place in the file
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, "{}:{}: ", line, column)?;
+        }
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+/// Collects diagnostics, turning byte spans into lines and columns
+struct Checker<'a> {
+    content: &'a str,
+    /// Directory relative wallpaper paths start from
+    base: &'a Path,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn report(&mut self, severity: Severity, span: Option<Range<usize>>, message: String) {
+        let (line, column) = match span {
+            Some(span) => {
+                let (line, column) = line_column(self.content, span.start);
+                (Some(line), Some(column))
+            }
+            None => (None, None),
+        };
+        self.diagnostics.push(Diagnostic {
+            severity,
+            line,
+            column,
+            message,
+        });
+    }
+
+    /// Diagnostics in file order
+    fn finish(mut self) -> Vec<Diagnostic> {
+        self.diagnostics.sort_by_key(|d| (d.line, d.column));
+        self.diagnostics
+    }
+
+    /// Report keys of `table` missing from `known`, descending into the
+    /// nested tables that have a struct of their own
+    fn unknown_keys(&mut self, table: &dyn TableLike, known: &[&str], section: &str) {
+        for (key, item) in table.iter() {
+            let span = table.get_key_value(key).and_then(|(key, _)| key.span());
+            if !known.contains(&key) {
+                self.report(
+                    Severity::Error,
+                    span,
+                    format!("Unknown key `{}` in [{}]", key, section),
+                );
+                continue;
+            }
+            if let (Some(fields), Some(nested)) = (nested_fields(key), item.as_table_like()) {
+                self.unknown_keys(nested, fields, &format!("{}.{}", section, key));
+            }
+        }
+    }
+
+    /// Report unknown keys in the sections of the root table or a profile,
+    /// `prefix` naming the latter
+    fn sections(&mut self, root: &dyn TableLike, prefix: &str) {
+        if let Some(default) = root.get("default").and_then(Item::as_table_like) {
+            self.unknown_keys(
+                default,
+                struct_fields::<DefaultConfig>(),
+                &format!("{}default", prefix),
+            );
+        }
+        for (section, member_fields) in [("monitors", &[][..]), ("groups", &["monitors"][..])] {
+            let Some(sections) = root.get(section).and_then(Item::as_table_like) else {
+                continue;
+            };
+            for (name, item) in sections.iter() {
+                if let Some(table) = item.as_table_like() {
+                    let known: Vec<&str> = struct_fields::<MonitorConfig>()
+                        .iter()
+                        .chain(member_fields)
+                        .copied()
+                        .collect();
+                    self.unknown_keys(table, &known, &format!("{}{}.{}", prefix, section, name));
+                }
+            }
+        }
+        if let Some(workspaces) = root.get("workspaces").and_then(Item::as_table_like) {
+            for (key, _) in workspaces.iter() {
+                if key != "enabled" && key.parse::<i32>().is_err() {
+                    let span = workspaces
+                        .get_key_value(key)
+                        .and_then(|(key, _)| key.span());
+                    self.report(
+                        Severity::Error,
+                        span,
+                        format!(
+                            "Unknown key `{}` in [{}workspaces], expected a workspace number",
+                            key, prefix
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Check the wallpaper paths in the sections of the root table or a
+    /// profile
+    fn wallpaper_paths(&mut self, root: &dyn TableLike) {
+        self.wallpaper_path(root.get("default").and_then(|d| d.get("path")), true);
+        for section in ["monitors", "groups"] {
+            let Some(sections) = root.get(section).and_then(Item::as_table_like) else {
+                continue;
+            };
+            for (_, item) in sections.iter() {
+                let recursive = item
+                    .get("recursive")
+                    .and_then(Item::as_bool)
+                    .unwrap_or(true);
+                self.wallpaper_path(item.get("path"), recursive);
+                if let Some(schedule) = item.get("schedule").and_then(Item::as_table_like) {
+                    for (_, path) in schedule.iter() {
+                        self.wallpaper_path(Some(path), recursive);
+                    }
+                }
+                if let Some(daylight) = item.get("daylight").and_then(Item::as_table_like) {
+                    self.daylight_path(daylight.get("day"));
+                    self.daylight_path(daylight.get("night"));
+                }
+            }
+        }
+        if let Some(workspaces) = root.get("workspaces").and_then(Item::as_table_like) {
+            for (key, item) in workspaces.iter() {
+                if key != "enabled" {
+                    self.wallpaper_path(Some(item), true);
+                    self.workspace_slideshow(item);
+                }
+            }
+        }
+    }
+
+    /// GNOME slideshows play as a monitor's `path`, not a workspace's
+    fn workspace_slideshow(&mut self, item: &Item) {
+        let Some(path) = item.as_str() else {
+            return;
+        };
+        let expanded = expand_path(Path::new(path), self.base);
+        if expanded.is_ok_and(|expanded| gnome::is_timeline(&expanded)) {
+            let message = format!(
+                "{} is a GNOME slideshow, which only plays as a monitor's `path`",
+                path
+            );
+            self.report(Severity::Error, item.span(), message);
+        }
+    }
+
+    /// Check that a daylight image is a file, a directory would never blend
+    fn daylight_path(&mut self, item: Option<&Item>) {
+        let Some(item) = item else {
+            return;
+        };
+        let expanded = item
+            .as_str()
+            .and_then(|path| expand_path(Path::new(path), self.base).ok());
+        match expanded.filter(|expanded| expanded.is_dir()) {
+            Some(dir) => {
+                let error = ConfigError::DaylightPath(dir);
+                self.report(Severity::Error, item.span(), error.to_string());
+            }
+            None => self.wallpaper_path(Some(item), false),
+        }
+    }
+
+    /// Check that a wallpaper path exists, and that a directory has images
+    fn wallpaper_path(&mut self, item: Option<&Item>, recursive: bool) {
+        let Some(item) = item else {
+            return;
+        };
+        let Some(path) = item.as_str() else {
+            return;
+        };
+
+        let expanded = match expand_path(Path::new(path), self.base) {
+            Ok(expanded) => expanded,
+            Err(e) => {
+                self.report(Severity::Error, item.span(), e.to_string());
+                return;
+            }
+        };
+        if !expanded.exists() {
+            let error = ConfigError::InvalidPath(format!("{} does not exist", path));
+            self.report(Severity::Error, item.span(), error.to_string());
+        } else if gnome::is_timeline(&expanded) {
+            if let Err(e) = Timeline::load(&expanded) {
+                self.report(Severity::Error, item.span(), format!("{:#}", e));
+            }
+        } else if kde::is_package(&expanded) {
+            if kde::variants(&expanded).is_empty() {
+                let error = ConfigError::InvalidPath(format!("{} contains no images", path));
+                self.report(Severity::Error, item.span(), error.to_string());
+            }
+        } else if expanded.is_dir() {
+            let mut picker = ImagePicker::new();
+            let empty = picker.scan_directory(&expanded, recursive).is_err() || picker.count() == 0;
+            if empty {
+                let error = ConfigError::InvalidPath(format!("{} contains no images", path));
+                self.report(Severity::Error, item.span(), error.to_string());
+            }
+        }
+    }
+}
+
+/// Validate a config file: TOML syntax and types, unknown keys, the files
+/// it includes, wallpaper paths and, given the connected `outputs`, monitor
+/// sections that match none of them
+pub fn check_config(path: &Path, outputs: Option<&[OutputIdentity]>) -> Vec<Diagnostic> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => {
+            let error = ConfigError::from(e);
+            return vec![Diagnostic {
+                severity: Severity::Error,
+                line: None,
+                column: None,
+                message: error.to_string(),
+            }];
+        }
+    };
+    let mut checker = Checker {
+        content: &content,
+        base: path.parent().unwrap_or(Path::new("")),
+        diagnostics: Vec::new(),
+    };
+
+    let document = match ImDocument::parse(content.as_str()) {
+        Ok(document) => document,
+        Err(e) => {
+            checker.report(Severity::Error, e.span(), e.message().trim().to_string());
+            return checker.finish();
+        }
+    };
+    let root = document.as_table();
+    checker.unknown_keys(root, struct_fields::<Config>(), "root");
+    checker.sections(root, "");
+    let profiles: Vec<(&str, &dyn TableLike)> = root
+        .get("profiles")
+        .and_then(Item::as_table_like)
+        .map(|profiles| {
+            profiles
+                .iter()
+                .filter_map(|(name, item)| Some((name, item.as_table_like()?)))
+                .collect()
+        })
+        .unwrap_or_default();
+    for &(name, profile) in &profiles {
+        let section = format!("profiles.{}", name);
+        checker.unknown_keys(profile, PROFILE_SECTIONS, &section);
+        checker.sections(profile, &format!("{}.", section));
+    }
+
+    checker.wallpaper_paths(root);
+    for &(_, profile) in &profiles {
+        checker.wallpaper_paths(profile);
+    }
+
+    // Types and values, toml locates those itself
+    let config = match toml::from_str::<Config>(&content) {
+        Ok(config) => config,
+        Err(e) => {
+            checker.report(Severity::Error, e.span(), e.message().trim().to_string());
+            return checker.finish();
+        }
+    };
+    // Included files, drop-ins and profiles, whose errors name where they
+    // are. Those of this file's own paths are already reported in place.
+    let config = match Config::load(path) {
+        Ok(merged) => merged,
+        Err(e) => {
+            let message = e.to_string();
+            if !checker
+                .diagnostics
+                .iter()
+                .any(|d| message.ends_with(&d.message))
+            {
+                checker.report(Severity::Error, None, message);
+            }
+            config
+        }
+    };
+
+    if let Some(outputs) = outputs {
+        let connected: Vec<String> = outputs.iter().map(OutputIdentity::display_name).collect();
+        let mut names: Vec<_> = config.monitors.iter().collect();
+        names.sort_by_key(|(name, _)| name.as_str());
+        for (name, monitor) in names {
+            if outputs.iter().any(|output| monitor.matches(name, output)) {
+                continue;
+            }
+            let span = root
+                .get("monitors")
+                .and_then(Item::as_table_like)
+                .and_then(|monitors| monitors.get_key_value(name))
+                .and_then(|(key, _)| key.span());
+            checker.report(
+                Severity::Warning,
+                span,
+                format!(
+                    "[monitors.{}] matches no connected monitor (connected: {})",
+                    name,
+                    connected.join(", ")
+                ),
+            );
+        }
+    }
+
+    checker.finish()
+}
+
+/// Sections a profile may lay over the rest of the config
+const PROFILE_SECTIONS: &[&str] = &["default", "monitors", "groups", "workspaces"];
+
+/// Nested tables of a monitor or default section with their own fields
+fn nested_fields(key: &str) -> Option<&'static [&'static str]> {
+    match key {
+        "transition_options" => Some(struct_fields::<TransitionOptions>()),
+        "motion" => Some(struct_fields::<MotionConfig>()),
+        "parallax" => Some(struct_fields::<ParallaxConfig>()),
+        "daylight" => Some(struct_fields::<DaylightConfig>()),
+        _ => None,
+    }
+}
+
+/// 1-based line and column (in characters) of a byte offset
+fn line_column(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// Field names of a struct, as its derived `Deserialize` sees them
+fn struct_fields<'de, T: de::Deserialize<'de>>() -> &'static [&'static str] {
+    let mut capture = FieldCapture(&[]);
+    let _ = T::deserialize(&mut capture);
+    capture.0
+}
+
+/// Deserializer that only records the fields a struct asks for
+struct FieldCapture(&'static [&'static str]);
+
+impl<'de> Deserializer<'de> for &mut FieldCapture {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0 = fields;
+        Err(de::Error::custom("fields captured"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+        ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check `content` written to a file named after the test
+    fn check(name: &str, content: &str, outputs: Option<&[OutputIdentity]>) -> Vec<Diagnostic> {
+        let path =
+            std::env::temp_dir().join(format!("canviz-{}-{}.toml", name, std::process::id()));
+        fs::write(&path, content).unwrap();
+        let diagnostics = check_config(&path, outputs);
+        fs::remove_file(path).unwrap();
+        diagnostics
+    }
+
+    #[test]
+    fn test_struct_fields() {
+        let fields = struct_fields::<MonitorConfig>();
+        assert!(fields.contains(&"path") && fields.contains(&"parallax"));
+        assert!(struct_fields::<Config>().contains(&"groups"));
+    }
+
+    #[test]
+    fn test_syntax_error_location() {
+        let diagnostics = check(
+            "syntax",
+            "[default]\ntransition = \"fade\"\nmode = \n",
+            None,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            (diagnostics[0].line, diagnostics[0].column),
+            (Some(3), Some(8))
+        );
+    }
+
+    #[test]
+    fn test_unknown_keys_and_paths() {
+        let content = "\
+[default]
+transiton = \"fade\"
+
+[monitors.DP-1]
+path = \"/nonexistent/canviz.png\"
+motion = { zom = [1.0, 1.2] }
+";
+        let diagnostics = check("keys", content, None);
+        let lines: Vec<_> = diagnostics.iter().map(|d| d.line.unwrap()).collect();
+        assert_eq!(lines, [2, 5, 6]);
+        assert!(diagnostics[0].message.contains("`transiton`"));
+        assert!(diagnostics[1].message.starts_with("Invalid path"));
+        assert!(diagnostics[2]
+            .message
+            .contains("`zom` in [monitors.DP-1.motion]"));
+    }
+
+    #[test]
+    fn test_invalid_value_location() {
+        let diagnostics = check("value", "[default]\nmode = \"stretch\"\n", None);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, Some(2));
+    }
+
+    #[test]
+    fn test_unknown_monitor() {
+        let output = OutputIdentity {
+            id: 1,
+            name: Some("DP-2".to_string()),
+            make: String::new(),
+            model: String::new(),
+            description: None,
+            serial: None,
+        };
+        let content = "[monitors.\"DP-*\"]\n\n[monitors.HDMI-A-1]\n";
+        let diagnostics = check("monitors", content, Some(&[output]));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].line, Some(3));
+    }
+
+    #[test]
+    fn test_daylight_directory() {
+        let dir = std::env::temp_dir();
+        let content = format!(
+            "[monitors.DP-1.daylight]\nday = {:?}\nnight = {:?}\nlatitude = 0.0\nlongitude = 0.0\n",
+            dir, dir
+        );
+        let diagnostics = check("daylight", &content, None);
+        let lines: Vec<_> = diagnostics.iter().map(|d| d.line).collect();
+        // Reported where it is, not again for the failed load
+        assert_eq!(lines, [Some(2), Some(3)]);
+        assert!(diagnostics[0].message.contains("have to be image files"));
+    }
+
+    #[test]
+    fn test_workspace_slideshow() {
+        let xml = std::env::temp_dir().join(format!("canviz-slideshow-{}.xml", std::process::id()));
+        let slideshow = "<background>\
+            <static><duration>60</duration><file>a.jpg</file></static>\
+        </background>";
+        fs::write(&xml, slideshow).unwrap();
+        let content = format!(
+            "[monitors.DP-1]\npath = {:?}\n\n[workspaces]\n1 = {:?}\n",
+            xml, xml
+        );
+        let diagnostics = check("slideshow", &content, None);
+        fs::remove_file(&xml).unwrap();
+
+        // Only the workspace can't play it
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, Some(5));
+        assert!(diagnostics[0].message.contains("GNOME slideshow"));
+    }
+
+    #[test]
+    fn test_profile_sections() {
+        let content = "\
+[profiles.work]
+monitor = {}
+
+[profiles.work.monitors.DP-1]
+pth = \"/tmp\"
+";
+        let diagnostics = check("profiles", content, None);
+        let lines: Vec<_> = diagnostics.iter().map(|d| d.line.unwrap()).collect();
+        assert_eq!(lines, [2, 5]);
+        assert!(diagnostics[0]
+            .message
+            .contains("`monitor` in [profiles.work]"));
+        assert!(diagnostics[1]
+            .message
+            .contains("`pth` in [profiles.work.monitors.DP-1]"));
+    }
+}
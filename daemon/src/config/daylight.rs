@@ -0,0 +1,130 @@
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Julian date of the Unix epoch
+const UNIX_EPOCH_JD: f64 = 2_440_587.5;
+/// Julian date of J2000.0, 2000-01-01 12:00 UTC
+const J2000_JD: f64 = 2_451_545.0;
+
+/// Blend between a day and a night image by the height of the sun at a
+/// location, `[monitors.<name>.daylight]`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct DaylightConfig {
+    /// Image shown while the sun is up
+    pub day: PathBuf,
+    /// Image shown while it is dark
+    pub night: PathBuf,
+    /// Degrees north, negative for south
+    pub latitude: f64,
+    /// Degrees east, negative for west
+    pub longitude: f64,
+    /// Solar elevation in degrees from which only the day image shows
+    #[serde(default = "default_day_elevation")]
+    pub day_elevation: f64,
+    /// Solar elevation in degrees below which only the night image shows,
+    /// civil dusk by default
+    #[serde(default = "default_night_elevation")]
+    pub night_elevation: f64,
+}
+
+fn default_day_elevation() -> f64 {
+    6.0
+}
+
+fn default_night_elevation() -> f64 {
+    -6.0
+}
+
+impl DaylightConfig {
+    /// Weight of the day image at `time`, from 0.0 (night) to 1.0 (day)
+    pub fn blend(&self, time: SystemTime) -> f32 {
+        let elevation = solar_elevation(time, self.latitude, self.longitude);
+        let range = (self.day_elevation - self.night_elevation).max(f64::EPSILON);
+        let t = ((elevation - self.night_elevation) / range).clamp(0.0, 1.0);
+        // Smoothstep, so the blend eases in and out of the plain images
+        (t * t * (3.0 - 2.0 * t)) as f32
+    }
+}
+
+/// Elevation of the sun's center above the horizon in degrees, without
+/// refraction. This is the low precision formula of the Astronomical
+/// Almanac, good to about 0.01° for years around 2000.
+fn solar_elevation(time: SystemTime, latitude: f64, longitude: f64) -> f64 {
+    let unix = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs_f64(),
+        Err(e) => -e.duration().as_secs_f64(),
+    };
+    // Days since J2000.0
+    let n = unix / 86_400.0 + UNIX_EPOCH_JD - J2000_JD;
+
+    let mean_longitude = (280.460 + 0.985_647_4 * n).rem_euclid(360.0);
+    let mean_anomaly = (357.528 + 0.985_600_3 * n).rem_euclid(360.0).to_radians();
+    let ecliptic_longitude =
+        (mean_longitude + 1.915 * mean_anomaly.sin() + 0.020 * (2.0 * mean_anomaly).sin())
+            .to_radians();
+    let obliquity = (23.439 - 0.000_000_4 * n).to_radians();
+
+    let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+    let right_ascension =
+        (obliquity.cos() * ecliptic_longitude.sin()).atan2(ecliptic_longitude.cos());
+
+    // Greenwich mean sidereal time, in degrees
+    let sidereal = (280.460_618_37 + 360.985_647_366_29 * n).rem_euclid(360.0);
+    let hour_angle = (sidereal + longitude).to_radians() - right_ascension;
+
+    let latitude = latitude.to_radians();
+    let sin_elevation =
+        latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+    sin_elevation.clamp(-1.0, 1.0).asin().to_degrees()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(unix: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(unix)
+    }
+
+    #[test]
+    fn test_solar_elevation() {
+        // 2024-03-20 12:00 UTC, a few hours after the equinox: nearly
+        // overhead at the equator on the prime meridian
+        let equinox = 1_710_936_000;
+        assert!(solar_elevation(at(equinox), 0.0, 0.0) > 87.0);
+        assert!(solar_elevation(at(equinox), 0.0, 180.0) < -87.0);
+
+        // Berlin around solar noon of the June solstice peaks near 61°
+        let elevation = solar_elevation(at(1_718_967_600), 52.52, 13.405);
+        assert!((60.0..61.5).contains(&elevation), "{}", elevation);
+
+        // The December solstice at noon stays below 15°
+        let elevation = solar_elevation(at(1_734_782_400), 52.52, 13.405);
+        assert!((10.0..15.0).contains(&elevation), "{}", elevation);
+    }
+
+    #[test]
+    fn test_blend() {
+        let daylight = DaylightConfig {
+            day: PathBuf::from("day.png"),
+            night: PathBuf::from("night.png"),
+            latitude: 0.0,
+            longitude: 0.0,
+            day_elevation: 6.0,
+            night_elevation: -6.0,
+        };
+        let noon = 1_710_936_000;
+        assert_eq!(daylight.blend(at(noon)), 1.0);
+        assert_eq!(daylight.blend(at(noon + 12 * 3600)), 0.0);
+
+        // The sun sets around 18:07 and takes about 48 minutes from 6°
+        // above the horizon to 6° below
+        let dusk = (0..12 * 60)
+            .map(|minute| daylight.blend(at(noon + minute * 60)))
+            .filter(|&blend| blend > 0.0 && blend < 1.0)
+            .count();
+        assert!((40..56).contains(&dusk), "{}", dusk);
+    }
+}
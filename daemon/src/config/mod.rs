@@ -1,10 +1,22 @@
+use crate::output::OutputIdentity;
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 use thiserror::Error;
 
+mod check;
+mod daylight;
+mod schedule;
+mod watch;
+
+pub use check::{check_config, Diagnostic, Severity};
+pub use daylight::DaylightConfig;
+pub use schedule::{LocalTime, Schedule};
+pub use watch::ConfigWatcher;
+
 #[derive(Error, Debug)]
 pub enum ConfigError {
     #[error("Failed to read config file: {0}")]
@@ -13,28 +25,74 @@ pub enum ConfigError {
     ParseError(#[from] toml::de::Error),
     #[error("Invalid path: {0}")]
     InvalidPath(String),
+    #[error("{}: {source}", path.display())]
+    File {
+        path: PathBuf,
+        source: Box<ConfigError>,
+    },
+    #[error("Include cycle: {0}")]
+    IncludeCycle(String),
+    #[error("Variable ${0} is not set, use ${{{0}:-default}} for a fallback")]
+    UndefinedVariable(String),
+    #[error("Unknown profile: {0}")]
+    UnknownProfile(String),
+    #[error("Daylight `day` and `night` have to be image files, {} is not", .0.display())]
+    DaylightPath(PathBuf),
+    #[error("[profiles.{name}]: {source}")]
+    Profile {
+        name: String,
+        source: Box<ConfigError>,
+    },
 }
 
 /// Main configuration structure
 #[derive(Debug, Clone, Deserialize)]
 #[serde(default)]
 pub struct Config {
+    /// Other config files merged beneath this one, see `expand_path`
+    pub include: Vec<String>,
     /// Default settings applied to all monitors/workspaces unless overridden
     pub default: DefaultConfig,
-    /// Per-monitor wallpaper configuration
+    /// Per-monitor wallpaper configuration, see `find_monitor` for which
+    /// section applies to an output
     #[serde(default)]
     pub monitors: HashMap<String, MonitorConfig>,
+    /// Settings shared by several monitors, between `[default]` and the
+    /// monitor's own section
+    #[serde(default)]
+    pub groups: HashMap<String, GroupConfig>,
     /// Per-workspace wallpaper configuration (primary feature)
     #[serde(default)]
     pub workspaces: WorkspaceConfig,
+    /// Profile applied when the config is loaded
+    pub profile: Option<String>,
+    /// Named sets of `default`, `monitors`, `groups` and `workspaces`
+    /// settings laid over the rest of the config, see `with_profile`
+    pub profiles: HashMap<String, toml::Table>,
+    /// Profile the other fields have been resolved with
+    #[serde(skip)]
+    pub active_profile: Option<String>,
+    /// Every file the config was read from, watched for hot reloading
+    #[serde(skip)]
+    pub sources: Vec<PathBuf>,
+    /// The merged files before any profile, to switch profiles from
+    #[serde(skip)]
+    base: toml::Table,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            include: Vec::new(),
             default: DefaultConfig::default(),
             monitors: HashMap::new(),
+            groups: HashMap::new(),
             workspaces: WorkspaceConfig::default(),
+            profile: None,
+            profiles: HashMap::new(),
+            active_profile: None,
+            sources: Vec::new(),
+            base: toml::Table::new(),
         }
     }
 }
@@ -46,35 +104,148 @@ impl Config {
             return Ok(Self::default());
         }
 
-        let content = fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&content)?;
+        if !path.is_file() {
+            return Err(ConfigError::InvalidPath(format!("{} is not a file", path.display())));
+        }
+
+        let mut loader = Loader::default();
+        let mut table = loader.load_file(path)?;
+
+        // Drop-ins override the main file, later names over earlier ones
+        let drop_ins = conf_d(path);
+        for file in &drop_ins {
+            let drop_in = loader.load_file(file)?;
+            merge_tables(&mut table, drop_in);
+        }
+
+        let mut config = Config::deserialize(table.clone())?;
+        config.sources = loader.sources;
+        config.base = table;
+
+        // Catch mistakes in every profile now, not when switching to it
+        let mut names: Vec<_> = config.profiles.keys().collect();
+        names.sort();
+        for name in names {
+            config.with_profile(Some(name)).map_err(|e| ConfigError::Profile {
+                name: name.clone(),
+                source: Box::new(e),
+            })?;
+        }
+
+        let selected = config.profile.clone();
+        config.with_profile(selected.as_deref())
+    }
+
+    /// This config with a profile's sections merged over the files' own,
+    /// or with none for `None`
+    pub fn with_profile(&self, name: Option<&str>) -> Result<Self, ConfigError> {
+        let mut table = self.base.clone();
+        if let Some(name) = name {
+            let profile = self
+                .profiles
+                .get(name)
+                .ok_or_else(|| ConfigError::UnknownProfile(name.to_string()))?;
+            merge_tables(&mut table, profile.clone());
+        }
+
+        let mut config = Config::deserialize(table)?;
+        config.check_daylight()?;
+        config.active_profile = name.map(str::to_string);
+        config.sources = self.sources.clone();
+        config.base = self.base.clone();
         Ok(config)
     }
 
-    /// Get wallpaper path for a specific workspace on a monitor
-    pub fn get_wallpaper_for_workspace(&self, monitor: &str, workspace: i32) -> Option<PathBuf> {
-        // First check workspace-specific config
-        if self.workspaces.enabled {
-            if let Some(path) = self.workspaces.wallpapers.get(&workspace) {
-                return Some(expand_path(path));
+    /// Blending needs one image each for day and night, not a directory
+    fn check_daylight(&self) -> Result<(), ConfigError> {
+        let groups = self.groups.values().map(|group| &group.settings);
+        let settings = self.monitors.values().chain(groups);
+        for daylight in settings.filter_map(|settings| settings.daylight.as_ref()) {
+            for path in [&daylight.day, &daylight.night] {
+                if path.is_dir() {
+                    return Err(ConfigError::DaylightPath(path.clone()));
+                }
             }
         }
+        Ok(())
+    }
+
+    /// Names of the configured profiles, sorted
+    pub fn profile_names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.profiles.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Find the `[monitors]` section for an output: of the sections matching
+    /// it, the one with the highest `priority`, then an exact connector name,
+    /// then hardware matchers over name patterns, then the first by name
+    pub fn find_monitor(&self, output: &OutputIdentity) -> Option<(&str, &MonitorConfig)> {
+        let name = output.display_name();
+        // How specifically a section names the output
+        let rank = |key: &str, config: &MonitorConfig| match (config.has_matchers(), key == name) {
+            (false, true) => 2,
+            (true, _) => 1,
+            (false, false) => 0,
+        };
+        self.monitors
+            .iter()
+            .filter(|(key, config)| config.matches(key, output))
+            .max_by(|(a_key, a), (b_key, b)| {
+                (a.priority, rank(a_key, a))
+                    .cmp(&(b.priority, rank(b_key, b)))
+                    .then_with(|| b_key.cmp(a_key))
+            })
+            .map(|(key, config)| (key.as_str(), config))
+    }
+
+    /// Stack the layers that apply to an output: `[default]`, the groups it
+    /// belongs to (lowest priority first), its monitor section and, with
+    /// `workspace`, that workspace's wallpaper
+    pub fn resolve(&self, output: &OutputIdentity, workspace: Option<i32>) -> ResolvedConfig {
+        let mut resolved = ResolvedConfig::new(MonitorSettings::from_default(&self.default));
+
+        let mut groups: Vec<_> = self
+            .groups
+            .iter()
+            .filter(|(_, group)| group.matches(output))
+            .collect();
+        groups.sort_by(|(a_key, a), (b_key, b)| {
+            a.settings.priority.cmp(&b.settings.priority).then_with(|| a_key.cmp(b_key))
+        });
+        for (key, group) in groups {
+            resolved.apply(&group.settings, ConfigLayer::Group(key.clone()));
+        }
 
-        // Fall back to monitor-specific config
-        if let Some(mon_config) = self.monitors.get(monitor) {
-            return Some(expand_path(&mon_config.path));
+        if let Some((key, monitor)) = self.find_monitor(output) {
+            resolved.apply(monitor, ConfigLayer::Monitor(key.to_string()));
         }
 
-        // Fall back to default
-        self.default.path.as_ref().map(|p| expand_path(p))
+        if let Some(workspace) = workspace.filter(|_| self.workspaces.enabled) {
+            if let Some(path) = self.workspaces.wallpapers.get(&workspace) {
+                resolved.set_path(path.clone(), ConfigLayer::Workspace(workspace));
+            }
+        }
+
+        resolved
     }
 
-    /// Get the monitor config, falling back to defaults
-    pub fn get_monitor_config(&self, monitor: &str) -> MonitorConfig {
-        self.monitors
-            .get(monitor)
-            .cloned()
-            .unwrap_or_else(|| MonitorConfig::from_default(&self.default))
+    /// Get the wallpaper path a workspace sets for itself on a monitor
+    pub fn get_wallpaper_for_workspace(
+        &self,
+        output: &OutputIdentity,
+        workspace: i32,
+    ) -> Option<PathBuf> {
+        let resolved = self.resolve(output, Some(workspace));
+        match resolved.sources.get("path") {
+            Some(ConfigLayer::Workspace(_)) => Some(resolved.config.path),
+            _ => None,
+        }
+    }
+
+    /// Get the monitor config, with every value it doesn't set inherited
+    pub fn get_monitor_config(&self, output: &OutputIdentity) -> MonitorSettings {
+        self.resolve(output, None).config
     }
 }
 
@@ -89,6 +260,26 @@ pub struct DefaultConfig {
     pub transition_time: u32,
     /// Background mode
     pub mode: BackgroundMode,
+    /// Origin for positional transitions (grow, shrink, zoom, wipe)
+    pub transition_pos: TransitionPosition,
+    /// Pool of transitions picked from when `transition = "random"`
+    pub transition_random: Vec<TransitionType>,
+    /// Tuning parameters for the individual transition effects
+    pub transition_options: TransitionOptions,
+    /// What to do with a change that arrives while a transition is running
+    pub transition_queue: QueuePolicy,
+    /// Blend transitions in linear light, avoids dark dips when fading
+    pub linear_blending: bool,
+    /// Dither the output to hide banding in gradients
+    pub dither: bool,
+    /// How wallpapers are sampled when scaled
+    pub filter: TextureFilter,
+    /// Pause animations once the user has been idle this long
+    #[serde(with = "humantime_serde")]
+    pub idle_timeout: Duration,
+    /// Hand static wallpapers over to a wl_shm buffer and free the GPU
+    /// resources of the output until the next change
+    pub release_gpu: bool,
 }
 
 impl Default for DefaultConfig {
@@ -98,6 +289,15 @@ impl Default for DefaultConfig {
             transition: TransitionType::Fade,
             transition_time: 300,
             mode: BackgroundMode::Cover,
+            transition_pos: TransitionPosition::default(),
+            transition_random: Vec::new(),
+            transition_options: TransitionOptions::default(),
+            transition_queue: QueuePolicy::default(),
+            linear_blending: true,
+            dither: true,
+            filter: TextureFilter::default(),
+            idle_timeout: Duration::from_secs(300),
+            release_gpu: false,
         }
     }
 }
@@ -105,48 +305,405 @@ impl Default for DefaultConfig {
 #[derive(Debug, Clone, Deserialize)]
 #[serde(default)]
 pub struct MonitorConfig {
+    /// Output description to match instead of the section name (glob)
+    pub description: Option<String>,
+    /// Output manufacturer to match instead of the section name (glob)
+    pub make: Option<String>,
+    /// Output model to match instead of the section name (glob)
+    pub model: Option<String>,
+    /// Output serial number to match instead of the section name (glob)
+    pub serial: Option<String>,
+    /// Which section wins when several match an output, highest first
+    pub priority: i32,
     /// Wallpaper path (file or directory)
     pub path: PathBuf,
     /// Slideshow duration (if path is a directory)
     #[serde(default, with = "humantime_serde")]
     pub duration: Option<Duration>,
     /// Sorting method for slideshow
-    pub sorting: SortingMethod,
+    pub sorting: Option<SortingMethod>,
     /// Search subdirectories
-    pub recursive: bool,
+    pub recursive: Option<bool>,
     /// Background mode override
     pub mode: Option<BackgroundMode>,
     /// Transition type override
     pub transition: Option<TransitionType>,
     /// Transition time override
     pub transition_time: Option<u32>,
+    /// Transition origin override
+    pub transition_pos: Option<TransitionPosition>,
+    /// Random transition pool override
+    pub transition_random: Option<Vec<TransitionType>>,
+    /// Transition parameters override, merged with the lower layers' by key
+    pub transition_options: Option<TransitionOptionsLayer>,
+    /// Transition queueing policy override
+    pub transition_queue: Option<QueuePolicy>,
+    /// Linear light blending override
+    pub linear_blending: Option<bool>,
+    /// Dithering override
+    pub dither: Option<bool>,
+    /// Texture filter override
+    pub filter: Option<TextureFilter>,
+    /// Slow pan and zoom over each image (Ken Burns effect)
+    pub motion: Option<MotionLayer>,
+    /// Shift an oversized wallpaper with the active workspace
+    pub parallax: Option<ParallaxLayer>,
+    /// Wallpapers by time of day, replacing `path` while one applies
+    pub schedule: Option<Schedule>,
+    /// Day and night images blended by the height of the sun, replacing
+    /// `path` and `schedule`
+    pub daylight: Option<DaylightConfig>,
 }
 
 impl Default for MonitorConfig {
     fn default() -> Self {
         Self {
+            description: None,
+            make: None,
+            model: None,
+            serial: None,
+            priority: 0,
             path: PathBuf::new(),
             duration: None,
-            sorting: SortingMethod::Random,
-            recursive: true,
+            sorting: None,
+            recursive: None,
             mode: None,
             transition: None,
             transition_time: None,
+            transition_pos: None,
+            transition_random: None,
+            transition_options: None,
+            transition_queue: None,
+            linear_blending: None,
+            dither: None,
+            filter: None,
+            motion: None,
+            parallax: None,
+            schedule: None,
+            daylight: None,
         }
     }
 }
 
 impl MonitorConfig {
+    /// Whether the section sets any of `description`, `make`, `model` or
+    /// `serial`, its name is only a label then
+    fn has_matchers(&self) -> bool {
+        self.description.is_some() || self.make.is_some() || self.model.is_some() || self.serial.is_some()
+    }
+
+    /// Whether this section, named `key`, applies to `output`: every matcher
+    /// it sets has to match, without any the name is a connector pattern
+    fn matches(&self, key: &str, output: &OutputIdentity) -> bool {
+        if !self.has_matchers() {
+            return glob_match(key, &output.display_name());
+        }
+
+        let field = |pattern: &Option<String>, value: Option<&str>| {
+            pattern.as_deref().is_none_or(|pattern| value.is_some_and(|v| glob_match(pattern, v)))
+        };
+        // Outputs only report a serial through Hyprland, elsewhere it is
+        // usually a word of the description
+        let serial = self.serial.as_deref().is_none_or(|pattern| match &output.serial {
+            Some(serial) => glob_match(pattern, serial),
+            None => output
+                .description
+                .as_deref()
+                .is_some_and(|d| d.split_whitespace().any(|word| glob_match(pattern, word))),
+        });
+
+        field(&self.description, output.description.as_deref())
+            && field(&self.make, Some(&output.make))
+            && field(&self.model, Some(&output.model))
+            && serial
+    }
+}
+
+/// Effective settings of a monitor, its layers applied over `[default]`
+/// (see `Config::resolve`). Only the features a layer has to turn on are
+/// optional.
+#[derive(Debug, Clone)]
+pub struct MonitorSettings {
+    /// Wallpaper path (file or directory), empty if no layer sets one
+    pub path: PathBuf,
+    /// Slideshow duration, without one a directory shows a single image
+    pub duration: Option<Duration>,
+    pub sorting: SortingMethod,
+    pub recursive: bool,
+    pub mode: BackgroundMode,
+    pub transition: TransitionType,
+    pub transition_time: u32,
+    pub transition_pos: TransitionPosition,
+    pub transition_random: Vec<TransitionType>,
+    pub transition_options: TransitionOptions,
+    pub transition_queue: QueuePolicy,
+    pub linear_blending: bool,
+    pub dither: bool,
+    pub filter: TextureFilter,
+    pub motion: Option<MotionConfig>,
+    pub parallax: Option<ParallaxConfig>,
+    pub schedule: Option<Schedule>,
+    pub daylight: Option<DaylightConfig>,
+}
+
+impl MonitorSettings {
+    /// The settings `[default]` gives every monitor
     pub fn from_default(default: &DefaultConfig) -> Self {
         Self {
             path: default.path.clone().unwrap_or_default(),
             duration: None,
             sorting: SortingMethod::Random,
             recursive: true,
-            mode: Some(default.mode),
-            transition: Some(default.transition),
-            transition_time: Some(default.transition_time),
+            mode: default.mode,
+            transition: default.transition,
+            transition_time: default.transition_time,
+            transition_pos: default.transition_pos,
+            transition_random: default.transition_random.clone(),
+            transition_options: default.transition_options.clone(),
+            transition_queue: default.transition_queue,
+            linear_blending: default.linear_blending,
+            dither: default.dither,
+            filter: default.filter,
+            motion: None,
+            parallax: None,
+            schedule: None,
+            daylight: None,
+        }
+    }
+}
+
+/// Where a value of the effective monitor config comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigLayer {
+    Default,
+    Group(String),
+    Monitor(String),
+    Workspace(i32),
+    /// Set at runtime with `canvizctl set`
+    Ipc,
+}
+
+impl fmt::Display for ConfigLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigLayer::Default => write!(f, "[default]"),
+            ConfigLayer::Group(name) => write!(f, "[groups.{}]", name),
+            ConfigLayer::Monitor(name) => write!(f, "[monitors.{}]", name),
+            ConfigLayer::Workspace(id) => write!(f, "[workspaces] {}", id),
+            ConfigLayer::Ipc => write!(f, "canvizctl set"),
+        }
+    }
+}
+
+/// Effective monitor config and the layer each value came from
+#[derive(Debug, Clone)]
+pub struct ResolvedConfig {
+    pub config: MonitorSettings,
+    pub sources: BTreeMap<&'static str, ConfigLayer>,
+}
+
+impl ResolvedConfig {
+    /// Override the wallpaper path, for the workspace and runtime layers
+    pub fn set_path(&mut self, path: PathBuf, source: ConfigLayer) {
+        self.config.path = path;
+        self.sources.insert("path", source);
+    }
+
+    /// Every layered value with its source, unset ones have neither
+    pub fn explain(&self) -> Vec<(&'static str, Option<String>, Option<&ConfigLayer>)> {
+        self.config
+            .values()
+            .into_iter()
+            .map(|(key, value)| (key, value, self.sources.get(key)))
+            .collect()
+    }
+}
+
+/// Applies the values a layer sets on top of the ones below it, and lists
+/// the effective values for `canvizctl config explain`. `[default]` sets
+/// every `value`, `optional` ones stay unset unless a layer sets them.
+/// Nested tables (`table`, `optional_table`) merge key by key.
+macro_rules! layered_fields {
+    ($($field:ident: $kind:ident),* $(,)?) => {
+        impl ResolvedConfig {
+            /// Settings of `[default]`, with it as the source of what it sets
+            fn new(config: MonitorSettings) -> Self {
+                let mut sources = BTreeMap::new();
+                if !config.path.as_os_str().is_empty() {
+                    sources.insert("path", ConfigLayer::Default);
+                }
+                $(layered_field!($kind, default_source, sources, config.$field, $field);)*
+                Self { config, sources }
+            }
+
+            fn apply(&mut self, layer: &MonitorConfig, source: ConfigLayer) {
+                if !layer.path.as_os_str().is_empty() {
+                    self.set_path(layer.path.clone(), source.clone());
+                }
+                $(layered_field!($kind, apply, self, layer.$field, source, $field);)*
+            }
+        }
+
+        impl MonitorSettings {
+            fn values(&self) -> Vec<(&'static str, Option<String>)> {
+                let path = Some(self.path.display().to_string()).filter(|p| !p.is_empty());
+                let mut values = vec![("path", path)];
+                $(layered_field!($kind, describe, values, self.$field, $field);)*
+                values
+            }
+        }
+    };
+}
+
+/// The parts of `layered_fields!` that differ between kinds of settings
+macro_rules! layered_field {
+    (value, default_source, $sources:expr, $value:expr, $field:ident) => {
+        $sources.insert(stringify!($field), ConfigLayer::Default);
+    };
+    (table, default_source, $sources:expr, $value:expr, $field:ident) => {
+        for (key, _) in $value.explain() {
+            $sources.insert(key, ConfigLayer::Default);
+        }
+    };
+    ($kind:ident, default_source, $sources:expr, $value:expr, $field:ident) => {};
+
+    (value, apply, $resolved:expr, $layer:expr, $source:expr, $field:ident) => {
+        if let Some(value) = &$layer {
+            $resolved.config.$field = value.clone();
+            $resolved.sources.insert(stringify!($field), $source.clone());
+        }
+    };
+    (optional, apply, $resolved:expr, $layer:expr, $source:expr, $field:ident) => {
+        if $layer.is_some() {
+            $resolved.config.$field = $layer.clone();
+            $resolved.sources.insert(stringify!($field), $source.clone());
         }
+    };
+    (table, apply, $resolved:expr, $layer:expr, $source:expr, $field:ident) => {
+        if let Some(table) = &$layer {
+            table.apply(&mut $resolved.config.$field, &$source, &mut $resolved.sources);
+        }
+    };
+    (optional_table, apply, $resolved:expr, $layer:expr, $source:expr, $field:ident) => {
+        if let Some(table) = &$layer {
+            let enabled = $resolved.config.$field.is_some();
+            let target = $resolved.config.$field.get_or_insert_with(Default::default);
+            // Keys no layer sets come with the layer turning the table on
+            if !enabled {
+                for (key, _) in target.explain() {
+                    $resolved.sources.insert(key, $source.clone());
+                }
+            }
+            table.apply(target, &$source, &mut $resolved.sources);
+        }
+    };
+
+    (value, describe, $values:expr, $value:expr, $field:ident) => {
+        $values.push((stringify!($field), Some(format!("{:?}", $value))));
+    };
+    (optional, describe, $values:expr, $value:expr, $field:ident) => {
+        $values.push((stringify!($field), $value.as_ref().map(|v| format!("{:?}", v))));
+    };
+    (table, describe, $values:expr, $value:expr, $field:ident) => {
+        $values.extend($value.explain().into_iter().map(|(key, value)| (key, Some(value))));
+    };
+    (optional_table, describe, $values:expr, $value:expr, $field:ident) => {
+        match &$value {
+            Some(table) => {
+                $values.extend(table.explain().into_iter().map(|(key, value)| (key, Some(value))))
+            }
+            None => $values.push((stringify!($field), None)),
+        }
+    };
+}
+
+/// A nested settings table, plus the same table as a layer sets it: every
+/// key optional, the ones it leaves out coming from the layers below
+macro_rules! layered_table {
+    (
+        $(#[$meta:meta])*
+        pub struct $name:ident in $key:ident as $layer:ident {
+            $($(#[$field_meta:meta])* pub $field:ident: $ty:ty,)*
+        }
+    ) => {
+        $(#[$meta])*
+        pub struct $name {
+            $($(#[$field_meta])* pub $field: $ty,)*
+        }
+
+        #[derive(Debug, Clone, Default, Deserialize)]
+        #[serde(default)]
+        pub struct $layer {
+            $($(#[$field_meta])* pub $field: Option<$ty>,)*
+        }
+
+        impl $layer {
+            /// Set the keys this layer sets on `target`, from `source`
+            fn apply(
+                &self,
+                target: &mut $name,
+                source: &ConfigLayer,
+                sources: &mut BTreeMap<&'static str, ConfigLayer>,
+            ) {
+                $(
+                    if let Some(value) = &self.$field {
+                        target.$field = value.clone();
+                        sources.insert(concat!(stringify!($key), ".", stringify!($field)), source.clone());
+                    }
+                )*
+            }
+        }
+
+        impl $name {
+            /// Values by their `config explain` key, `table.key`
+            fn explain(&self) -> Vec<(&'static str, String)> {
+                vec![$((concat!(stringify!($key), ".", stringify!($field)), format!("{:?}", self.$field)),)*]
+            }
+        }
+    };
+}
+
+layered_fields!(
+    duration: optional,
+    sorting: value,
+    recursive: value,
+    mode: value,
+    transition: value,
+    transition_time: value,
+    transition_pos: value,
+    transition_random: value,
+    transition_options: table,
+    transition_queue: value,
+    linear_blending: value,
+    dither: value,
+    filter: value,
+    motion: optional_table,
+    parallax: optional_table,
+    schedule: optional,
+    daylight: optional,
+);
+
+/// Settings for a set of monitors
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct GroupConfig {
+    /// Connector names (globs) of the members, all monitors if empty and
+    /// no matcher is set
+    pub monitors: Vec<String>,
+    /// Matchers, priority and the settings themselves, as in `[monitors]`
+    #[serde(flatten)]
+    pub settings: MonitorConfig,
+}
+
+impl GroupConfig {
+    /// Whether `output` is a member: its name is listed and it satisfies
+    /// the matchers, whichever of the two the group sets
+    fn matches(&self, output: &OutputIdentity) -> bool {
+        let name = output.display_name();
+        let listed = self.monitors.is_empty()
+            || self.monitors.iter().any(|pattern| glob_match(pattern, &name));
+        let matched = !self.settings.has_matchers() || self.settings.matches("*", output);
+        listed && matched
     }
 }
 
@@ -156,7 +713,7 @@ pub struct WorkspaceConfig {
     /// Enable per-workspace wallpapers
     pub enabled: bool,
     /// Workspace number -> wallpaper path mapping
-    #[serde(flatten)]
+    #[serde(flatten, deserialize_with = "deserialize_workspace_map")]
     pub wallpapers: HashMap<i32, PathBuf>,
 }
 
@@ -169,24 +726,265 @@ impl Default for WorkspaceConfig {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
-#[serde(rename_all = "lowercase")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
 pub enum TransitionType {
     /// No transition, instant switch
     None,
     /// Simple fade/crossfade
     #[default]
     Fade,
-    /// Slide from a direction
-    Slide,
-    /// Wipe effect
-    Wipe,
     /// Crossfade with easing
     Crossfade,
+    /// Slide from a direction (alias for `slide_left`)
+    Slide,
+    /// New image slides in from the right
+    SlideLeft,
+    /// New image slides in from the left
+    SlideRight,
+    /// New image slides in from the bottom
+    SlideUp,
+    /// New image slides in from the top
+    SlideDown,
+    /// Wipe effect spreading from a line through the origin
+    Wipe,
+    /// Circle of the new image growing from the origin
+    Grow,
+    /// Circle of the old image shrinking into the origin
+    Shrink,
+    /// Old image zooms in towards the origin while fading out
+    ZoomIn,
+    /// New image zooms out from the origin while fading in
+    ZoomOut,
+    /// Pixelate the old image, then sharpen the new one
+    Pixelate,
+    /// Noise dissolve
+    Dissolve,
+    /// Venetian blinds
+    Blinds,
+    /// Wave distortion
+    Wave,
+    /// Pick one from `transition_random` on every change
+    Random,
+}
+
+impl TransitionType {
+    /// Every concrete effect, used as the pool for `random` when none is configured
+    pub const EFFECTS: &'static [TransitionType] = &[
+        TransitionType::Fade,
+        TransitionType::Crossfade,
+        TransitionType::SlideLeft,
+        TransitionType::SlideRight,
+        TransitionType::SlideUp,
+        TransitionType::SlideDown,
+        TransitionType::Wipe,
+        TransitionType::Grow,
+        TransitionType::Shrink,
+        TransitionType::ZoomIn,
+        TransitionType::ZoomOut,
+        TransitionType::Pixelate,
+        TransitionType::Dissolve,
+        TransitionType::Blinds,
+        TransitionType::Wave,
+    ];
+}
+
+/// Origin of positional transitions
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum TransitionPosition {
+    /// Fractions of the output size measured from the top-left corner
+    Fixed { x: f32, y: f32 },
+    /// Wherever the pointer is when the transition starts (Hyprland only)
+    Cursor,
+}
+
+impl Default for TransitionPosition {
+    fn default() -> Self {
+        Self::Fixed { x: 0.5, y: 0.5 }
+    }
+}
+
+impl TryFrom<String> for TransitionPosition {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl std::str::FromStr for TransitionPosition {
+    type Err = String;
+
+    /// Accepts named positions (`center`, `top-left`, `bottom`, ...),
+    /// `x,y` fractions such as `0.25,0.75`, or `cursor`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (x, y) = match s.trim() {
+            "cursor" => return Ok(Self::Cursor),
+            "center" => (0.5, 0.5),
+            "top" => (0.5, 0.0),
+            "bottom" => (0.5, 1.0),
+            "left" => (0.0, 0.5),
+            "right" => (1.0, 0.5),
+            "top-left" => (0.0, 0.0),
+            "top-right" => (1.0, 0.0),
+            "bottom-left" => (0.0, 1.0),
+            "bottom-right" => (1.0, 1.0),
+            other => {
+                let (x, y) = other
+                    .split_once(',')
+                    .ok_or_else(|| format!("Invalid transition position: {}", other))?;
+                let x: f32 = x
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("Invalid transition position: {}", other))?;
+                let y: f32 = y
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("Invalid transition position: {}", other))?;
+                (x, y)
+            }
+        };
+
+        Ok(Self::Fixed {
+            x: x.clamp(0.0, 1.0),
+            y: y.clamp(0.0, 1.0),
+        })
+    }
+}
+
+layered_table! {
+/// Tuning parameters for transition effects
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TransitionOptions in transition_options as TransitionOptionsLayer {
+    /// Direction in degrees for wipe and blinds (0 = left to right)
+    pub angle: f32,
+    /// Width of soft edges, as a fraction of the screen
+    pub softness: f32,
+    /// Number of slats for blinds
+    pub blinds: u32,
+    /// Wave distortion amplitude, as a fraction of the screen
+    pub wave_amplitude: f32,
+    /// Number of wave periods across the screen
+    pub wave_frequency: f32,
+    /// Largest block size in pixels for pixelate
+    pub pixel_size: f32,
+    /// Noise cell size in pixels for dissolve
+    pub dissolve_grain: f32,
+}
+}
+
+impl Default for TransitionOptions {
+    fn default() -> Self {
+        Self {
+            angle: 0.0,
+            softness: 0.02,
+            blinds: 12,
+            wave_amplitude: 0.03,
+            wave_frequency: 8.0,
+            pixel_size: 64.0,
+            dissolve_grain: 4.0,
+        }
+    }
+}
+
+layered_table! {
+/// Ken Burns style pan and zoom, played over each image's display time
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct MotionConfig in motion as MotionLayer {
+    /// Zoom at the start and end of an image, 1.0 = fitted size
+    pub zoom: [f32; 2],
+    /// Direction the view drifts in
+    pub pan: PanDirection,
+    /// Point to move towards when `pan = "focus"`, fractions from the top-left
+    pub focus: [f32; 2],
+    /// Frame rate cap for the animation
+    pub fps: u32,
+    /// How long one pass takes when the monitor has no slideshow `duration`
+    #[serde(with = "humantime_serde")]
+    pub period: Duration,
+}
+}
+
+impl Default for MotionConfig {
+    fn default() -> Self {
+        Self {
+            zoom: [1.0, 1.15],
+            pan: PanDirection::Random,
+            focus: [0.5, 0.5],
+            fps: 30,
+            period: Duration::from_secs(60),
+        }
+    }
+}
+
+layered_table! {
+/// Workspace parallax: the wallpaper scrolls across an image wider (or
+/// taller) than the screen as the workspace index changes
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ParallaxConfig in parallax as ParallaxLayer {
+    /// Number of workspaces spread over the image, later ones stay at the end
+    pub workspaces: u32,
+    /// Scroll vertically over a tall image instead of horizontally
+    pub vertical: bool,
+    /// Duration of the scroll animation in milliseconds
+    pub time: u32,
+}
+}
+
+impl Default for ParallaxConfig {
+    fn default() -> Self {
+        Self {
+            workspaces: 10,
+            vertical: false,
+            time: 400,
+        }
+    }
+}
+
+impl ParallaxConfig {
+    /// Position of a workspace along the image, 0..1
+    pub fn position(&self, workspace: i32) -> f32 {
+        let last = self.workspaces.max(2) - 1;
+        let index = (workspace - 1).clamp(0, last as i32);
+        index as f32 / last as f32
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
 #[serde(rename_all = "lowercase")]
+pub enum PanDirection {
+    /// Zoom around the center only
+    None,
+    Left,
+    Right,
+    Up,
+    Down,
+    /// Random start and end points and zoom direction for every image
+    #[default]
+    Random,
+    /// Zoom towards `focus`
+    Focus,
+}
+
+/// What happens to a wallpaper change that arrives mid-transition
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum QueuePolicy {
+    /// Start the new transition from the frame currently on screen
+    #[default]
+    Interrupt,
+    /// Play the change once the running transition has finished
+    Queue,
+    /// Ignore the change
+    Drop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
 pub enum BackgroundMode {
     /// Scale to cover entire screen, may crop
     #[default]
@@ -201,6 +999,20 @@ pub enum BackgroundMode {
     Center,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TextureFilter {
+    /// Sharp pixel edges, for pixel art
+    Nearest,
+    /// Bilinear sampling
+    #[default]
+    Linear,
+    /// Trilinear sampling from generated mipmaps, smooth when shrinking a lot
+    Mipmap,
+    /// Shrink to the on-screen size on the CPU with a Lanczos filter
+    Lanczos,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum SortingMethod {
@@ -213,20 +1025,311 @@ pub enum SortingMethod {
     Descending,
 }
 
-/// Expand ~ to home directory
-fn expand_path(path: &Path) -> PathBuf {
-    if let Ok(stripped) = path.strip_prefix("~") {
-        if let Some(home) = dirs::home_dir() {
-            return home.join(stripped);
+/// TOML keys are always strings, so parse workspace numbers ourselves
+fn deserialize_workspace_map<'de, D>(deserializer: D) -> Result<HashMap<i32, PathBuf>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let raw: HashMap<String, PathBuf> = HashMap::deserialize(deserializer)?;
+    raw.into_iter()
+        .map(|(key, path)| {
+            key.parse::<i32>()
+                .map(|id| (id, path))
+                .map_err(|_| serde::de::Error::custom(format!("Invalid workspace number: {}", key)))
+        })
+        .collect()
+}
+
+/// Match `text` against a shell-style pattern, where `*` stands for any
+/// run of characters and `?` for one
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text it has swallowed up to
+    let mut star = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// `*.toml` files in the `conf.d` directory next to a config file, in
+/// lexical order
+pub fn conf_d(path: &Path) -> Vec<PathBuf> {
+    let Some(dir) = path.parent().map(|dir| dir.join("conf.d")) else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Reads config files and the files they include into one table
+#[derive(Default)]
+struct Loader {
+    /// Files being included, to catch cycles
+    stack: Vec<PathBuf>,
+    sources: Vec<PathBuf>,
+}
+
+impl Loader {
+    fn load_file(&mut self, path: &Path) -> Result<toml::Table, ConfigError> {
+        let in_file = |source: ConfigError| ConfigError::File {
+            path: path.to_path_buf(),
+            source: Box::new(source),
+        };
+
+        let canonical = path.canonicalize().map_err(|e| in_file(e.into()))?;
+        if let Some(start) = self.stack.iter().position(|p| *p == canonical) {
+            let chain: Vec<_> = self.stack[start..]
+                .iter()
+                .chain([&canonical])
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(ConfigError::IncludeCycle(chain.join(" -> ")));
+        }
+        if !self.sources.contains(&canonical) {
+            self.sources.push(canonical.clone());
+        }
+
+        let content = fs::read_to_string(path).map_err(|e| in_file(e.into()))?;
+        // Each file must be valid on its own, so errors point into it
+        let own: Config = toml::from_str(&content).map_err(|e| in_file(e.into()))?;
+        let mut table: toml::Table = toml::from_str(&content).map_err(|e| in_file(e.into()))?;
+        table.remove("include");
+        let dir = path.parent().unwrap_or(Path::new(""));
+        expand_table_paths(&mut table, dir).map_err(in_file)?;
+
+        self.stack.push(canonical);
+        let mut merged = toml::Table::new();
+        for include in &own.include {
+            let included = expand_path(Path::new(include), dir).map_err(in_file)?;
+            if !included.is_file() {
+                return Err(in_file(ConfigError::InvalidPath(format!(
+                    "included file {} does not exist",
+                    included.display()
+                ))));
+            }
+            let included = self.load_file(&included)?;
+            merge_tables(&mut merged, included);
+        }
+        self.stack.pop();
+
+        // The including file's own keys win over what it includes
+        merge_tables(&mut merged, table);
+        Ok(merged)
+    }
+}
+
+/// Merge `overlay` into `base`, recursing into tables both have
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge_tables(base, overlay)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
         }
     }
-    path.to_path_buf()
+}
+
+/// Expand the wallpaper paths of a file's table, relative to its directory
+fn expand_table_paths(table: &mut toml::Table, dir: &Path) -> Result<(), ConfigError> {
+    let mut paths: Vec<&mut toml::Value> = Vec::new();
+    for (key, value) in table.iter_mut() {
+        let Some(section) = value.as_table_mut() else {
+            continue;
+        };
+        match key.as_str() {
+            "default" => paths.extend(section.get_mut("path")),
+            "monitors" | "groups" => {
+                for (_, monitor) in section.iter_mut() {
+                    let Some(monitor) = monitor.as_table_mut() else {
+                        continue;
+                    };
+                    for (key, value) in monitor.iter_mut() {
+                        match (key.as_str(), value) {
+                            ("path", value) => paths.push(value),
+                            ("schedule", toml::Value::Table(schedule)) => {
+                                paths.extend(schedule.iter_mut().map(|(_, v)| v))
+                            }
+                            ("daylight", toml::Value::Table(daylight)) => paths.extend(
+                                daylight
+                                    .iter_mut()
+                                    .filter(|(key, _)| matches!(key.as_str(), "day" | "night"))
+                                    .map(|(_, v)| v),
+                            ),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            "workspaces" => paths.extend(
+                section
+                    .iter_mut()
+                    .filter(|(key, _)| key.as_str() != "enabled")
+                    .map(|(_, v)| v),
+            ),
+            "profiles" => {
+                for (_, profile) in section.iter_mut() {
+                    if let Some(profile) = profile.as_table_mut() {
+                        expand_table_paths(profile, dir)?;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for value in paths {
+        if let toml::Value::String(path) = value {
+            let expanded = expand_path(Path::new(path), dir)?;
+            *path = expanded.to_string_lossy().into_owned();
+        }
+    }
+    Ok(())
+}
+
+/// Expand a path from the config: `$VAR` and `${VAR:-default}`, a leading
+/// `~`, then relative paths against `base`, the directory of the file the
+/// path is written in. An empty path stays empty.
+pub fn expand_path(path: &Path, base: &Path) -> Result<PathBuf, ConfigError> {
+    if path.as_os_str().is_empty() {
+        return Ok(PathBuf::new());
+    }
+    let path = match path.to_str() {
+        Some(text) => PathBuf::from(expand_vars(text, &variable)?),
+        None => path.to_path_buf(),
+    };
+    let path = match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path,
+    };
+    Ok(base.join(path))
+}
+
+/// Replace `$VAR`, `${VAR}` and `${VAR:-default}` (used when VAR is unset
+/// or empty) in a string, with values from `lookup`. A `$` not followed by
+/// a name is kept.
+fn expand_vars(
+    text: &str,
+    lookup: &impl Fn(&str) -> Option<String>,
+) -> Result<String, ConfigError> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        if let Some(braced) = rest.strip_prefix('{') {
+            let Some(end) = braced.find('}') else {
+                result.push('$');
+                continue;
+            };
+            let (name, default) = match braced[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&braced[..end], None),
+            };
+            rest = &braced[end + 1..];
+            match (lookup(name), default) {
+                (Some(value), Some(default)) if value.is_empty() => {
+                    result.push_str(&expand_vars(default, lookup)?)
+                }
+                (Some(value), _) => result.push_str(&value),
+                (None, Some(default)) => result.push_str(&expand_vars(default, lookup)?),
+                (None, None) => return Err(ConfigError::UndefinedVariable(name.to_string())),
+            }
+        } else {
+            let starts_name = rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_');
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            if !starts_name {
+                result.push('$');
+                continue;
+            }
+            let name = &rest[..len];
+            rest = &rest[len..];
+            let value =
+                lookup(name).ok_or_else(|| ConfigError::UndefinedVariable(name.to_string()))?;
+            result.push_str(&value);
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// An environment variable, or one of the values shells and desktop
+/// sessions usually provide but don't always export
+fn variable(name: &str) -> Option<String> {
+    if let Ok(value) = std::env::var(name) {
+        return Some(value);
+    }
+    match name {
+        "HOSTNAME" => hostname(),
+        // From user-dirs.dirs, like the file manager
+        "XDG_PICTURES_DIR" => dirs::picture_dir()
+            .or_else(|| dirs::home_dir().map(|home| home.join("Pictures")))
+            .map(|dir| dir.to_string_lossy().into_owned()),
+        _ => None,
+    }
+}
+
+/// The machine's host name
+fn hostname() -> Option<String> {
+    let mut buffer = [0u8; 256];
+    // SAFETY: the buffer is valid for its length, gethostname truncates
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+    if result != 0 {
+        return None;
+    }
+    let len = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    Some(String::from_utf8_lossy(&buffer[..len]).into_owned())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn test_output(name: &str, description: Option<&str>, serial: Option<&str>) -> OutputIdentity {
+        OutputIdentity {
+            id: 1,
+            name: Some(name.to_string()),
+            make: "Dell Inc.".to_string(),
+            model: "DELL U2720Q".to_string(),
+            description: description.map(str::to_string),
+            serial: serial.map(str::to_string),
+        }
+    }
+
     #[test]
     fn test_default_config() {
         let config = Config::default();
@@ -235,10 +1338,477 @@ mod tests {
         assert_eq!(config.default.mode, BackgroundMode::Cover);
     }
 
+    #[test]
+    fn test_transition_config() {
+        let config: Config = toml::from_str(
+            r#"
+            [default]
+            transition = "random"
+            transition_pos = "top-right"
+            transition_random = ["grow", "zoom_in", "slide_left"]
+
+            [default.transition_options]
+            blinds = 6
+
+            [monitors.DP-1]
+            path = "/tmp/a.png"
+            transition = "wave"
+            transition_pos = "0.25, 0.75"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.default.transition, TransitionType::Random);
+        assert_eq!(
+            config.default.transition_pos,
+            TransitionPosition::Fixed { x: 1.0, y: 0.0 }
+        );
+        assert_eq!(
+            config.default.transition_random,
+            vec![TransitionType::Grow, TransitionType::ZoomIn, TransitionType::SlideLeft]
+        );
+        assert_eq!(config.default.transition_options.blinds, 6);
+        assert_eq!(config.default.transition_options.angle, 0.0);
+
+        let monitor = config.get_monitor_config(&test_output("DP-1", None, None));
+        assert_eq!(monitor.transition, TransitionType::Wave);
+        assert_eq!(monitor.transition_pos, TransitionPosition::Fixed { x: 0.25, y: 0.75 });
+    }
+
+    #[test]
+    fn test_workspace_config() {
+        let config: Config = toml::from_str(
+            r#"
+            [default]
+            transition_queue = "queue"
+
+            [workspaces]
+            enabled = true
+            1 = "/tmp/one.png"
+            2 = "/tmp/two.png"
+            "#,
+        )
+        .unwrap();
+
+        assert!(config.workspaces.enabled);
+        assert_eq!(config.default.transition_queue, QueuePolicy::Queue);
+        let output = test_output("DP-1", None, None);
+        assert_eq!(
+            config.get_wallpaper_for_workspace(&output, 2),
+            Some(PathBuf::from("/tmp/two.png"))
+        );
+        assert_eq!(config.get_wallpaper_for_workspace(&output, 3), None);
+    }
+
+    #[test]
+    fn test_motion_config() {
+        let config: Config = toml::from_str(
+            r#"
+            [monitors.DP-1]
+            path = "/tmp/slides"
+            duration = "10m"
+
+            [monitors.DP-1.motion]
+            zoom = [1.2, 1.0]
+            pan = "focus"
+            focus = [0.3, 0.6]
+            "#,
+        )
+        .unwrap();
+
+        let motion = config.get_monitor_config(&test_output("DP-1", None, None)).motion.unwrap();
+        assert_eq!(motion.zoom, [1.2, 1.0]);
+        assert_eq!(motion.pan, PanDirection::Focus);
+        assert_eq!(motion.focus, [0.3, 0.6]);
+        assert_eq!(motion.fps, 30);
+        assert_eq!(config.default.idle_timeout, Duration::from_secs(300));
+    }
+
+    #[test]
+    fn test_parallax_config() {
+        let config: Config = toml::from_str(
+            r#"
+            [monitors.DP-1]
+            path = "/tmp/panorama.png"
+
+            [monitors.DP-1.parallax]
+            workspaces = 5
+            "#,
+        )
+        .unwrap();
+
+        let parallax = config.get_monitor_config(&test_output("DP-1", None, None)).parallax.unwrap();
+        assert!(!parallax.vertical);
+        assert_eq!(parallax.position(1), 0.0);
+        assert_eq!(parallax.position(3), 0.5);
+        assert_eq!(parallax.position(5), 1.0);
+        // Out of range workspaces stay at the ends
+        assert_eq!(parallax.position(9), 1.0);
+        assert_eq!(parallax.position(-98), 0.0);
+    }
+
+    #[test]
+    fn test_texture_filter() {
+        let config: Config = toml::from_str(
+            r#"
+            [default]
+            filter = "mipmap"
+
+            [monitors.DP-1]
+            path = "/tmp/pixel-art.png"
+            filter = "nearest"
+            "#,
+        )
+        .unwrap();
+
+        let filter = |name| config.get_monitor_config(&test_output(name, None, None)).filter;
+        assert_eq!(filter("DP-1"), TextureFilter::Nearest);
+        assert_eq!(filter("HDMI-A-1"), TextureFilter::Mipmap);
+        assert!(toml::from_str::<Config>("[default]\nfilter = \"bicubic\"").is_err());
+    }
+
+    #[test]
+    fn test_invalid_transition_position() {
+        assert!("middle".parse::<TransitionPosition>().is_err());
+        assert!("0.5".parse::<TransitionPosition>().is_err());
+        assert_eq!("cursor".parse(), Ok(TransitionPosition::Cursor));
+    }
+
     #[test]
     fn test_expand_path() {
-        let path = Path::new("~/Pictures/test.jpg");
-        let expanded = expand_path(path);
+        let base = Path::new("/etc/canviz");
+        let expanded = expand_path(Path::new("~/Pictures/test.jpg"), base).unwrap();
         assert!(!expanded.starts_with("~"));
+        assert_eq!(
+            expand_path(Path::new("walls/a.png"), base).unwrap(),
+            PathBuf::from("/etc/canviz/walls/a.png")
+        );
+        assert_eq!(expand_path(Path::new("/tmp/a.png"), base).unwrap(), PathBuf::from("/tmp/a.png"));
+        assert_eq!(expand_path(Path::new(""), base).unwrap(), PathBuf::new());
+    }
+
+    #[test]
+    fn test_expand_vars() {
+        let lookup = |name: &str| match name {
+            "WALLS" => Some("/srv/walls".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+        let expand = |text| expand_vars(text, &lookup);
+        assert_eq!(expand("$WALLS/a.png").unwrap(), "/srv/walls/a.png");
+        assert_eq!(expand("${WALLS}x").unwrap(), "/srv/wallsx");
+        assert_eq!(expand("${UNSET:-/tmp}/a").unwrap(), "/tmp/a");
+        assert_eq!(expand("${EMPTY:-$WALLS}").unwrap(), "/srv/walls");
+        assert_eq!(expand("${EMPTY}a").unwrap(), "a");
+        assert_eq!(expand("cost $5 and $").unwrap(), "cost $5 and $");
+        assert!(matches!(
+            expand("$UNSET/a"),
+            Err(ConfigError::UndefinedVariable(name)) if name == "UNSET"
+        ));
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("DP-1", "DP-1"));
+        assert!(!glob_match("DP-1", "DP-12"));
+        assert!(glob_match("DP-*", "DP-12"));
+        assert!(glob_match("*U2720Q*", "Dell Inc. DELL U2720Q 8XYZ"));
+        assert!(glob_match("HDMI-A-?", "HDMI-A-1"));
+        assert!(!glob_match("HDMI-A-?", "HDMI-A-10"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("a*b", "acbd"));
+    }
+
+    #[test]
+    fn test_monitor_matching() {
+        let config: Config = toml::from_str(
+            r#"
+            [monitors."DP-*"]
+            path = "/tmp/any-dp.png"
+
+            [monitors.DP-1]
+            path = "/tmp/dp-1.png"
+
+            [monitors.desk]
+            model = "*U2720Q"
+            serial = "8XYZ"
+            path = "/tmp/desk.png"
+
+            [monitors.tv]
+            description = "LG Electronics*"
+            priority = 10
+            path = "/tmp/tv.png"
+            "#,
+        )
+        .unwrap();
+
+        let path = |output: &OutputIdentity| config.get_monitor_config(output).path;
+
+        // An exact name beats a pattern of the same priority
+        assert_eq!(path(&test_output("DP-1", None, None)), PathBuf::from("/tmp/dp-1.png"));
+        assert_eq!(path(&test_output("DP-3", None, None)), PathBuf::from("/tmp/any-dp.png"));
+        assert_eq!(path(&test_output("eDP-1", None, None)), PathBuf::new());
+
+        // Matchers ignore the connector, the serial falls back to the description
+        let desk = test_output("HDMI-A-1", Some("Dell Inc. DELL U2720Q 8XYZ"), None);
+        assert_eq!(path(&desk), PathBuf::from("/tmp/desk.png"));
+        let other = test_output("HDMI-A-1", None, Some("9ABC"));
+        assert_eq!(path(&other), PathBuf::new());
+
+        // Both the name pattern and the matchers apply, the hardware wins
+        let desk_on_dp = test_output("DP-5", None, Some("8XYZ"));
+        assert_eq!(config.find_monitor(&desk_on_dp).unwrap().0, "desk");
+
+        // Priority beats everything
+        let tv = test_output("DP-1", Some("LG Electronics OLED 0x0101"), None);
+        assert_eq!(path(&tv), PathBuf::from("/tmp/tv.png"));
+    }
+
+    #[test]
+    fn test_layered_config() {
+        let config: Config = toml::from_str(
+            r#"
+            [default]
+            path = "/tmp/default.png"
+            mode = "contain"
+            transition = "wipe"
+            transition_time = 700
+
+            [workspaces]
+            enabled = true
+            2 = "/tmp/two.png"
+
+            [groups.docked]
+            monitors = ["DP-*"]
+            transition_time = 500
+            dither = false
+
+            [groups.dell]
+            make = "Dell*"
+            priority = 1
+            transition_time = 900
+
+            [monitors.DP-1]
+            path = "/tmp/dp-1.png"
+            transition = "fade"
+            "#,
+        )
+        .unwrap();
+
+        let resolved = config.resolve(&test_output("DP-1", None, None), Some(2));
+        let monitor = &resolved.config;
+        // Values a monitor section leaves out come from the layers below
+        assert_eq!(monitor.mode, BackgroundMode::Contain);
+        assert_eq!(monitor.transition, TransitionType::Fade);
+        assert_eq!(monitor.transition_time, 900);
+        assert!(!monitor.dither);
+        assert_eq!(monitor.path, PathBuf::from("/tmp/two.png"));
+
+        assert_eq!(resolved.sources["mode"], ConfigLayer::Default);
+        assert_eq!(resolved.sources["dither"], ConfigLayer::Group("docked".to_string()));
+        assert_eq!(resolved.sources["transition_time"], ConfigLayer::Group("dell".to_string()));
+        assert_eq!(resolved.sources["transition"], ConfigLayer::Monitor("DP-1".to_string()));
+        assert_eq!(resolved.sources["path"], ConfigLayer::Workspace(2));
+
+        // Outside the group only the defaults apply
+        let laptop = config.get_monitor_config(&OutputIdentity {
+            make: "BOE".to_string(),
+            ..test_output("eDP-1", None, None)
+        });
+        assert_eq!(laptop.transition_time, 700);
+        assert!(laptop.dither);
+        assert_eq!(laptop.path, PathBuf::from("/tmp/default.png"));
+    }
+
+    #[test]
+    fn test_nested_tables() {
+        let config: Config = toml::from_str(
+            r#"
+            [default.transition_options]
+            blinds = 6
+
+            [groups.all.motion]
+            fps = 60
+
+            [monitors.DP-1]
+            transition_options = { angle = 90 }
+            motion = { zoom = [1.0, 1.3] }
+            "#,
+        )
+        .unwrap();
+
+        let resolved = config.resolve(&test_output("DP-1", None, None), None);
+        let monitor = &resolved.config;
+        // Keys merge with the lower layers instead of replacing the table
+        assert_eq!(monitor.transition_options.blinds, 6);
+        assert_eq!(monitor.transition_options.angle, 90.0);
+        assert_eq!(monitor.transition_options.softness, 0.02);
+        let motion = monitor.motion.as_ref().unwrap();
+        assert_eq!((motion.fps, motion.zoom), (60, [1.0, 1.3]));
+
+        let group = ConfigLayer::Group("all".to_string());
+        let dp_1 = ConfigLayer::Monitor("DP-1".to_string());
+        assert_eq!(resolved.sources["transition_options.blinds"], ConfigLayer::Default);
+        assert_eq!(resolved.sources["transition_options.angle"], dp_1);
+        assert_eq!(resolved.sources["motion.fps"], group);
+        assert_eq!(resolved.sources["motion.zoom"], dp_1);
+        assert_eq!(resolved.sources["motion.period"], group);
+
+        let explained = resolved.explain();
+        let angle = explained.iter().find(|(key, _, _)| *key == "transition_options.angle");
+        assert_eq!(angle, Some(&("transition_options.angle", Some("90.0".to_string()), Some(&dp_1))));
+        let parallax = explained.iter().find(|(key, _, _)| *key == "parallax");
+        assert_eq!(parallax, Some(&("parallax", None, None)));
+    }
+
+    /// A directory of config files named after the test
+    fn config_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("canviz-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        for (file, content) in files {
+            fs::write(dir.join(file), content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_include_and_conf_d() {
+        let dir = config_dir(
+            "include",
+            &[
+                (
+                    "config.toml",
+                    "include = [\"monitors.toml\"]\n[default]\ntransition_time = 500\n",
+                ),
+                (
+                    "monitors.toml",
+                    "[default]\ntransition_time = 100\nmode = \"tile\"\n[monitors.DP-1]\npath = \"/tmp/a.png\"\n",
+                ),
+                (
+                    "conf.d/10-first.toml",
+                    "[monitors.DP-1]\npath = \"/tmp/b.png\"\n[monitors.HDMI-A-1]\npath = \"walls\"\n",
+                ),
+                ("conf.d/20-second.toml", "[monitors.DP-1]\npath = \"/tmp/c.png\"\n"),
+                ("conf.d/30-ignored.txt", "not toml"),
+            ],
+        );
+
+        let config = Config::load(&dir.join("config.toml")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // The including file wins over what it includes, tables are merged
+        assert_eq!(config.default.transition_time, 500);
+        assert_eq!(config.default.mode, BackgroundMode::Tile);
+        // Drop-ins override both, in lexical order
+        assert_eq!(config.monitors["DP-1"].path, PathBuf::from("/tmp/c.png"));
+        assert_eq!(config.sources.len(), 4);
+        // Relative paths start from the file they're written in
+        assert_eq!(config.monitors["HDMI-A-1"].path, dir.join("conf.d/walls"));
+    }
+
+    #[test]
+    fn test_daylight_paths() {
+        let daylight = |day| {
+            let location = "latitude = 0.0\nlongitude = 0.0\n";
+            format!("[monitors.DP-1.daylight]\nday = {:?}\nnight = \"/n.png\"\n{}", day, location)
+        };
+        let dir = config_dir(
+            "daylight",
+            &[
+                ("config.toml", "include = [\"conf.d/day.toml\"]\n"),
+                ("conf.d/day.toml", &daylight("day.png")),
+            ],
+        );
+        let walls_dir = config_dir("daylight-walls", &[("config.toml", &daylight("conf.d"))]);
+
+        let config = Config::load(&dir.join("config.toml")).unwrap();
+        let walls = Config::load(&walls_dir.join("config.toml")).unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&walls_dir).unwrap();
+
+        // Relative paths start from the file they're written in
+        let daylight = config.monitors["DP-1"].daylight.as_ref().unwrap();
+        assert_eq!(daylight.day, dir.join("conf.d/day.png"));
+        assert_eq!(daylight.night, PathBuf::from("/n.png"));
+        assert_eq!(daylight.day_elevation, 6.0);
+        // A directory would never blend
+        assert!(matches!(walls, ConfigError::DaylightPath(path) if path == walls_dir.join("conf.d")));
+    }
+
+    #[test]
+    fn test_include_errors_name_the_file() {
+        let dir = config_dir(
+            "include-errors",
+            &[
+                ("config.toml", "include = [\"a.toml\"]\n"),
+                ("a.toml", "include = [\"b.toml\"]\n"),
+                ("b.toml", "include = [\"a.toml\"]\n"),
+                ("bad.toml", "include = [\"broken.toml\"]\n"),
+                ("broken.toml", "[default]\nmode = \"stretch\"\n"),
+            ],
+        );
+
+        let cycle = Config::load(&dir.join("config.toml")).unwrap_err();
+        let broken = Config::load(&dir.join("bad.toml")).unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let files: Vec<_> = match &cycle {
+            ConfigError::IncludeCycle(chain) => chain
+                .split(" -> ")
+                .map(|p| Path::new(p).file_name().unwrap().to_owned())
+                .collect(),
+            _ => unreachable!(),
+        };
+        assert_eq!(files, ["a.toml", "b.toml", "a.toml"]);
+        assert!(broken.to_string().contains("broken.toml: Failed to parse config"));
+    }
+
+    #[test]
+    fn test_profiles() {
+        let dir = config_dir(
+            "profiles",
+            &[
+                (
+                    "config.toml",
+                    r#"
+                    profile = "work"
+
+                    [default]
+                    transition_time = 300
+
+                    [monitors.DP-1]
+                    path = "/tmp/home.png"
+                    mode = "tile"
+
+                    [profiles.work.monitors.DP-1]
+                    path = "/tmp/work.png"
+
+                    [profiles.gaming.default]
+                    transition_time = 0
+                    "#,
+                ),
+                ("broken.toml", "[profiles.work.default]\nmode = \"stretch\"\n"),
+            ],
+        );
+
+        let config = Config::load(&dir.join("config.toml")).unwrap();
+        let broken = Config::load(&dir.join("broken.toml")).unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // The selected profile overlays single keys of the sections
+        assert_eq!(config.active_profile.as_deref(), Some("work"));
+        assert_eq!(config.monitors["DP-1"].path, PathBuf::from("/tmp/work.png"));
+        assert_eq!(config.monitors["DP-1"].mode, Some(BackgroundMode::Tile));
+        assert_eq!(config.profile_names(), ["gaming", "work"]);
+
+        // Switching starts from the files, not from the active profile
+        let gaming = config.with_profile(Some("gaming")).unwrap();
+        assert_eq!(gaming.default.transition_time, 0);
+        assert_eq!(gaming.monitors["DP-1"].path, PathBuf::from("/tmp/home.png"));
+        assert_eq!(config.with_profile(None).unwrap().default.transition_time, 300);
+        assert!(matches!(config.with_profile(Some("party")), Err(ConfigError::UnknownProfile(_))));
+
+        assert!(broken.to_string().starts_with("[profiles.work]: "));
     }
 }
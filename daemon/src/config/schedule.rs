@@ -0,0 +1,258 @@
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAY: u32 = 24 * 60 * 60;
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// Wall-clock time in the local time zone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalTime {
+    /// 0 for Monday to 6 for Sunday
+    pub weekday: u8,
+    /// Seconds since midnight
+    pub seconds: u32,
+}
+
+impl LocalTime {
+    pub fn now() -> Self {
+        Self::at(SystemTime::now())
+    }
+
+    pub fn at(time: SystemTime) -> Self {
+        let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()) as libc::time_t;
+        // SAFETY: localtime_r only writes the tm it's given
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        unsafe { libc::localtime_r(&secs, &mut tm) };
+        Self {
+            weekday: ((tm.tm_wday + 6) % 7) as u8,
+            seconds: (tm.tm_hour * 3600 + tm.tm_min * 60 + tm.tm_sec) as u32,
+        }
+    }
+}
+
+/// Days of the week an entry applies on, bit 0 for Monday
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Weekdays(u8);
+
+impl Weekdays {
+    const ALL: Weekdays = Weekdays(0x7f);
+
+    fn contains(self, weekday: u8) -> bool {
+        self.0 & (1 << weekday) != 0
+    }
+
+    /// Parse "mon", "mon-fri" or "sat,sun" (and combinations of them)
+    fn parse(text: &str) -> Result<Self, String> {
+        let day = |name: &str| {
+            WEEKDAYS
+                .iter()
+                .position(|day| name.eq_ignore_ascii_case(day))
+                .ok_or_else(|| {
+                    format!(
+                        "unknown weekday `{}`, expected one of {}",
+                        name,
+                        WEEKDAYS.join(", ")
+                    )
+                })
+        };
+
+        let mut days = 0;
+        for part in text.split(',') {
+            match part.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last) = (day(first)?, day(last)?);
+                    // Ranges may wrap around the weekend, "fri-mon"
+                    let mut day = first;
+                    loop {
+                        days |= 1 << day;
+                        if day == last {
+                            break;
+                        }
+                        day = (day + 1) % 7;
+                    }
+                }
+                None => days |= 1 << day(part)?,
+            }
+        }
+        Ok(Weekdays(days))
+    }
+}
+
+/// One wallpaper of a schedule, from its start time until the next entry's
+#[derive(Clone, PartialEq, Eq)]
+pub struct ScheduleEntry {
+    /// The key as written, "07:00" or "mon-fri 07:00"
+    pub key: String,
+    pub path: PathBuf,
+    days: Weekdays,
+    /// Start in seconds since midnight
+    start: u32,
+}
+
+impl ScheduleEntry {
+    fn parse(key: &str, path: PathBuf) -> Result<Self, String> {
+        let (days, time) = match key.trim().rsplit_once(char::is_whitespace) {
+            Some((days, time)) => (Weekdays::parse(days.trim())?, time),
+            None => (Weekdays::ALL, key.trim()),
+        };
+
+        let invalid = || format!("invalid time `{}`, expected HH:MM or HH:MM:SS", time);
+        let parts: Vec<u32> = time
+            .split(':')
+            .map(|part| part.parse().map_err(|_| invalid()))
+            .collect::<Result<_, _>>()?;
+        let start = match parts[..] {
+            [h, m] if h < 24 && m < 60 => h * 3600 + m * 60,
+            [h, m, s] if h < 24 && m < 60 && s < 60 => h * 3600 + m * 60 + s,
+            _ => return Err(invalid()),
+        };
+
+        Ok(Self {
+            key: key.to_string(),
+            path,
+            days,
+            start,
+        })
+    }
+
+    /// Entries limited to some weekdays win over everyday ones at the
+    /// same time
+    fn rank(&self) -> (u32, bool) {
+        (self.start, self.days != Weekdays::ALL)
+    }
+}
+
+/// Wallpapers by time of day, `[monitors.<name>.schedule]` mapping start
+/// times, optionally after weekdays, to files or directories
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Schedule {
+    entries: Vec<ScheduleEntry>,
+}
+
+impl Schedule {
+    /// The entry showing at `now`: the last one started, going back to
+    /// earlier days when none has started yet today
+    pub fn active(&self, now: &LocalTime) -> Option<&ScheduleEntry> {
+        (0..8).find_map(|days_back| {
+            let weekday = (now.weekday + 7 - days_back % 7) % 7;
+            self.entries
+                .iter()
+                .filter(|entry| entry.days.contains(weekday))
+                .filter(|entry| days_back > 0 || entry.start <= now.seconds)
+                .max_by_key(|entry| entry.rank())
+        })
+    }
+
+    /// Time until the next entry starts
+    pub fn until_next_change(&self, now: &LocalTime) -> Option<Duration> {
+        (0..8).find_map(|days_ahead| {
+            let weekday = (now.weekday + days_ahead % 7) % 7;
+            let start = self
+                .entries
+                .iter()
+                .filter(|entry| entry.days.contains(weekday))
+                .filter(|entry| days_ahead > 0 || entry.start > now.seconds)
+                .map(|entry| entry.start)
+                .min()?;
+            let seconds = days_ahead as u32 * DAY + start - now.seconds;
+            Some(Duration::from_secs(seconds.into()))
+        })
+    }
+}
+
+impl fmt::Debug for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.entries.iter().map(|entry| (&entry.key, &entry.path)))
+            .finish()
+    }
+}
+
+impl<'de> Deserialize<'de> for Schedule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw: HashMap<String, PathBuf> = HashMap::deserialize(deserializer)?;
+        let mut entries = raw
+            .into_iter()
+            .map(|(key, path)| {
+                ScheduleEntry::parse(&key, path)
+                    .map_err(|e| de::Error::custom(format!("schedule entry `{}`: {}", key, e)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by(|a, b| a.rank().cmp(&b.rank()).then_with(|| a.key.cmp(&b.key)));
+        Ok(Self { entries })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(entries: &[(&str, &str)]) -> Schedule {
+        let table: toml::Table = entries
+            .iter()
+            .map(|(key, path)| (key.to_string(), toml::Value::String(path.to_string())))
+            .collect();
+        Schedule::deserialize(toml::Value::Table(table)).unwrap()
+    }
+
+    fn at(weekday: u8, hour: u32, minute: u32) -> LocalTime {
+        LocalTime {
+            weekday,
+            seconds: hour * 3600 + minute * 60,
+        }
+    }
+
+    #[test]
+    fn test_active_entry() {
+        let schedule = schedule(&[
+            ("07:00", "morning"),
+            ("12:00", "day"),
+            ("22:00", "night"),
+            ("sat,sun 09:00", "weekend"),
+        ]);
+        let active = |time| schedule.active(&time).unwrap().key.clone();
+
+        assert_eq!(active(at(2, 8, 30)), "07:00");
+        assert_eq!(active(at(2, 12, 0)), "12:00");
+        // Before the first entry of the day, yesterday's last one shows
+        assert_eq!(active(at(2, 3, 0)), "22:00");
+        // Weekday entries only on their days
+        assert_eq!(active(at(5, 10, 0)), "sat,sun 09:00");
+        assert_eq!(active(at(4, 10, 0)), "07:00");
+    }
+
+    #[test]
+    fn test_weekday_only_schedule() {
+        let schedule = schedule(&[("mon 08:00", "monday"), ("fri-sun 18:00", "weekend")]);
+        // Tuesday to Friday evening still show Monday's entry
+        assert_eq!(schedule.active(&at(3, 12, 0)).unwrap().key, "mon 08:00");
+        assert_eq!(schedule.active(&at(0, 7, 0)).unwrap().key, "fri-sun 18:00");
+        assert_eq!(
+            schedule.until_next_change(&at(0, 7, 0)),
+            Some(Duration::from_secs(3600))
+        );
+        // From Monday morning the next start is Friday evening
+        assert_eq!(
+            schedule.until_next_change(&at(0, 8, 0)),
+            Some(Duration::from_secs(4 * DAY as u64 + 10 * 3600))
+        );
+    }
+
+    #[test]
+    fn test_invalid_entries() {
+        let parse = |key: &str| {
+            let table: toml::Table = [(key.to_string(), toml::Value::String("a".into()))]
+                .into_iter()
+                .collect();
+            Schedule::deserialize(toml::Value::Table(table))
+        };
+        assert!(parse("25:00").is_err());
+        assert!(parse("7am").is_err());
+        assert!(parse("someday 07:00").is_err());
+        assert!(parse("07:00:30").is_ok());
+    }
+}
@@ -0,0 +1,77 @@
+use hotwatch::{Event, EventKind, Hotwatch};
+use log::{debug, warn};
+use smithay_client_toolkit::reexports::calloop::channel::Sender;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Editors often write a file several times in a row, wait for them to settle
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Watches the config file, its includes and `conf.d`, and sends a message
+/// when any of them changes
+pub struct ConfigWatcher {
+    hotwatch: Hotwatch,
+    sender: Sender<()>,
+    watched: Vec<PathBuf>,
+}
+
+impl ConfigWatcher {
+    pub fn new(sender: Sender<()>) -> Result<Self, hotwatch::Error> {
+        Ok(Self {
+            hotwatch: Hotwatch::new_with_custom_delay(DEBOUNCE)?,
+            sender,
+            watched: Vec::new(),
+        })
+    }
+
+    /// Watch the files a config was loaded from. The config's directory is
+    /// watched as a whole, which covers `conf.d` and files editors replace
+    /// rather than rewrite; included files elsewhere are watched one by one.
+    pub fn update(&mut self, config_path: &Path, sources: &[PathBuf]) {
+        for path in self.watched.drain(..) {
+            let _ = self.hotwatch.unwatch(&path);
+        }
+
+        let dir = config_path.parent().and_then(|dir| dir.canonicalize().ok());
+        if let Some(dir) = &dir {
+            let sender = self.sender.clone();
+            let watched = self.hotwatch.watch(dir, move |event: Event| {
+                let is_toml = event
+                    .paths
+                    .iter()
+                    .any(|p| p.extension().is_some_and(|e| e == "toml"));
+                if is_toml && is_change(&event) {
+                    let _ = sender.send(());
+                }
+            });
+            match watched {
+                Ok(()) => self.watched.push(dir.clone()),
+                Err(e) => warn!("Failed to watch {:?} for config changes: {}", dir, e),
+            }
+        }
+
+        let outside = sources
+            .iter()
+            .filter(|p| dir.as_ref().is_none_or(|dir| !p.starts_with(dir)));
+        for path in outside {
+            let sender = self.sender.clone();
+            let watched = self.hotwatch.watch(path, move |event: Event| {
+                if is_change(&event) {
+                    let _ = sender.send(());
+                }
+            });
+            match watched {
+                Ok(()) => self.watched.push(path.clone()),
+                Err(e) => warn!("Failed to watch {:?} for config changes: {}", path, e),
+            }
+        }
+        debug!("Watching {:?} for config changes", self.watched);
+    }
+}
+
+fn is_change(event: &Event) -> bool {
+    matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+    )
+}
@@ -1,14 +1,26 @@
-use crate::config::Config;
-use crate::render::init_egl_display;
-use crate::surface::WallpaperSurface;
-use color_eyre::eyre::{Result, WrapErr};
+use crate::config::{self, Config, ConfigLayer, ConfigWatcher, TransitionPosition};
+use crate::hyprland::{
+    self, CursorPosition, HyprlandClient, HyprlandMonitor, WorkspaceEvent, WorkspaceListener,
+};
+use crate::image::{kde, DecodedImage, ImageLoader};
+use crate::ipc::{ConfigValue, IpcCommand, IpcRequest, IpcResponse, IpcServer, MonitorStatus};
+use crate::render::{init_egl_display, EglContext, GlResources, GpuLoss};
+use crate::state;
+use crate::output::OutputIdentity;
+use crate::surface::{FrameFeedback, SavedState, WallpaperSurface};
+use color_eyre::eyre::{eyre, Result, WrapErr};
 use log::{debug, error, info, warn};
+use smithay_client_toolkit::reexports::calloop::timer::{TimeoutAction, Timer};
+use smithay_client_toolkit::reexports::calloop::{channel, EventLoop, LoopHandle};
+use smithay_client_toolkit::reexports::calloop_wayland_source::WaylandSource;
 use smithay_client_toolkit::{
     compositor::{CompositorHandler, CompositorState},
-    delegate_compositor, delegate_layer, delegate_output, delegate_registry, delegate_shm,
-    output::{OutputHandler, OutputState},
+    delegate_compositor, delegate_layer, delegate_output, delegate_registry, delegate_seat,
+    delegate_shm,
+    output::{OutputHandler, OutputInfo, OutputState},
     registry::{ProvidesRegistryState, RegistryState},
     registry_handlers,
+    seat::{Capability, SeatHandler, SeatState},
     shell::wlr_layer::{
         Anchor, KeyboardInteractivity, Layer, LayerShell, LayerShellHandler, LayerSurface,
         LayerSurfaceConfigure,
@@ -16,59 +28,168 @@ use smithay_client_toolkit::{
     shm::{Shm, ShmHandler},
 };
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
 use wayland_client::{
     globals::registry_queue_init,
-    protocol::{wl_output, wl_surface},
-    Connection, QueueHandle,
+    protocol::{wl_output, wl_seat, wl_surface},
+    Connection, Dispatch, QueueHandle,
+};
+use wayland_protocols::ext::idle_notify::v1::client::{
+    ext_idle_notification_v1::{self, ExtIdleNotificationV1},
+    ext_idle_notifier_v1::ExtIdleNotifierV1,
+};
+use wayland_protocols::wp::fractional_scale::v1::client::{
+    wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+    wp_fractional_scale_v1::{self, WpFractionalScaleV1},
 };
+use wayland_protocols::wp::presentation_time::client::{
+    wp_presentation::WpPresentation,
+    wp_presentation_feedback::{self, WpPresentationFeedback},
+};
+use wayland_protocols::wp::viewporter::client::{wp_viewport::WpViewport, wp_viewporter::WpViewporter};
+
+/// How long to wait for Hyprland when querying it from the event loop
+const HYPRLAND_QUERY_TIMEOUT: Duration = Duration::from_millis(100);
 
-extern crate khronos_egl as egl;
+/// Disconnected monitors whose wallpaper is remembered for a reconnect
+const MAX_DISCONNECTED_OUTPUTS: usize = 16;
+
+/// Longest wait between schedule checks, which bounds how late a schedule
+/// follows a resume from suspend or a clock change
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Time between updates of the day/night blend. Dusk takes most of an hour,
+/// so steps this far apart are hardly noticeable.
+const DAYLIGHT_UPDATE_INTERVAL: Duration = Duration::from_secs(3 * 60);
 
 /// Main daemon state
 pub struct Canviz {
     pub config: Config,
+    pub config_path: PathBuf,
+    /// Reloads the config when it or a file it includes changes
+    pub config_watcher: Option<ConfigWatcher>,
+    pub runtime: tokio::runtime::Runtime,
+    pub qh: QueueHandle<Canviz>,
+    /// For timers of the surfaces
+    pub loop_handle: LoopHandle<'static, Canviz>,
     pub registry_state: RegistryState,
     pub output_state: OutputState,
     pub compositor_state: CompositorState,
     pub layer_shell: LayerShell,
     pub shm: Shm,
-    pub egl_display: egl::Display,
-    pub surfaces: HashMap<String, WallpaperSurface>,
+    pub seat_state: SeatState,
+    /// wp_fractional_scale_manager_v1 and wp_viewporter, used together
+    pub fractional_scale: Option<(WpFractionalScaleManagerV1, WpViewporter)>,
+    /// ext-idle-notify, if the compositor supports it
+    pub idle_notifier: Option<ExtIdleNotifierV1>,
+    /// Idle notifications per seat, created with `idle_timeout`
+    pub idle_notifications: HashMap<wl_seat::WlSeat, ExtIdleNotificationV1>,
+    /// EGL context shared by all outputs
+    pub egl_context: Rc<EglContext>,
+    /// Programs and textures in the shared context
+    pub gl_resources: Rc<GlResources>,
+    /// Decodes wallpapers off the event loop
+    pub image_loader: ImageLoader,
+    /// wp_presentation, for frame timing statistics
+    pub presentation: Option<WpPresentation>,
+    pub surfaces: HashMap<wl_output::WlOutput, WallpaperSurface>,
+    /// What disconnected monitors were showing, most recent last
+    pub disconnected: Vec<(OutputIdentity, SavedState)>,
+    /// Wallpapers set with `canvizctl set`, until a workspace or config
+    /// change replaces them
+    pub wallpaper_overrides: HashMap<wl_output::WlOutput, PathBuf>,
+    /// Active Hyprland workspace per monitor
+    pub active_workspaces: HashMap<String, i32>,
+    /// Monitor that currently has focus in Hyprland
+    pub focused_monitor: Option<String>,
+    /// Wall clock and monotonic time of the last schedule check
+    pub schedule_checked: Option<(SystemTime, Instant)>,
     pub exit: bool,
 }
 
 impl Canviz {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         config: Config,
+        config_path: PathBuf,
+        config_watcher: Option<ConfigWatcher>,
+        runtime: tokio::runtime::Runtime,
+        qh: QueueHandle<Canviz>,
+        loop_handle: LoopHandle<'static, Canviz>,
         registry_state: RegistryState,
         output_state: OutputState,
         compositor_state: CompositorState,
         layer_shell: LayerShell,
         shm: Shm,
-        egl_display: egl::Display,
+        seat_state: SeatState,
+        fractional_scale: Option<(WpFractionalScaleManagerV1, WpViewporter)>,
+        idle_notifier: Option<ExtIdleNotifierV1>,
+        egl_context: EglContext,
+        image_loader: ImageLoader,
+        presentation: Option<WpPresentation>,
     ) -> Self {
         Self {
             config,
+            config_path,
+            config_watcher,
+            runtime,
+            qh,
+            loop_handle,
             registry_state,
             output_state,
             compositor_state,
             layer_shell,
             shm,
-            egl_display,
+            seat_state,
+            fractional_scale,
+            idle_notifier,
+            idle_notifications: HashMap::new(),
+            egl_context: Rc::new(egl_context),
+            gl_resources: Rc::new(GlResources::new()),
+            image_loader,
+            presentation,
             surfaces: HashMap::new(),
+            disconnected: Vec::new(),
+            wallpaper_overrides: HashMap::new(),
+            active_workspaces: HashMap::new(),
+            focused_monitor: None,
+            schedule_checked: None,
             exit: false,
         }
     }
 
+    /// Surface of the output with this name
+    fn surface(&self, name: &str) -> Option<&WallpaperSurface> {
+        self.surfaces.values().find(|surface| surface.output_name() == name)
+    }
+
+    fn surface_mut(&mut self, name: &str) -> Option<&mut WallpaperSurface> {
+        self.surfaces.values_mut().find(|surface| surface.output_name() == name)
+    }
+
     /// Create a wallpaper surface for an output
+    ///
+    /// The surface starts on `saved`, or on what the same monitor showed
+    /// before it was last disconnected.
     fn create_surface_for_output(
         &mut self,
         qh: &QueueHandle<Self>,
         output: &wl_output::WlOutput,
-        output_name: String,
+        info: &OutputInfo,
+        saved: Option<SavedState>,
     ) -> Result<()> {
+        let mut identity = OutputIdentity::from_info(info);
+        self.complete_identity(&mut identity);
+        let output_name = identity.display_name();
         info!("Creating wallpaper surface for output: {}", output_name);
 
+        let saved = saved.or_else(|| {
+            let index = self.disconnected.iter().rposition(|(old, _)| old.matches(&identity))?;
+            Some(self.disconnected.remove(index).1)
+        });
+
         // Create the wayland surface
         let wl_surface = self.compositor_state.create_surface(qh);
 
@@ -90,22 +211,662 @@ impl Canviz {
         wl_surface.commit();
 
         // Get config for this monitor
-        let monitor_config = self.config.get_monitor_config(&output_name);
+        let monitor_config = self.config.get_monitor_config(&identity);
+        if let Some((section, _)) = self.config.find_monitor(&identity) {
+            debug!("Output {} uses [monitors.{}]", output_name, section);
+        }
+
+        // Render at the exact scale where the compositor supports it
+        let fractional_scale = self.fractional_scale.as_ref().map(|(manager, viewporter)| {
+            (
+                manager.get_fractional_scale(&wl_surface, qh, ()),
+                viewporter.get_viewport(&wl_surface, qh, ()),
+            )
+        });
 
         // Create our wallpaper surface wrapper
-        let wallpaper_surface = WallpaperSurface::new(
+        let mut wallpaper_surface = WallpaperSurface::new(
             wl_surface,
             layer_surface,
             output.clone(),
-            output_name.clone(),
+            identity,
             monitor_config,
-            self.egl_display,
+            self.egl_context.clone(),
+            self.gl_resources.clone(),
+            fractional_scale,
+            self.image_loader.clone(),
+            self.presentation.clone(),
+            self.loop_handle.clone(),
         )?;
 
-        self.surfaces.insert(output_name, wallpaper_surface);
+        if let Some(mode) = info.modes.iter().find(|mode| mode.current) {
+            wallpaper_surface.set_mode(mode.dimensions);
+            wallpaper_surface.set_refresh_rate(mode.refresh_rate);
+        }
+        if let Some(saved) = saved {
+            wallpaper_surface.restore_state(saved);
+        }
+
+        self.surfaces.insert(output.clone(), wallpaper_surface);
 
         Ok(())
     }
+
+    /// GPU memory of all outputs in bytes, counting textures shared between
+    /// outputs once
+    fn gpu_memory(&self) -> u64 {
+        let mut textures = HashMap::new();
+        let mut total = 0;
+        for surface in self.surfaces.values() {
+            total += surface.surface_memory();
+            for texture in surface.textures() {
+                textures.insert(texture.id, texture.bytes());
+            }
+        }
+        total + textures.values().sum::<u64>()
+    }
+
+    /// Handle a command received over IPC
+    fn handle_ipc(&mut self, qh: &QueueHandle<Self>, command: IpcCommand) -> IpcResponse {
+        match command {
+            IpcCommand::Status => IpcResponse::Status {
+                monitors: self
+                    .surfaces
+                    .values()
+                    .map(|surface| {
+                        let (gpu_memory, cpu_memory) = surface.memory_usage();
+                        MonitorStatus {
+                            name: surface.output_name().to_string(),
+                            wallpaper: surface.current_wallpaper().cloned(),
+                            workspace: self.active_workspaces.get(surface.output_name()).copied(),
+                            slideshow_active: false,
+                            slideshow_paused: false,
+                            error: surface.last_error().map(str::to_string),
+                            gpu_memory,
+                            cpu_memory,
+                            schedule_entry: surface.schedule_entry().map(str::to_string),
+                            daylight: surface.daylight(),
+                        }
+                    })
+                    .collect(),
+                gpu_memory: self.gpu_memory(),
+            },
+            IpcCommand::Set {
+                monitor,
+                path,
+                transition_pos,
+            } => {
+                let position = match transition_pos.map(|p| p.parse::<TransitionPosition>()) {
+                    Some(Ok(position)) => Some(position),
+                    Some(Err(message)) => return IpcResponse::Error { message },
+                    None => None,
+                };
+
+                match self.set_wallpaper(qh, monitor.as_deref(), &path, position) {
+                    Ok(()) => {
+                        for surface in self.surfaces.values() {
+                            if monitor.as_deref().is_none_or(|m| m == surface.output_name()) {
+                                self.wallpaper_overrides.insert(surface.output().clone(), path.clone());
+                            }
+                        }
+                        IpcResponse::Ok { message: None }
+                    }
+                    Err(e) => IpcResponse::Error {
+                        message: format!("{:#}", e),
+                    },
+                }
+            }
+            IpcCommand::GetWallpaper { monitor } => {
+                let surface = match monitor {
+                    Some(ref name) => self.surface(name),
+                    None => self.surfaces.values().next(),
+                };
+                IpcResponse::Wallpaper {
+                    path: surface.and_then(|s| s.current_wallpaper().cloned()),
+                }
+            }
+            IpcCommand::Reload => match self.reload_config() {
+                Ok(()) => IpcResponse::Ok {
+                    message: Some("Configuration reloaded".to_string()),
+                },
+                Err(e) => IpcResponse::Error {
+                    message: format!("{:#}", e),
+                },
+            },
+            IpcCommand::Stats { monitor } => {
+                let mut monitors: Vec<_> = self
+                    .surfaces
+                    .values()
+                    .filter(|surface| monitor.as_deref().is_none_or(|m| m == surface.output_name()))
+                    .map(|surface| surface.stats())
+                    .collect();
+                if monitors.is_empty() {
+                    if let Some(name) = monitor {
+                        return IpcResponse::Error {
+                            message: format!("Unknown monitor: {}", name),
+                        };
+                    }
+                }
+                monitors.sort_by(|a, b| a.name.cmp(&b.name));
+                IpcResponse::Stats { monitors }
+            }
+            IpcCommand::ExplainConfig { monitor } => self.explain_config(&monitor),
+            IpcCommand::ValidateConfig { path } => {
+                let path = path.unwrap_or_else(|| self.config_path.clone());
+                let outputs: Vec<_> = self.surfaces.values().map(|s| s.identity().clone()).collect();
+                let diagnostics = config::check_config(&path, Some(&outputs));
+                IpcResponse::ConfigCheck { path, diagnostics }
+            }
+            IpcCommand::SetProfile { name } => match self.set_profile(name.clone()) {
+                Ok(()) => IpcResponse::Ok {
+                    message: Some(match name {
+                        Some(name) => format!("Switched to profile {}", name),
+                        None => "Switched to no profile".to_string(),
+                    }),
+                },
+                Err(e) => IpcResponse::Error {
+                    message: format!("{:#}", e),
+                },
+            },
+            IpcCommand::ListProfiles => IpcResponse::Profiles {
+                active: self.config.active_profile.clone(),
+                profiles: self.config.profile_names(),
+            },
+            IpcCommand::Next { .. }
+            | IpcCommand::Previous { .. }
+            | IpcCommand::Pause { .. }
+            | IpcCommand::Resume { .. } => IpcResponse::Error {
+                message: "No slideshow is running".to_string(),
+            },
+        }
+    }
+
+    /// Effective config of a monitor with the layer of every value, the
+    /// runtime override on top
+    fn explain_config(&self, monitor: &str) -> IpcResponse {
+        let Some(surface) = self.surface(monitor) else {
+            return IpcResponse::Error {
+                message: format!("Unknown monitor: {}", monitor),
+            };
+        };
+
+        let workspace = self.active_workspaces.get(monitor).copied();
+        let mut resolved = self.config.resolve(surface.identity(), workspace);
+        if let Some(path) = self.wallpaper_overrides.get(surface.output()) {
+            resolved.set_path(path.clone(), ConfigLayer::Ipc);
+        }
+
+        let values = resolved
+            .explain()
+            .into_iter()
+            .map(|(key, value, source)| ConfigValue {
+                key: key.to_string(),
+                value,
+                source: source.map(ToString::to_string),
+            })
+            .collect();
+        IpcResponse::ConfigExplain {
+            monitor: monitor.to_string(),
+            values,
+        }
+    }
+
+    /// Show a wallpaper on one monitor, or on all of them
+    ///
+    /// `position` overrides each monitor's configured transition origin.
+    fn set_wallpaper(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        monitor: Option<&str>,
+        path: &Path,
+        position: Option<TransitionPosition>,
+    ) -> Result<()> {
+        if !path.is_file() && !kde::is_package(path) {
+            return Err(eyre!("Not a file or KDE package: {:?}", path));
+        }
+
+        let targets: Vec<wl_output::WlOutput> = match monitor {
+            Some(name) => match self.surface(name) {
+                Some(surface) => vec![surface.output().clone()],
+                None => return Err(eyre!("Unknown monitor: {}", name)),
+            },
+            None => self.surfaces.keys().cloned().collect(),
+        };
+
+        // Sample the pointer once so every output starts from the same spot
+        let wants_cursor = targets.iter().any(|output| {
+            position.unwrap_or_else(|| self.surfaces[output].transition_position())
+                == TransitionPosition::Cursor
+        });
+        let cursor = if wants_cursor { self.query_cursor() } else { None };
+
+        for output in targets {
+            let Some(surface) = self.surfaces.get_mut(&output) else {
+                continue;
+            };
+            let name = surface.output_name().to_string();
+
+            let origin = match position.unwrap_or_else(|| surface.transition_position()) {
+                TransitionPosition::Fixed { x, y } => Some([x, y]),
+                TransitionPosition::Cursor => cursor.as_ref().and_then(|(pos, monitors)| {
+                    monitors
+                        .iter()
+                        .find(|m| m.name == name)
+                        .map(|m| m.to_local(pos.x, pos.y))
+                }),
+            };
+
+            surface
+                .load_wallpaper(path, origin)
+                .wrap_err_with(|| format!("Failed to set wallpaper on {}", name))?;
+            surface.request_redraw(qh)?;
+        }
+
+        Ok(())
+    }
+
+    /// Handle a Hyprland workspace switch
+    fn handle_workspace_event(&mut self, qh: &QueueHandle<Self>, event: WorkspaceEvent) {
+        // workspacev2 events don't say which monitor they happened on, it is
+        // the focused one
+        let monitor = if event.monitor.is_empty() {
+            if self.focused_monitor.is_none() {
+                self.refresh_workspaces();
+            }
+            match self.focused_monitor.clone() {
+                Some(monitor) => monitor,
+                None => {
+                    debug!("No focused monitor known for workspace {}", event.workspace_id);
+                    return;
+                }
+            }
+        } else {
+            self.focused_monitor = Some(event.monitor.clone());
+            event.monitor
+        };
+
+        debug!("Workspace {} active on {}", event.workspace_id, monitor);
+        self.active_workspaces.insert(monitor.clone(), event.workspace_id);
+        self.apply_workspace_parallax(qh, &monitor);
+        self.apply_workspace_wallpaper(qh, &monitor);
+    }
+
+    /// Scroll a parallax wallpaper to the active workspace of a monitor
+    fn apply_workspace_parallax(&mut self, qh: &QueueHandle<Self>, monitor: &str) {
+        let Some(&workspace) = self.active_workspaces.get(monitor) else {
+            return;
+        };
+        let Some(surface) = self.surface_mut(monitor) else {
+            return;
+        };
+
+        if let Err(e) = surface.set_workspace(workspace, qh) {
+            error!("Failed to scroll wallpaper on {}: {}", monitor, e);
+        }
+    }
+
+    /// Wallpaper file the active workspace of a monitor sets for itself
+    fn workspace_wallpaper(&self, monitor: &str) -> Option<PathBuf> {
+        if !self.config.workspaces.enabled {
+            return None;
+        }
+        let workspace = *self.active_workspaces.get(monitor)?;
+        let surface = self.surface(monitor)?;
+        // Directories (slideshows) fall back to what the monitor config picked
+        self.config
+            .get_wallpaper_for_workspace(surface.identity(), workspace)
+            .filter(|path| path.is_file() || kde::is_package(path))
+    }
+
+    /// Show the wallpaper configured for the active workspace of a monitor
+    fn apply_workspace_wallpaper(&mut self, qh: &QueueHandle<Self>, monitor: &str) {
+        if !self.config.workspaces.enabled || !self.active_workspaces.contains_key(monitor) {
+            return;
+        }
+        let Some(surface) = self.surface(monitor) else {
+            return;
+        };
+
+        let path = match self.workspace_wallpaper(monitor) {
+            Some(path) => path,
+            None => match surface.config_wallpaper() {
+                Some(path) => path.clone(),
+                None => return,
+            },
+        };
+
+        if surface.current_wallpaper() == Some(&path) {
+            return;
+        }
+
+        let output = surface.output().clone();
+        match self.set_wallpaper(qh, Some(monitor), &path, None) {
+            Ok(()) => {
+                self.wallpaper_overrides.remove(&output);
+            }
+            Err(e) => error!("Failed to apply workspace wallpaper on {}: {:#}", monitor, e),
+        }
+    }
+
+    /// Ask Hyprland for the active workspace of every monitor
+    fn refresh_workspaces(&mut self) {
+        if !hyprland::is_hyprland() {
+            return;
+        }
+
+        let query = tokio::time::timeout(HYPRLAND_QUERY_TIMEOUT, HyprlandClient::get_monitors());
+        match self.runtime.block_on(query) {
+            Ok(Ok(monitors)) => {
+                for monitor in monitors {
+                    if monitor.focused {
+                        self.focused_monitor = Some(monitor.name.clone());
+                    }
+                    self.active_workspaces
+                        .insert(monitor.name, monitor.active_workspace.id);
+                }
+            }
+            Ok(Err(e)) => warn!("Failed to query Hyprland monitors: {}", e),
+            Err(_) => warn!("Timed out querying Hyprland monitors"),
+        }
+    }
+
+    /// Ask Hyprland for the serial number of an output, which wl_output
+    /// doesn't report, and its description if wl_output didn't either
+    fn complete_identity(&self, identity: &mut OutputIdentity) {
+        let Some(name) = identity.name.as_deref().filter(|_| hyprland::is_hyprland()) else {
+            return;
+        };
+
+        let query = tokio::time::timeout(HYPRLAND_QUERY_TIMEOUT, HyprlandClient::get_monitors());
+        let monitor = match self.runtime.block_on(query) {
+            Ok(Ok(monitors)) => monitors.into_iter().find(|monitor| monitor.name == name),
+            Ok(Err(e)) => {
+                warn!("Failed to query Hyprland monitors: {}", e);
+                None
+            }
+            Err(_) => {
+                warn!("Timed out querying Hyprland monitors");
+                None
+            }
+        };
+        let Some(monitor) = monitor else {
+            return;
+        };
+
+        if !monitor.serial.is_empty() {
+            identity.serial = Some(monitor.serial);
+        }
+        if identity.description.is_none() && !monitor.description.is_empty() {
+            identity.description = Some(monitor.description);
+        }
+    }
+
+    /// Ask Hyprland for the pointer position and the monitor layout
+    fn query_cursor(&self) -> Option<(CursorPosition, Vec<HyprlandMonitor>)> {
+        if !hyprland::is_hyprland() {
+            debug!("Cursor transition origin needs Hyprland, using the default");
+            return None;
+        }
+
+        let query = async {
+            let cursor = HyprlandClient::get_cursor_pos().await?;
+            let monitors = HyprlandClient::get_monitors().await?;
+            Ok::<_, color_eyre::Report>((cursor, monitors))
+        };
+
+        match self
+            .runtime
+            .block_on(async { tokio::time::timeout(HYPRLAND_QUERY_TIMEOUT, query).await })
+        {
+            Ok(Ok(result)) => Some(result),
+            Ok(Err(e)) => {
+                warn!("Failed to query cursor position: {}", e);
+                None
+            }
+            Err(_) => {
+                warn!("Timed out querying cursor position");
+                None
+            }
+        }
+    }
+
+    /// Move every monitor with a schedule to the entry for the current time,
+    /// returning how long until the next check
+    fn check_schedules(&mut self) -> Duration {
+        let wall_clock = SystemTime::now();
+        let monotonic = Instant::now();
+        // The monotonic clock stands still during suspend, the wall clock
+        // doesn't; a gap between them means a resume or a clock change.
+        // Entries missed in the meantime aren't worth a transition then.
+        let jumped = self.schedule_checked.is_some_and(|(last_wall_clock, last_monotonic)| {
+            let wall_elapsed = wall_clock.duration_since(last_wall_clock).unwrap_or_default();
+            wall_elapsed.abs_diff(monotonic - last_monotonic) > SCHEDULE_CHECK_INTERVAL
+        });
+        if jumped {
+            info!("Wall clock jumped (resume or clock change), skipping schedule transitions");
+        }
+        self.schedule_checked = Some((wall_clock, monotonic));
+
+        let qh = self.qh.clone();
+        let changed: Vec<_> = self
+            .surfaces
+            .values_mut()
+            .filter_map(|surface| {
+                let changed = surface.update_schedule(wall_clock);
+                changed.then(|| surface.output_name().to_string())
+            })
+            .collect();
+        for name in changed {
+            // A workspace with its own wallpaper keeps it, the schedule shows
+            // once the workspace falls back to the monitor's
+            let covered = self.workspace_wallpaper(&name).is_some();
+            let Some(surface) = self.surface_mut(&name) else {
+                continue;
+            };
+            match surface.schedule_entry() {
+                Some(entry) => info!("Schedule entry {:?} starts on {}", entry, name),
+                None => info!("Slideshow moves on to its next image on {}", name),
+            }
+            if covered {
+                continue;
+            }
+            let output = surface.output().clone();
+            if jumped {
+                surface.skip_transition();
+            }
+            surface.load_config_wallpaper();
+            if let Err(e) = surface.request_redraw(&qh) {
+                error!("Failed to redraw {}: {}", name, e);
+            }
+            self.wallpaper_overrides.remove(&output);
+        }
+
+        // Timers don't count time spent suspended, so never sleep long
+        self.surfaces
+            .values()
+            .filter_map(|surface| surface.until_schedule_change(wall_clock))
+            .fold(SCHEDULE_CHECK_INTERVAL, Duration::min)
+            .max(Duration::from_secs(1))
+    }
+
+    /// Move the day/night blend of every monitor with `daylight` to the
+    /// current height of the sun
+    fn update_daylight(&mut self) {
+        let now = SystemTime::now();
+        let qh = self.qh.clone();
+        for surface in self.surfaces.values_mut() {
+            if surface.update_daylight(now) {
+                if let Err(e) = surface.request_redraw(&qh) {
+                    error!("Failed to redraw {}: {}", surface.output_name(), e);
+                }
+            }
+        }
+    }
+
+    /// Re-read the config file and apply it to every surface
+    fn reload_config(&mut self) -> Result<()> {
+        info!("Reloading config from: {:?}", self.config_path);
+        let config = Config::load(&self.config_path)?;
+        if let Some(watcher) = &mut self.config_watcher {
+            watcher.update(&self.config_path, &config.sources);
+        }
+
+        self.apply_config(restore_profile(config));
+        Ok(())
+    }
+
+    /// Switch to a profile, or to none, and remember it across restarts
+    fn set_profile(&mut self, name: Option<String>) -> Result<()> {
+        let config = self.config.with_profile(name.as_deref())?;
+        info!("Switching to profile {}", name.as_deref().unwrap_or("(none)"));
+        let saved = state::SavedProfile {
+            profile: name,
+            selected: self.config.profile.clone(),
+        };
+        if let Err(e) = state::save_profile(&saved) {
+            warn!("Failed to save the active profile: {}", e);
+        }
+        self.apply_config(config);
+        Ok(())
+    }
+
+    /// Replace the config, moving every surface to its new settings with a
+    /// transition where the wallpaper changes
+    fn apply_config(&mut self, config: Config) {
+        let idle_timeout = self.config.default.idle_timeout;
+        self.config = config;
+
+        let qh = self.qh.clone();
+        for surface in self.surfaces.values_mut() {
+            let config = self.config.get_monitor_config(surface.identity());
+            if config.path != surface.config().path {
+                self.wallpaper_overrides.remove(surface.output());
+            }
+            surface.apply_config(config);
+            // Start any newly enabled pan and zoom
+            if let Err(e) = surface.request_redraw(&qh) {
+                error!("Failed to redraw {}: {}", surface.output_name(), e);
+            }
+        }
+
+        let names: Vec<_> = self.surfaces.values().map(|s| s.output_name().to_string()).collect();
+        for name in names {
+            self.apply_workspace_parallax(&qh, &name);
+            self.apply_workspace_wallpaper(&qh, &name);
+        }
+
+        if self.config.default.idle_timeout != idle_timeout {
+            let seats: Vec<_> = self.idle_notifications.keys().cloned().collect();
+            for seat in seats {
+                self.watch_idle(&qh, seat);
+            }
+        }
+    }
+
+    /// Carry shared texture uploads along between events. Outputs waiting
+    /// for a texture would otherwise depend on the frames of the outputs
+    /// that draw, and a powered off or hidden one gets none. Returns
+    /// whether uploads are left.
+    fn upload_textures(&mut self) -> bool {
+        if !self.gl_resources.has_uploads() {
+            return false;
+        }
+        if !self.surfaces.values().any(WallpaperSurface::make_current) {
+            return false;
+        }
+        self.gl_resources.upload_step();
+
+        // Outputs whose texture is complete start their transition
+        let qh = self.qh.clone();
+        for surface in self.surfaces.values_mut().filter(|surface| surface.is_uploading()) {
+            if let Err(e) = surface.request_redraw(&qh) {
+                error!("Failed to draw surface {}: {}", surface.output_name(), e);
+            }
+        }
+        self.gl_resources.has_uploads()
+    }
+
+    /// Hand outputs showing a still frame over to shared memory
+    fn release_static_surfaces(&mut self) {
+        if !self.config.default.release_gpu {
+            return;
+        }
+
+        for surface in self.surfaces.values_mut() {
+            if surface.can_release_gpu() {
+                if let Err(e) = surface.release_gpu(&self.shm) {
+                    error!("Failed to release GPU resources of {}: {:#}", surface.output_name(), e);
+                }
+            }
+        }
+    }
+
+    /// Hand a decoded wallpaper to the outputs waiting for it
+    fn handle_decoded_image(&mut self, decoded: DecodedImage) {
+        self.image_loader.finished(&decoded);
+        let qh = self.qh.clone();
+        for surface in self.surfaces.values_mut() {
+            if let Err(e) = surface.image_decoded(&decoded, &qh) {
+                error!("Failed to show {:?} on {}: {:#}", decoded.path, surface.output_name(), e);
+            }
+        }
+    }
+
+    /// Rebuild what EGL reported lost during the last dispatch, and draw the
+    /// outputs that fell back to software
+    fn recover_gpu(&mut self) {
+        let losses: Vec<_> = self.surfaces.values().filter_map(|s| s.gpu_loss()).collect();
+        if losses.contains(&GpuLoss::Context) {
+            warn!("EGL context lost, recreating it for all outputs");
+            // Everything in the old context goes before the new one is
+            // made current, GL names are only unique per context
+            match EglContext::new(self.egl_context.display()) {
+                Ok(context) => {
+                    self.egl_context = Rc::new(context);
+                    self.gl_resources = Rc::new(GlResources::new());
+                    for surface in self.surfaces.values_mut() {
+                        surface.discard_gpu(self.egl_context.clone(), self.gl_resources.clone());
+                    }
+                    for surface in self.surfaces.values_mut() {
+                        surface.recover_gpu(&self.qh);
+                    }
+                }
+                Err(e) => {
+                    for surface in self.surfaces.values_mut() {
+                        surface.use_software(&e);
+                    }
+                }
+            }
+        } else if !losses.is_empty() {
+            for surface in self.surfaces.values_mut() {
+                if surface.gpu_loss().is_some() {
+                    surface.recover_gpu(&self.qh);
+                }
+            }
+        }
+
+        for surface in self.surfaces.values_mut() {
+            if surface.needs_software_frame() {
+                if let Err(e) = surface.draw_software(&self.shm) {
+                    error!("Failed to draw {} in software: {:#}", surface.output_name(), e);
+                }
+            }
+        }
+    }
+
+    /// (Re)create the idle notification for a seat with the configured timeout
+    fn watch_idle(&mut self, qh: &QueueHandle<Self>, seat: wl_seat::WlSeat) {
+        let Some(notifier) = &self.idle_notifier else {
+            return;
+        };
+
+        let timeout = self.config.default.idle_timeout.as_millis().min(u32::MAX as u128) as u32;
+        let notification = notifier.get_idle_notification(timeout, &seat, qh, ());
+        if let Some(old) = self.idle_notifications.insert(seat, notification) {
+            old.destroy();
+        }
+        debug!("Watching for {}ms of inactivity", timeout);
+    }
 }
 
 impl CompositorHandler for Canviz {
@@ -117,9 +878,9 @@ impl CompositorHandler for Canviz {
         new_factor: i32,
     ) {
         // Find and update the surface that matches
-        for (name, wallpaper_surface) in &mut self.surfaces {
+        for wallpaper_surface in self.surfaces.values_mut() {
             if wallpaper_surface.wl_surface() == surface {
-                debug!("Scale factor changed for {}: {}", name, new_factor);
+                debug!("Scale factor changed for {}: {}", wallpaper_surface.output_name(), new_factor);
                 if let Err(e) = wallpaper_surface.set_scale_factor(new_factor, qh) {
                     error!("Failed to update scale factor: {}", e);
                 }
@@ -131,11 +892,18 @@ impl CompositorHandler for Canviz {
     fn transform_changed(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _new_transform: wl_output::Transform,
+        qh: &QueueHandle<Self>,
+        surface: &wl_surface::WlSurface,
+        new_transform: wl_output::Transform,
     ) {
-        // Handle transform changes if needed
+        for wallpaper_surface in self.surfaces.values_mut() {
+            if wallpaper_surface.wl_surface() == surface {
+                if let Err(e) = wallpaper_surface.set_transform(new_transform, qh) {
+                    error!("Failed to update transform of {}: {}", wallpaper_surface.output_name(), e);
+                }
+                break;
+            }
+        }
     }
 
     fn frame(
@@ -146,10 +914,10 @@ impl CompositorHandler for Canviz {
         _time: u32,
     ) {
         // Find the surface and draw
-        for (name, wallpaper_surface) in &mut self.surfaces {
+        for wallpaper_surface in self.surfaces.values_mut() {
             if wallpaper_surface.wl_surface() == surface {
                 if let Err(e) = wallpaper_surface.draw(qh) {
-                    error!("Failed to draw surface {}: {}", name, e);
+                    error!("Failed to draw surface {}: {}", wallpaper_surface.output_name(), e);
                 }
                 break;
             }
@@ -186,29 +954,62 @@ impl OutputHandler for Canviz {
         qh: &QueueHandle<Self>,
         output: wl_output::WlOutput,
     ) {
-        // Get output info
-        let info = self.output_state.info(&output);
-        let output_name = info
-            .as_ref()
-            .and_then(|i| i.name.clone())
-            .unwrap_or_else(|| "unknown".to_string());
+        let Some(info) = self.output_state.info(&output) else {
+            warn!("New output without info, ignoring it");
+            return;
+        };
 
-        info!("New output detected: {}", output_name);
+        info!(
+            "New output detected: {} ({} {})",
+            OutputIdentity::from_info(&info).display_name(),
+            info.make,
+            info.model
+        );
 
-        if let Err(e) = self.create_surface_for_output(qh, &output, output_name.clone()) {
-            error!("Failed to create surface for {}: {}", output_name, e);
+        if let Err(e) = self.create_surface_for_output(qh, &output, &info, None) {
+            error!("Failed to create surface for {:?}: {}", info.name, e);
         }
     }
 
     fn update_output(
         &mut self,
         _conn: &Connection,
-        _qh: &QueueHandle<Self>,
+        qh: &QueueHandle<Self>,
         output: wl_output::WlOutput,
     ) {
-        let info = self.output_state.info(&output);
-        if let Some(info) = info {
-            debug!("Output updated: {:?}", info.name);
+        let Some(info) = self.output_state.info(&output) else {
+            return;
+        };
+        let Some(surface) = self.surfaces.get_mut(&output) else {
+            return;
+        };
+        debug!("Output updated: {}", surface.output_name());
+
+        // Compositors without wl_surface.preferred_buffer_transform only
+        // tell us about rotation through the output
+        if let Err(e) = surface.set_transform(info.transform, qh) {
+            error!("Failed to update transform of {}: {}", surface.output_name(), e);
+        }
+
+        let Some(mode) = info.modes.iter().find(|mode| mode.current) else {
+            return;
+        };
+        surface.set_refresh_rate(mode.refresh_rate);
+        if !surface.set_mode(mode.dimensions) {
+            return;
+        }
+
+        // Start over with a surface for the new mode, on the same wallpaper
+        info!(
+            "Mode of {} changed to {}x{}, recreating its surface",
+            surface.output_name(),
+            mode.dimensions.0,
+            mode.dimensions.1
+        );
+        let saved = surface.save_state();
+        self.surfaces.remove(&output);
+        if let Err(e) = self.create_surface_for_output(qh, &output, &info, Some(saved)) {
+            error!("Failed to recreate surface for {:?}: {}", info.name, e);
         }
     }
 
@@ -218,14 +1019,19 @@ impl OutputHandler for Canviz {
         _qh: &QueueHandle<Self>,
         output: wl_output::WlOutput,
     ) {
-        let info = self.output_state.info(&output);
-        let output_name = info
-            .as_ref()
-            .and_then(|i| i.name.clone())
-            .unwrap_or_else(|| "unknown".to_string());
+        let Some(surface) = self.surfaces.remove(&output) else {
+            return;
+        };
+        info!("Output removed: {}", surface.output_name());
+        self.wallpaper_overrides.remove(&output);
 
-        info!("Output removed: {}", output_name);
-        self.surfaces.remove(&output_name);
+        // Remember the wallpaper in case the monitor comes back
+        let identity = surface.identity().clone();
+        self.disconnected.retain(|(old, _)| !old.matches(&identity));
+        self.disconnected.push((identity, surface.save_state()));
+        if self.disconnected.len() > MAX_DISCONNECTED_OUTPUTS {
+            self.disconnected.remove(0);
+        }
     }
 }
 
@@ -244,19 +1050,30 @@ impl LayerShellHandler for Canviz {
         _serial: u32,
     ) {
         // Find the surface that matches this layer surface
-        for (name, wallpaper_surface) in &mut self.surfaces {
+        let mut first_configure = None;
+        for wallpaper_surface in self.surfaces.values_mut() {
             if wallpaper_surface.layer_surface() == layer {
                 debug!(
                     "Configure event for {}: {}x{}",
-                    name, configure.new_size.0, configure.new_size.1
+                    wallpaper_surface.output_name(), configure.new_size.0, configure.new_size.1
                 );
 
+                if !wallpaper_surface.is_configured() {
+                    first_configure = Some(wallpaper_surface.output_name().to_string());
+                }
                 if let Err(e) = wallpaper_surface.configure(configure, qh) {
-                    error!("Failed to configure surface {}: {}", name, e);
+                    error!("Failed to configure surface {}: {}", wallpaper_surface.output_name(), e);
                 }
                 break;
             }
         }
+
+        // A new output starts on the wallpaper (and scroll position) of its
+        // active workspace
+        if let Some(name) = first_configure {
+            self.apply_workspace_parallax(qh, &name);
+            self.apply_workspace_wallpaper(qh, &name);
+        }
     }
 }
 
@@ -271,7 +1088,138 @@ impl ProvidesRegistryState for Canviz {
         &mut self.registry_state
     }
 
-    registry_handlers![OutputState];
+    registry_handlers![OutputState, SeatState];
+}
+
+impl SeatHandler for Canviz {
+    fn seat_state(&mut self) -> &mut SeatState {
+        &mut self.seat_state
+    }
+
+    fn new_seat(&mut self, _conn: &Connection, qh: &QueueHandle<Self>, seat: wl_seat::WlSeat) {
+        self.watch_idle(qh, seat);
+    }
+
+    fn new_capability(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _seat: wl_seat::WlSeat,
+        _capability: Capability,
+    ) {
+    }
+
+    fn remove_capability(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _seat: wl_seat::WlSeat,
+        _capability: Capability,
+    ) {
+    }
+
+    fn remove_seat(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, seat: wl_seat::WlSeat) {
+        if let Some(notification) = self.idle_notifications.remove(&seat) {
+            notification.destroy();
+        }
+    }
+}
+
+impl Dispatch<WpFractionalScaleV1, ()> for Canviz {
+    fn event(
+        state: &mut Self,
+        proxy: &WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let wp_fractional_scale_v1::Event::PreferredScale { scale } = event else {
+            return;
+        };
+
+        for surface in state.surfaces.values_mut() {
+            if surface.fractional_scale() == Some(proxy) {
+                if let Err(e) = surface.set_preferred_scale(scale, qh) {
+                    error!("Failed to update scale of {}: {}", surface.output_name(), e);
+                }
+                break;
+            }
+        }
+    }
+}
+
+impl Dispatch<WpPresentationFeedback, FrameFeedback> for Canviz {
+    fn event(
+        state: &mut Self,
+        _proxy: &WpPresentationFeedback,
+        event: wp_presentation_feedback::Event,
+        data: &FrameFeedback,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let Some(surface) = state.surfaces.get_mut(&data.output) else {
+            return;
+        };
+        match event {
+            wp_presentation_feedback::Event::Presented {
+                tv_sec_hi,
+                tv_sec_lo,
+                tv_nsec,
+                refresh,
+                ..
+            } => {
+                let secs = ((tv_sec_hi as u64) << 32) | tv_sec_lo as u64;
+                let at = Duration::new(secs, tv_nsec);
+                surface.frame_presented(at, Duration::from_nanos(refresh as u64), data);
+            }
+            wp_presentation_feedback::Event::Discarded => surface.frame_discarded(),
+            _ => {}
+        }
+    }
+}
+
+// These have no events (or none we need)
+wayland_client::delegate_noop!(Canviz: ignore WpPresentation);
+wayland_client::delegate_noop!(Canviz: ignore WpFractionalScaleManagerV1);
+wayland_client::delegate_noop!(Canviz: ignore WpViewporter);
+wayland_client::delegate_noop!(Canviz: ignore WpViewport);
+
+impl Dispatch<ExtIdleNotifierV1, ()> for Canviz {
+    fn event(
+        _state: &mut Self,
+        _notifier: &ExtIdleNotifierV1,
+        _event: <ExtIdleNotifierV1 as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // The notifier has no events
+    }
+}
+
+impl Dispatch<ExtIdleNotificationV1, ()> for Canviz {
+    fn event(
+        state: &mut Self,
+        _notification: &ExtIdleNotificationV1,
+        event: ext_idle_notification_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let idle = match event {
+            ext_idle_notification_v1::Event::Idled => true,
+            ext_idle_notification_v1::Event::Resumed => false,
+            _ => return,
+        };
+
+        info!("User is {}", if idle { "idle, pausing animations" } else { "back" });
+        for surface in state.surfaces.values_mut() {
+            if let Err(e) = surface.set_idle(idle, qh) {
+                error!("Failed to update idle state of {}: {}", surface.output_name(), e);
+            }
+        }
+    }
 }
 
 delegate_compositor!(Canviz);
@@ -279,9 +1227,38 @@ delegate_output!(Canviz);
 delegate_layer!(Canviz);
 delegate_shm!(Canviz);
 delegate_registry!(Canviz);
+delegate_seat!(Canviz);
+
+/// A freshly loaded config with the profile last picked with `canvizctl
+/// profile`, at startup and on reload alike. The pick stays until the
+/// config's own `profile` changes, which forgets it.
+fn restore_profile(config: Config) -> Config {
+    let Some(saved) = state::saved_profile() else {
+        return config;
+    };
+    let Some(profile) = saved.for_selected(config.profile.as_deref()) else {
+        info!("Config selects another profile, forgetting the one picked before");
+        if let Err(e) = state::forget_profile() {
+            warn!("Failed to forget the saved profile: {}", e);
+        }
+        return config;
+    };
+    if profile == config.active_profile.as_deref() {
+        return config;
+    }
+
+    info!("Restoring profile {}", profile.unwrap_or("(none)"));
+    match config.with_profile(profile) {
+        Ok(with_profile) => with_profile,
+        Err(e) => {
+            warn!("Keeping the config's own profile: {}", e);
+            config
+        }
+    }
+}
 
 /// Main daemon entry point
-pub fn run(config: Config, _foreground: bool) -> Result<()> {
+pub fn run(config: Config, config_path: PathBuf, _foreground: bool) -> Result<()> {
     info!("Initializing Wayland connection");
 
     // Connect to Wayland
@@ -291,11 +1268,13 @@ pub fn run(config: Config, _foreground: bool) -> Result<()> {
     // Initialize EGL with Wayland display
     let egl_display = init_egl_display(&conn)
         .wrap_err("Failed to initialize EGL display")?;
+    let egl_context = EglContext::new(egl_display)
+        .wrap_err("Failed to create EGL context")?;
 
     info!("EGL initialized successfully");
 
     // Initialize registry
-    let (globals, mut event_queue) = registry_queue_init(&conn)
+    let (globals, event_queue) = registry_queue_init(&conn)
         .wrap_err("Failed to initialize Wayland registry")?;
     let qh = event_queue.handle();
 
@@ -308,31 +1287,175 @@ pub fn run(config: Config, _foreground: bool) -> Result<()> {
     let shm = Shm::bind(&globals, &qh)
         .wrap_err("Failed to bind shm")?;
     let registry_state = RegistryState::new(&globals);
+    let seat_state = SeatState::new(&globals, &qh);
+    let fractional_scale = globals
+        .bind::<WpFractionalScaleManagerV1, _, _>(&qh, 1..=1, ())
+        .ok()
+        .zip(globals.bind::<WpViewporter, _, _>(&qh, 1..=1, ()).ok());
+    if fractional_scale.is_none() {
+        info!("Fractional scaling unavailable, using integer buffer scale");
+    }
+    let idle_notifier = globals.bind::<ExtIdleNotifierV1, _, _>(&qh, 1..=1, ()).ok();
+    let presentation = globals.bind::<WpPresentation, _, _>(&qh, 1..=1, ()).ok();
+    if presentation.is_none() {
+        info!("Presentation time unavailable, frame stats use draw times");
+    }
+    if idle_notifier.is_none() {
+        warn!("Compositor lacks ext-idle-notify, animations won't pause when idle");
+    }
+
+    // Async runtime for the IPC server and Hyprland sockets
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .wrap_err("Failed to start async runtime")?;
+
+    let mut event_loop: EventLoop<Canviz> =
+        EventLoop::try_new().wrap_err("Failed to create event loop")?;
+    let loop_handle = event_loop.handle();
+
+    WaylandSource::new(conn, event_queue)
+        .insert(loop_handle.clone())
+        .map_err(|e| eyre!("Failed to insert Wayland source: {}", e))?;
+
+    // Forward IPC commands into the event loop
+    let (ipc_tx, ipc_rx) = channel::channel::<IpcRequest>();
+    loop_handle
+        .insert_source(ipc_rx, |event, _, canviz| {
+            if let channel::Event::Msg(request) = event {
+                let qh = canviz.qh.clone();
+                let response = canviz.handle_ipc(&qh, request.command);
+                let _ = request.reply.send(response);
+            }
+        })
+        .map_err(|e| eyre!("Failed to insert IPC source: {}", e))?;
+
+    runtime.spawn(async move {
+        match IpcServer::new().await {
+            Ok(server) => server.run(ipc_tx).await,
+            Err(e) => error!("Failed to start IPC server: {:?}", e),
+        }
+    });
+
+    // Decoded wallpapers come back into the event loop for upload
+    let (image_tx, image_rx) = channel::channel::<DecodedImage>();
+    loop_handle
+        .insert_source(image_rx, |event, _, canviz| {
+            if let channel::Event::Msg(decoded) = event {
+                canviz.handle_decoded_image(decoded);
+            }
+        })
+        .map_err(|e| eyre!("Failed to insert image source: {}", e))?;
+    let image_loader = ImageLoader::new(runtime.handle().clone(), image_tx);
+
+    // Forward Hyprland workspace switches into the event loop
+    if hyprland::is_hyprland() {
+        let (workspace_tx, workspace_rx) = channel::channel::<WorkspaceEvent>();
+        loop_handle
+            .insert_source(workspace_rx, |event, _, canviz| {
+                if let channel::Event::Msg(event) = event {
+                    let qh = canviz.qh.clone();
+                    canviz.handle_workspace_event(&qh, event);
+                }
+            })
+            .map_err(|e| eyre!("Failed to insert workspace source: {}", e))?;
+
+        runtime.spawn(async move {
+            let mut listener = match WorkspaceListener::new().await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    error!("Failed to listen for workspace events: {:?}", e);
+                    return;
+                }
+            };
+            while let Some(event) = listener.recv().await {
+                if workspace_tx.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    let config = restore_profile(config);
+
+    // Reload when the config or anything it includes is saved
+    let (config_tx, config_rx) = channel::channel::<()>();
+    loop_handle
+        .insert_source(config_rx, |event, _, canviz| {
+            if let channel::Event::Msg(()) = event {
+                if let Err(e) = canviz.reload_config() {
+                    error!("Failed to reload config, keeping the previous one: {}", e);
+                }
+            }
+        })
+        .map_err(|e| eyre!("Failed to insert config watch source: {}", e))?;
+    let config_watcher = match ConfigWatcher::new(config_tx) {
+        Ok(mut watcher) => {
+            watcher.update(&config_path, &config.sources);
+            Some(watcher)
+        }
+        Err(e) => {
+            warn!("Failed to watch the config for changes: {}", e);
+            None
+        }
+    };
 
     // Create main daemon state
     let mut canviz = Canviz::new(
         config,
+        config_path,
+        config_watcher,
+        runtime,
+        qh,
+        loop_handle.clone(),
         registry_state,
         output_state,
         compositor_state,
         layer_shell,
         shm,
-        egl_display,
+        seat_state,
+        fractional_scale,
+        idle_notifier,
+        egl_context,
+        image_loader,
+        presentation,
     );
 
-    info!("Starting event loop");
+    canviz.refresh_workspaces();
 
-    // Main event loop
-    loop {
-        if canviz.exit {
-            info!("Exit requested, shutting down");
-            break;
-        }
+    // Follow time-of-day schedules, the first check settles the clocks
+    let first_check = canviz.check_schedules();
+    loop_handle
+        .insert_source(Timer::from_duration(first_check), |_, _, canviz| {
+            TimeoutAction::ToDuration(canviz.check_schedules())
+        })
+        .map_err(|e| eyre!("Failed to insert schedule timer: {}", e))?;
 
-        event_queue
-            .blocking_dispatch(&mut canviz)
-            .wrap_err("Wayland dispatch failed")?;
+    // Blend day and night images by the sun, surfaces start out with the
+    // blend of their creation time
+    loop_handle
+        .insert_source(Timer::from_duration(DAYLIGHT_UPDATE_INTERVAL), |_, _, canviz| {
+            canviz.update_daylight();
+            TimeoutAction::ToDuration(DAYLIGHT_UPDATE_INTERVAL)
+        })
+        .map_err(|e| eyre!("Failed to insert daylight timer: {}", e))?;
+
+    info!("Starting event loop");
+
+    // Main event loop, which doesn't wait for events while textures are
+    // being uploaded
+    let mut timeout = None;
+    while !canviz.exit {
+        event_loop
+            .dispatch(timeout, &mut canviz)
+            .wrap_err("Event loop dispatch failed")?;
+        canviz.recover_gpu();
+        timeout = canviz.upload_textures().then_some(Duration::ZERO);
+        canviz.release_static_surfaces();
     }
 
+    info!("Exit requested, shutting down");
+
     Ok(())
 }
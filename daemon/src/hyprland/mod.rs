@@ -2,7 +2,7 @@ use color_eyre::eyre::{eyre, Result, WrapErr};
 use log::{debug, error, info, warn};
 use serde::Deserialize;
 use std::path::PathBuf;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::UnixStream;
 use tokio::sync::mpsc;
 
@@ -20,11 +20,39 @@ pub struct HyprlandMonitor {
     pub id: i32,
     pub name: String,
     pub description: String,
+    #[serde(default)]
+    pub make: String,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub serial: String,
     #[serde(rename = "activeWorkspace")]
     pub active_workspace: HyprlandWorkspace,
     pub width: i32,
     pub height: i32,
+    pub x: i32,
+    pub y: i32,
     pub scale: f64,
+    #[serde(default)]
+    pub focused: bool,
+}
+
+impl HyprlandMonitor {
+    /// Convert a position in the global layout into fractions of this
+    /// monitor measured from its top-left corner. Positions outside the
+    /// monitor are clamped to its nearest edge.
+    pub fn to_local(&self, x: f64, y: f64) -> [f32; 2] {
+        let logical_width = (self.width as f64 / self.scale).max(1.0);
+        let logical_height = (self.height as f64 / self.scale).max(1.0);
+
+        let local_x = (x - self.x as f64) / logical_width;
+        let local_y = (y - self.y as f64) / logical_height;
+
+        [
+            local_x.clamp(0.0, 1.0) as f32,
+            local_y.clamp(0.0, 1.0) as f32,
+        ]
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -33,6 +61,13 @@ pub struct HyprlandWorkspace {
     pub name: String,
 }
 
+/// Pointer position in global layout coordinates
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct CursorPosition {
+    pub x: f64,
+    pub y: f64,
+}
+
 /// Get Hyprland IPC socket paths
 fn get_hyprland_socket_paths() -> Result<(PathBuf, PathBuf)> {
     let instance = std::env::var("HYPRLAND_INSTANCE_SIGNATURE")
@@ -51,20 +86,28 @@ fn get_hyprland_socket_paths() -> Result<(PathBuf, PathBuf)> {
 pub struct HyprlandClient;
 
 impl HyprlandClient {
-    /// Get list of monitors from Hyprland
-    pub async fn get_monitors() -> Result<Vec<HyprlandMonitor>> {
+    /// Send a command on the request socket and read the full reply
+    async fn request(command: &str) -> Result<String> {
         let (socket1, _) = get_hyprland_socket_paths()?;
 
         let mut stream = UnixStream::connect(&socket1).await
             .wrap_err("Failed to connect to Hyprland socket")?;
 
-        stream.write_all(b"j/monitors").await
-            .wrap_err("Failed to send monitors command")?;
+        stream.write_all(command.as_bytes()).await
+            .wrap_err_with(|| format!("Failed to send {} command", command))?;
 
+        // Hyprland closes the connection after replying, and JSON replies
+        // span multiple lines
         let mut response = String::new();
-        let mut reader = BufReader::new(stream);
-        reader.read_line(&mut response).await
-            .wrap_err("Failed to read monitors response")?;
+        stream.read_to_string(&mut response).await
+            .wrap_err_with(|| format!("Failed to read {} response", command))?;
+
+        Ok(response)
+    }
+
+    /// Get list of monitors from Hyprland
+    pub async fn get_monitors() -> Result<Vec<HyprlandMonitor>> {
+        let response = Self::request("j/monitors").await?;
 
         let monitors: Vec<HyprlandMonitor> = serde_json::from_str(&response)
             .wrap_err("Failed to parse monitors response")?;
@@ -72,6 +115,16 @@ impl HyprlandClient {
         Ok(monitors)
     }
 
+    /// Get the pointer position in global layout coordinates
+    pub async fn get_cursor_pos() -> Result<CursorPosition> {
+        let response = Self::request("j/cursorpos").await?;
+
+        let position: CursorPosition = serde_json::from_str(&response)
+            .wrap_err("Failed to parse cursorpos response")?;
+
+        Ok(position)
+    }
+
     /// Get active workspace for a monitor
     pub async fn get_active_workspace(monitor: &str) -> Result<i32> {
         let monitors = Self::get_monitors().await?;
@@ -178,3 +231,39 @@ impl WorkspaceListener {
 pub fn is_hyprland() -> bool {
     std::env::var("HYPRLAND_INSTANCE_SIGNATURE").is_ok()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(x: i32, y: i32, width: i32, height: i32, scale: f64) -> HyprlandMonitor {
+        HyprlandMonitor {
+            id: 0,
+            name: "DP-1".to_string(),
+            description: String::new(),
+            make: String::new(),
+            model: String::new(),
+            serial: String::new(),
+            active_workspace: HyprlandWorkspace {
+                id: 1,
+                name: "1".to_string(),
+            },
+            width,
+            height,
+            x,
+            y,
+            scale,
+            focused: false,
+        }
+    }
+
+    #[test]
+    fn test_cursor_to_local() {
+        // Second monitor to the right of a 1920 wide one, scaled 2x
+        let mon = monitor(1920, 0, 3840, 2160, 2.0);
+        assert_eq!(mon.to_local(1920.0 + 480.0, 270.0), [0.25, 0.25]);
+
+        // Pointer on another monitor clamps to the nearest edge
+        assert_eq!(mon.to_local(100.0, 540.0), [0.0, 0.5]);
+    }
+}
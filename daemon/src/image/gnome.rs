@@ -0,0 +1,346 @@
+use super::kde;
+use crate::config::TransitionType;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, XmlVersion};
+use serde::de::value::{Error as ValueError, StrDeserializer};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Whether `path` is a GNOME slideshow, going by its `.xml` extension
+pub fn is_timeline(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("xml"))
+        && path.is_file()
+}
+
+/// One entry of a GNOME slideshow
+#[derive(Debug, Clone, PartialEq)]
+enum Entry {
+    /// An image shown as is, possibly in several sizes
+    Static {
+        duration: f64,
+        files: Vec<(PathBuf, Option<(u32, u32)>)>,
+    },
+    /// A change from one image to the next over the whole entry
+    Transition {
+        duration: f64,
+        kind: TransitionType,
+        to: PathBuf,
+    },
+}
+
+impl Entry {
+    fn duration(&self) -> f64 {
+        match self {
+            Entry::Static { duration, .. } | Entry::Transition { duration, .. } => *duration,
+        }
+    }
+}
+
+/// An open element while parsing, with the attributes entries need
+struct Element {
+    name: String,
+    /// `type` of a `<transition>`
+    kind: Option<TransitionType>,
+    /// `width` and `height` of a `<size>`
+    size: Option<(u32, u32)>,
+}
+
+/// What a slideshow shows at some point of its loop
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub path: PathBuf,
+    /// Effect and remaining time of the change to `path`, while the
+    /// slideshow is in a transition
+    pub transition: Option<(TransitionType, Duration)>,
+}
+
+/// GNOME timed slideshow, a `<background>` XML file whose `<static>` and
+/// `<transition>` entries play in a loop from `<starttime>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timeline {
+    /// Start of the first loop in seconds since the Unix epoch
+    start: f64,
+    entries: Vec<Entry>,
+    /// Length of one loop in seconds
+    total: f64,
+}
+
+impl Timeline {
+    /// Read a slideshow file, its relative paths start from its directory
+    pub fn load(path: &Path) -> Result<Self> {
+        let xml = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read slideshow {:?}", path))?;
+        Self::parse(&xml, path.parent().unwrap_or(Path::new("")))
+            .wrap_err_with(|| format!("Invalid slideshow {:?}", path))
+    }
+
+    fn parse(xml: &str, dir: &Path) -> Result<Self> {
+        let mut reader = Reader::from_str(xml);
+
+        let mut stack: Vec<Element> = Vec::new();
+        let mut text = String::new();
+        let mut start = [2000, 1, 1, 0, 0, 0];
+        let mut duration = None;
+        let mut files = Vec::new();
+        let mut to = None;
+        let mut entries = Vec::new();
+
+        loop {
+            match reader.read_event()? {
+                Event::Start(element) => {
+                    let name = String::from_utf8_lossy(element.local_name().as_ref()).into_owned();
+                    let kind = (name == "transition")
+                        .then(|| transition_type(&element))
+                        .transpose()?;
+                    let size = (name == "size").then(|| size(&element)).flatten();
+                    stack.push(Element { name, kind, size });
+                    text.clear();
+                }
+                Event::Text(content) => text.push_str(&content.decode()?),
+                Event::CData(content) => text.push_str(&content.decode()?),
+                Event::GeneralRef(reference) => {
+                    let entity = format!("&{};", String::from_utf8_lossy(&reference));
+                    text.push_str(&quick_xml::escape::unescape(&entity)?);
+                }
+                Event::End(_) => {
+                    let Some(Element { name, kind, size }) = stack.pop() else {
+                        continue;
+                    };
+                    let parent = stack.last().map(|element| element.name.as_str());
+                    // Entities split the text, so it's only trimmed as a whole
+                    let value = std::mem::take(&mut text).trim().to_string();
+                    match (parent, name.as_str()) {
+                        (Some("starttime"), field) => {
+                            let index = ["year", "month", "day", "hour", "minute", "second"]
+                                .iter()
+                                .position(|f| *f == field);
+                            if let Some(index) = index {
+                                start[index] = value.parse().map_err(|_| {
+                                    eyre!("invalid {} `{}` in starttime", field, value)
+                                })?;
+                            }
+                        }
+                        (Some("static" | "transition"), "duration") => {
+                            let seconds: f64 = value
+                                .parse()
+                                .map_err(|_| eyre!("invalid duration `{}`", value))?;
+                            duration = Some(seconds).filter(|s| s.is_finite() && *s >= 0.0);
+                        }
+                        (Some("static"), "file") if !value.is_empty() => {
+                            files.push((dir.join(&value), None))
+                        }
+                        (Some("file"), "size") => files.push((dir.join(&value), size)),
+                        (Some("transition"), "to") => to = Some(dir.join(&value)),
+                        (Some("background"), "static") => {
+                            let duration = duration
+                                .take()
+                                .ok_or_else(|| eyre!("static entry without a duration"))?;
+                            if files.is_empty() {
+                                return Err(eyre!("static entry without a file"));
+                            }
+                            entries.push(Entry::Static {
+                                duration,
+                                files: std::mem::take(&mut files),
+                            });
+                        }
+                        (Some("background"), "transition") => {
+                            let duration = duration
+                                .take()
+                                .ok_or_else(|| eyre!("transition without a duration"))?;
+                            let to = to
+                                .take()
+                                .ok_or_else(|| eyre!("transition without a `to` file"))?;
+                            entries.push(Entry::Transition {
+                                duration,
+                                kind: kind.unwrap_or(TransitionType::Fade),
+                                to,
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        let total: f64 = entries.iter().map(Entry::duration).sum();
+        if total <= 0.0 {
+            return Err(eyre!("no entries with a duration"));
+        }
+        Ok(Self {
+            start: local_timestamp(start)?,
+            entries,
+            total,
+        })
+    }
+
+    /// Where in the loop `now` falls: the entry and the seconds left of it
+    fn position(&self, now: SystemTime) -> (&Entry, f64) {
+        let now = match now.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs_f64(),
+            Err(e) => -e.duration().as_secs_f64(),
+        };
+        let mut offset = (now - self.start).rem_euclid(self.total);
+        for entry in &self.entries {
+            if offset < entry.duration() {
+                return (entry, entry.duration() - offset);
+            }
+            offset -= entry.duration();
+        }
+        // Rounding at the very end of the loop
+        (&self.entries[0], self.entries[0].duration())
+    }
+
+    /// The image to show at `now` on an output of `output` pixels
+    pub fn frame(&self, now: SystemTime, output: (u32, u32)) -> Frame {
+        match self.position(now) {
+            (Entry::Static { files, .. }, _) => {
+                let sized: Vec<_> = files
+                    .iter()
+                    .filter_map(|(path, size)| Some((path.clone(), (*size)?)))
+                    .collect();
+                let path = kde::best_variant(&sized, output)
+                    .map(Path::to_path_buf)
+                    .unwrap_or_else(|| files[0].0.clone());
+                Frame {
+                    path,
+                    transition: None,
+                }
+            }
+            (Entry::Transition { kind, to, .. }, left) => Frame {
+                path: to.clone(),
+                transition: Some((*kind, Duration::from_secs_f64(left))),
+            },
+        }
+    }
+
+    /// Time until the next entry starts
+    pub fn until_next_change(&self, now: SystemTime) -> Duration {
+        // At least a millisecond, so a timer never spins on the boundary
+        Duration::from_secs_f64(self.position(now).1).max(Duration::from_millis(1))
+    }
+}
+
+/// The effect of a `<transition type="...">`. GNOME only knows `overlay`,
+/// a crossfade, but other packs use the names of Canviz's effects.
+fn transition_type(element: &BytesStart) -> Result<TransitionType> {
+    let Some(attribute) = element.try_get_attribute("type")? else {
+        return Ok(TransitionType::Fade);
+    };
+    let name = attribute.normalized_value(XmlVersion::Implicit1_0)?;
+    Ok(match name.as_ref() {
+        "overlay" => TransitionType::Fade,
+        name => TransitionType::deserialize(StrDeserializer::<ValueError>::new(name))
+            .unwrap_or(TransitionType::Fade),
+    })
+}
+
+/// `width` and `height` of a `<size>` element
+fn size(element: &BytesStart) -> Option<(u32, u32)> {
+    let attribute = |name| -> Option<u32> {
+        let attribute = element.try_get_attribute(name).ok()??;
+        attribute
+            .normalized_value(XmlVersion::Implicit1_0)
+            .ok()?
+            .parse()
+            .ok()
+    };
+    Some((attribute("width")?, attribute("height")?))
+}
+
+/// Seconds since the Unix epoch of a local date and time
+fn local_timestamp([year, month, day, hour, minute, second]: [i32; 6]) -> Result<f64> {
+    // SAFETY: mktime only reads and normalizes the tm it's given
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_year = year - 1900;
+    tm.tm_mon = month - 1;
+    tm.tm_mday = day;
+    tm.tm_hour = hour;
+    tm.tm_min = minute;
+    tm.tm_sec = second;
+    // Let the time zone rules decide about daylight saving time
+    tm.tm_isdst = -1;
+    match unsafe { libc::mktime(&mut tm) } {
+        -1 => Err(eyre!("invalid starttime")),
+        time => Ok(time as f64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SLIDESHOW: &str = r#"<?xml version="1.0"?>
+<background>
+  <starttime>
+    <year>2024</year><month>1</month><day>1</day>
+    <hour>0</hour><minute>0</minute><second>0</second>
+  </starttime>
+  <static>
+    <duration>60.0</duration>
+    <file>
+      <size width="1920" height="1080">day-1080.jpg</size>
+      <size width="3840" height="2160">day-2160.jpg</size>
+    </file>
+  </static>
+  <transition type="overlay">
+    <duration>10.0</duration>
+    <from>day-1080.jpg</from>
+    <to>/walls/night &amp; stars.jpg</to>
+  </transition>
+  <static>
+    <duration>50.0</duration>
+    <file>/walls/night &amp; stars.jpg</file>
+  </static>
+  <transition type="wipe">
+    <duration>0</duration>
+    <from>/walls/night &amp; stars.jpg</from>
+    <to>day-1080.jpg</to>
+  </transition>
+</background>"#;
+
+    #[test]
+    fn test_timeline() {
+        let timeline = Timeline::parse(SLIDESHOW, Path::new("/pack")).unwrap();
+        assert_eq!(timeline.total, 120.0);
+        let start = UNIX_EPOCH + Duration::from_secs_f64(timeline.start);
+        let at = |seconds| start + Duration::from_secs(seconds);
+
+        let frame = timeline.frame(at(30), (2560, 1440));
+        assert_eq!(frame.path, PathBuf::from("/pack/day-2160.jpg"));
+        assert_eq!(frame.transition, None);
+        assert_eq!(timeline.until_next_change(at(30)), Duration::from_secs(30));
+
+        let frame = timeline.frame(at(64), (1920, 1080));
+        assert_eq!(frame.path, PathBuf::from("/walls/night & stars.jpg"));
+        assert_eq!(
+            frame.transition,
+            Some((TransitionType::Fade, Duration::from_secs(6)))
+        );
+
+        // The loop starts over, also a few loops later
+        let frame = timeline.frame(at(3 * 120 + 5), (1920, 1080));
+        assert_eq!(frame.path, PathBuf::from("/pack/day-1080.jpg"));
+    }
+
+    #[test]
+    fn test_invalid_timelines() {
+        let dir = Path::new("/pack");
+        assert!(Timeline::parse("<background></background>", dir).is_err());
+        let without_duration = "<background><static><file>a.jpg</file></static></background>";
+        assert!(Timeline::parse(without_duration, dir).is_err());
+        assert!(Timeline::parse("<background><static><duration>5</duration>", dir).is_err());
+
+        let transition =
+            "<transition type=\"slide_up\"><duration>5</duration><to>b.jpg</to></transition>";
+        let timeline =
+            Timeline::parse(&format!("<background>{}</background>", transition), dir).unwrap();
+        let frame = timeline.frame(UNIX_EPOCH + Duration::from_secs_f64(timeline.start), (0, 0));
+        assert_eq!(frame.transition.unwrap().0, TransitionType::SlideUp);
+    }
+}
@@ -0,0 +1,125 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Whether `path` is a KDE wallpaper package: a directory with
+/// `metadata.json` (or the older `metadata.desktop`) and the image in
+/// several sizes under `contents/images`
+pub fn is_package(path: &Path) -> bool {
+    let has_metadata =
+        path.join("metadata.json").is_file() || path.join("metadata.desktop").is_file();
+    has_metadata && path.join("contents/images").is_dir()
+}
+
+/// Images of a package with their sizes, from names like `1920x1080.jpg`
+/// or, failing that, the image headers
+pub fn variants(package: &Path) -> Vec<(PathBuf, (u32, u32))> {
+    let Ok(entries) = fs::read_dir(package.join("contents/images")) else {
+        return Vec::new();
+    };
+    let mut variants: Vec<_> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter_map(|path| {
+            let size = path
+                .file_stem()
+                .and_then(|stem| parse_size(&stem.to_string_lossy()))
+                .or_else(|| image::image_dimensions(&path).ok())?;
+            Some((path, size))
+        })
+        .collect();
+    variants.sort();
+    variants
+}
+
+/// "1920x1080" as width and height
+fn parse_size(name: &str) -> Option<(u32, u32)> {
+    let (width, height) = name.split_once('x')?;
+    let size = (width.parse().ok()?, height.parse().ok()?);
+    Some(size).filter(|&(width, height)| width > 0 && height > 0)
+}
+
+/// The variant of a package to show on an output of `output` pixels
+pub fn pick(package: &Path, output: (u32, u32)) -> Option<PathBuf> {
+    best_variant(&variants(package), output).map(Path::to_path_buf)
+}
+
+/// The variant that suits an output best: one of the same orientation,
+/// then one covering the output, then the closest aspect ratio, then the
+/// smallest (or, when none covers it, the largest)
+pub fn best_variant(variants: &[(PathBuf, (u32, u32))], output: (u32, u32)) -> Option<&Path> {
+    let area = |(width, height): (u32, u32)| width as u64 * height as u64;
+    if output.0 == 0 || output.1 == 0 {
+        return variants
+            .iter()
+            .max_by_key(|(_, size)| area(*size))
+            .map(|(path, _)| path.as_path());
+    }
+
+    let ratio = |(width, height): (u32, u32)| width as f64 / height as f64;
+    let portrait = output.1 > output.0;
+    variants
+        .iter()
+        .min_by_key(|(_, size)| {
+            let covers = size.0 >= output.0 && size.1 >= output.1;
+            // In hundredths, so 1366x768 counts as 16:9
+            let ratio_distance = ((ratio(*size) / ratio(output)).ln().abs() * 100.0).round() as u64;
+            let cost = if covers {
+                area(*size)
+            } else {
+                u64::MAX - area(*size)
+            };
+            ((size.1 > size.0) != portrait, !covers, ratio_distance, cost)
+        })
+        .map(|(path, _)| path.as_path())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variants(sizes: &[(u32, u32)]) -> Vec<(PathBuf, (u32, u32))> {
+        sizes
+            .iter()
+            .map(|&(w, h)| (PathBuf::from(format!("{}x{}.jpg", w, h)), (w, h)))
+            .collect()
+    }
+
+    #[test]
+    fn test_best_variant() {
+        let variants = variants(&[
+            (1366, 768),
+            (1920, 1080),
+            (2560, 1600),
+            (3840, 2160),
+            (1080, 1920),
+        ]);
+        let best = |output| best_variant(&variants, output).unwrap().to_str().unwrap();
+
+        assert_eq!(best((1920, 1080)), "1920x1080.jpg");
+        assert_eq!(best((2560, 1440)), "3840x2160.jpg");
+        assert_eq!(best((1920, 1200)), "2560x1600.jpg");
+        assert_eq!(best((1280, 720)), "1366x768.jpg");
+        // Nothing covers it, so the largest of the right shape
+        assert_eq!(best((5120, 2880)), "3840x2160.jpg");
+        assert_eq!(best((1080, 1920)), "1080x1920.jpg");
+        assert_eq!(best((0, 0)), "3840x2160.jpg");
+    }
+
+    #[test]
+    fn test_package() {
+        let dir = std::env::temp_dir().join(format!("canviz-kde-{}", std::process::id()));
+        fs::create_dir_all(dir.join("contents/images")).unwrap();
+        fs::write(dir.join("metadata.json"), "{}").unwrap();
+        for name in ["1920x1080.png", "3840x2160.png", "screenshot.txt"] {
+            fs::write(dir.join("contents/images").join(name), "").unwrap();
+        }
+
+        let package = is_package(&dir);
+        let picked = pick(&dir, (2560, 1440));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(package);
+        assert_eq!(picked, Some(dir.join("contents/images/3840x2160.png")));
+    }
+}
@@ -1,7 +1,20 @@
 use color_eyre::eyre::{eyre, Result, WrapErr};
-use image::{DynamicImage, GenericImageView, ImageFormat};
-use log::{debug, info};
-use std::path::Path;
+use crate::config::BackgroundMode;
+use crate::render::{ImageKey, UvRect};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GenericImageView, ImageFormat, RgbaImage};
+use log::{debug, info, warn};
+use smithay_client_toolkit::reexports::calloop::channel::Sender;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+
+pub mod gnome;
+pub mod kde;
 
 /// Loaded image data ready for GPU upload
 pub struct ImageData {
@@ -52,25 +65,121 @@ impl ImageData {
     }
 }
 
-/// Background image loader with caching
+/// Whether an image is taller than it is wide, read from its header only
+pub fn is_portrait(path: &Path) -> Option<bool> {
+    let (width, height) = image::image_dimensions(path).ok()?;
+    Some(height > width)
+}
+
+/// Decode an image file into RGBA, shrunk to fit `max_size` on both axes
+/// (the GPU's texture limit)
+pub fn decode(path: &Path, max_size: u32) -> Result<RgbaImage> {
+    info!("Loading image: {:?}", path);
+
+    let mut img = image::open(path)
+        .wrap_err_with(|| format!("Failed to open image: {:?}", path))?;
+
+    // Larger images can't be uploaded at all, shrink them to fit
+    if img.width() > max_size || img.height() > max_size {
+        warn!(
+            "{:?} is {}x{}, larger than the GPU's {} texture limit, downscaling",
+            path,
+            img.width(),
+            img.height(),
+            max_size
+        );
+        img = img.resize(max_size, max_size, image::imageops::FilterType::Lanczos3);
+    }
+
+    Ok(img.to_rgba8())
+}
+
+/// Shrink an image to the size it's drawn at on a `viewport` in `mode`
+/// with a Lanczos filter, for `filter = "lanczos"`. Images shown at or
+/// above their size are left alone.
+pub fn prescale(image: RgbaImage, mode: BackgroundMode, viewport: (u32, u32)) -> RgbaImage {
+    let (width, height) = UvRect::fit(mode, image.dimensions(), viewport).screen_size(viewport);
+    if width >= image.width() || height >= image.height() {
+        return image;
+    }
+    debug!(
+        "Prescaling {}x{} image to {}x{}",
+        image.width(),
+        image.height(),
+        width,
+        height
+    );
+    imageops::resize(&image, width, height, FilterType::Lanczos3)
+}
+
+/// An image decoded by `ImageLoader`, or why it couldn't be
+pub struct DecodedImage {
+    pub path: PathBuf,
+    /// The file and prescaling decoded for, with the size limit
+    pub key: (ImageKey, u32),
+    pub image: Result<Arc<RgbaImage>, String>,
+    /// How long decoding (and prescaling) took
+    pub elapsed: Duration,
+}
+
+/// Decodes images on the runtime's blocking threads, so large files don't
+/// hold up drawing. Results arrive on the event loop's channel.
+#[derive(Clone)]
 pub struct ImageLoader {
-    // Could add LRU cache here for frequently used images
+    runtime: Handle,
+    sender: Sender<DecodedImage>,
+    /// Decodes on their way, shared by the clones of all outputs
+    in_flight: Rc<RefCell<HashSet<(ImageKey, u32)>>>,
 }
 
 impl ImageLoader {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(runtime: Handle, sender: Sender<DecodedImage>) -> Self {
+        Self {
+            runtime,
+            sender,
+            in_flight: Rc::default(),
+        }
     }
 
-    /// Load an image, potentially from cache
-    pub fn load(&self, path: &Path) -> Result<ImageData> {
-        ImageData::load(path)
+    /// Start decoding an image, see `decode`. With a mode and viewport, it's
+    /// also passed through `prescale`. Every output gets the result, so a
+    /// request like one on its way doesn't decode the image again.
+    pub fn request(
+        &self,
+        path: &Path,
+        max_size: u32,
+        prescale_for: Option<(BackgroundMode, (u32, u32))>,
+    ) {
+        let key = (ImageKey::for_path(path).prescaled_for(prescale_for), max_size);
+        if !self.in_flight.borrow_mut().insert(key.clone()) {
+            debug!("Already decoding {:?}", path);
+            return;
+        }
+
+        let path = path.to_path_buf();
+        let sender = self.sender.clone();
+        self.runtime.spawn_blocking(move || {
+            let started = Instant::now();
+            let image = decode(&path, max_size)
+                .map(|image| match prescale_for {
+                    Some((mode, viewport)) => prescale(image, mode, viewport),
+                    None => image,
+                })
+                .map(Arc::new)
+                .map_err(|e| format!("{:#}", e));
+            let elapsed = started.elapsed();
+            let _ = sender.send(DecodedImage {
+                path,
+                key,
+                image,
+                elapsed,
+            });
+        });
     }
-}
 
-impl Default for ImageLoader {
-    fn default() -> Self {
-        Self::new()
+    /// Forget a decode once its result arrived, later requests start anew
+    pub fn finished(&self, decoded: &DecodedImage) {
+        self.in_flight.borrow_mut().remove(&decoded.key);
     }
 }
 
@@ -190,3 +299,62 @@ impl Default for ImagePicker {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_downscales_to_texture_limit() {
+        let path = std::env::temp_dir().join(format!("canviz-decode-{}.png", std::process::id()));
+        RgbaImage::from_pixel(300, 100, image::Rgba([10, 20, 30, 255]))
+            .save(&path)
+            .unwrap();
+
+        let full = decode(&path, 4096).unwrap();
+        let shrunk = decode(&path, 150).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(full.dimensions(), (300, 100));
+        // Aspect ratio is kept
+        assert_eq!(shrunk.dimensions(), (150, 50));
+        assert_eq!(shrunk.get_pixel(75, 25).0, [10, 20, 30, 255]);
+    }
+
+    #[test]
+    fn test_loader_decodes_once() {
+        let path = std::env::temp_dir().join(format!("canviz-loader-{}.png", std::process::id()));
+        RgbaImage::new(4, 4).save(&path).unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let (sender, _receiver) = smithay_client_toolkit::reexports::calloop::channel::channel();
+        let loader = ImageLoader::new(runtime.handle().clone(), sender);
+
+        loader.request(&path, 4096, None);
+        loader.clone().request(&path, 4096, None);
+        // Another size limit or prescaling is another decode
+        loader.request(&path, 2048, None);
+        loader.request(&path, 4096, Some((BackgroundMode::Cover, (2, 2))));
+        assert_eq!(loader.in_flight.borrow().len(), 3);
+
+        let key = (ImageKey::for_path(&path), 4096);
+        std::fs::remove_file(&path).unwrap();
+        loader.finished(&DecodedImage {
+            path,
+            key,
+            image: Err(String::new()),
+            elapsed: Duration::ZERO,
+        });
+        assert_eq!(loader.in_flight.borrow().len(), 2);
+    }
+
+    #[test]
+    fn test_prescale_to_screen_size() {
+        let image = RgbaImage::new(400, 200);
+        let scaled = prescale(image, BackgroundMode::Contain, (100, 100));
+        assert_eq!(scaled.dimensions(), (100, 50));
+
+        // Never scaled up
+        let small = prescale(RgbaImage::new(40, 20), BackgroundMode::Cover, (100, 100));
+        assert_eq!(small.dimensions(), (40, 20));
+    }
+}
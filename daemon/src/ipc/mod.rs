@@ -1,9 +1,12 @@
+use crate::config::Diagnostic;
 use color_eyre::eyre::{Result, WrapErr};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
+use smithay_client_toolkit::reexports::calloop::channel::Sender;
 use std::path::PathBuf;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::oneshot;
 
 /// IPC Commands that can be sent to the daemon
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,6 +18,9 @@ pub enum IpcCommand {
     Set {
         monitor: Option<String>,
         path: PathBuf,
+        /// Transition origin for this change (`x,y`, a named position or `cursor`)
+        #[serde(default)]
+        transition_pos: Option<String>,
     },
     /// Go to next wallpaper (slideshow)
     Next { monitor: Option<String> },
@@ -28,6 +34,17 @@ pub enum IpcCommand {
     Pause { monitor: Option<String> },
     /// Resume slideshow
     Resume { monitor: Option<String> },
+    /// Get frame timing statistics
+    Stats { monitor: Option<String> },
+    /// Show the effective config of a monitor and where each value comes from
+    ExplainConfig { monitor: String },
+    /// Check a config file (the daemon's own by default) against the
+    /// connected monitors
+    ValidateConfig { path: Option<PathBuf> },
+    /// Switch to a profile, or back to none
+    SetProfile { name: Option<String> },
+    /// List the configured profiles
+    ListProfiles,
 }
 
 /// IPC Response from the daemon
@@ -36,8 +53,18 @@ pub enum IpcCommand {
 pub enum IpcResponse {
     Ok { message: Option<String> },
     Error { message: String },
-    Status { monitors: Vec<MonitorStatus> },
+    Status {
+        monitors: Vec<MonitorStatus>,
+        /// GPU memory of all monitors in bytes, with textures shared
+        /// between monitors counted once
+        #[serde(default)]
+        gpu_memory: u64,
+    },
     Wallpaper { path: Option<PathBuf> },
+    Stats { monitors: Vec<MonitorStats> },
+    ConfigExplain { monitor: String, values: Vec<ConfigValue> },
+    ConfigCheck { path: PathBuf, diagnostics: Vec<Diagnostic> },
+    Profiles { active: Option<String>, profiles: Vec<String> },
 }
 
 /// Status of a single monitor
@@ -48,6 +75,74 @@ pub struct MonitorStatus {
     pub workspace: Option<i32>,
     pub slideshow_active: bool,
     pub slideshow_paused: bool,
+    /// Why the monitor isn't showing its wallpaper, if it failed
+    #[serde(default)]
+    pub error: Option<String>,
+    /// Approximate GPU memory held for the output, in bytes. Textures
+    /// shared with other outputs count on each of them.
+    #[serde(default)]
+    pub gpu_memory: u64,
+    /// Shared memory held while the GPU resources are released, in bytes
+    #[serde(default)]
+    pub cpu_memory: u64,
+    /// Schedule entry deciding the wallpaper, "07:00" or "mon-fri 07:00"
+    #[serde(default)]
+    pub schedule_entry: Option<String>,
+    /// Weight of the `daylight` day image against the night one, 0.0 to 1.0
+    #[serde(default)]
+    pub daylight: Option<f32>,
+}
+
+/// Frame timing of a single monitor, over its recent animations
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MonitorStats {
+    pub name: String,
+    /// Frames drawn since the daemon started
+    pub frames: u64,
+    /// Frames that came later than the refresh interval allows
+    pub late_frames: u64,
+    /// Refresh cycles missed by late frames, plus frames the compositor
+    /// discarded
+    pub dropped_frames: u64,
+    /// Refresh interval of the output, if known
+    pub refresh_ms: Option<f64>,
+    /// Timestamps come from wp_presentation rather than the draw calls
+    pub presentation_feedback: bool,
+    /// Time between consecutive frames of an animation
+    pub frame_time: TimingStats,
+    /// Wall time from the start to the end of each transition
+    pub transitions: TimingStats,
+    /// Time from a decoded image to its complete texture
+    pub uploads: TimingStats,
+    /// Time to decode an image off-thread
+    pub decodes: TimingStats,
+}
+
+/// Summary of a measurement; averages and percentiles cover the recent
+/// samples, `count` all of them
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TimingStats {
+    pub count: u64,
+    pub last_ms: f64,
+    pub avg_ms: f64,
+    pub p95_ms: f64,
+    pub max_ms: f64,
+}
+
+/// One value of a monitor's effective config
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigValue {
+    pub key: String,
+    /// Unset values have neither a value nor a source
+    pub value: Option<String>,
+    /// Config layer the value comes from, `[default]`, `[monitors.DP-1]`, ...
+    pub source: Option<String>,
+}
+
+/// A command received over IPC, forwarded to the daemon's event loop
+pub struct IpcRequest {
+    pub command: IpcCommand,
+    pub reply: oneshot::Sender<IpcResponse>,
 }
 
 /// Get the IPC socket path
@@ -104,6 +199,35 @@ impl IpcServer {
         Ok((command, stream))
     }
 
+    /// Accept commands forever, handing each to the event loop and writing
+    /// back whatever it replies
+    pub async fn run(self, tx: Sender<IpcRequest>) {
+        loop {
+            let (command, stream) = match self.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("IPC error: {:?}", e);
+                    continue;
+                }
+            };
+
+            let (reply, response) = oneshot::channel();
+            if tx.send(IpcRequest { command, reply }).is_err() {
+                error!("Event loop is gone, stopping IPC server");
+                return;
+            }
+
+            tokio::spawn(async move {
+                let response = response.await.unwrap_or_else(|_| IpcResponse::Error {
+                    message: "Daemon dropped the request".to_string(),
+                });
+                if let Err(e) = Self::respond(stream, response).await {
+                    warn!("Failed to send IPC response: {:?}", e);
+                }
+            });
+        }
+    }
+
     /// Send a response
     pub async fn respond(mut stream: UnixStream, response: IpcResponse) -> Result<()> {
         let json = serde_json::to_vec(&response)
@@ -3,13 +3,19 @@ mod daemon;
 mod hyprland;
 mod image;
 mod ipc;
+mod output;
 mod render;
+mod state;
 mod surface;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use color_eyre::eyre::Result;
-use log::{error, info};
-use std::path::PathBuf;
+use log::{error, info, warn};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use hyprland::HyprlandClient;
+use output::OutputIdentity;
 
 #[derive(Parser, Debug)]
 #[command(name = "canviz")]
@@ -26,6 +32,54 @@ struct Args {
     /// Run in foreground (don't daemonize)
     #[arg(short, long)]
     foreground: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check a config file and exit, non-zero if it has errors
+    CheckConfig {
+        /// Config file to check [default: the one the daemon would load]
+        path: Option<PathBuf>,
+    },
+}
+
+/// Monitors connected right now, as far as Hyprland can tell without a
+/// Wayland connection
+fn hyprland_outputs() -> Option<Vec<OutputIdentity>> {
+    if !hyprland::is_hyprland() {
+        return None;
+    }
+
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().ok()?;
+    let query = tokio::time::timeout(Duration::from_secs(1), HyprlandClient::get_monitors());
+    match runtime.block_on(query) {
+        Ok(Ok(monitors)) => Some(monitors.iter().map(OutputIdentity::from_hyprland).collect()),
+        _ => {
+            warn!("Couldn't list Hyprland monitors, not checking monitor names");
+            None
+        }
+    }
+}
+
+/// Print the problems in a config file, returning whether it has errors
+fn check_config(path: &Path) -> bool {
+    let outputs = hyprland_outputs();
+    let diagnostics = config::check_config(path, outputs.as_deref());
+    for diagnostic in &diagnostics {
+        println!("{}:{}", path.display(), diagnostic);
+    }
+
+    let errors = diagnostics.iter().filter(|d| d.severity == config::Severity::Error).count();
+    let warnings = diagnostics.len() - errors;
+    if diagnostics.is_empty() {
+        println!("{}: OK", path.display());
+    } else {
+        println!("{} error(s), {} warning(s)", errors, warnings);
+    }
+    errors > 0
 }
 
 fn main() -> Result<()> {
@@ -37,28 +91,34 @@ fn main() -> Result<()> {
     let log_level = if args.verbose { "debug" } else { "info" };
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(log_level)).init();
 
-    info!("Starting Canviz wallpaper daemon v{}", env!("CARGO_PKG_VERSION"));
-
-    // Load configuration
     let config_path = args.config.unwrap_or_else(|| {
         dirs::config_dir()
             .map(|p| p.join("canviz/config.toml"))
             .expect("Could not determine config directory")
     });
 
+    if let Some(Command::CheckConfig { path }) = args.command {
+        let failed = check_config(&path.unwrap_or(config_path));
+        std::process::exit(if failed { 1 } else { 0 });
+    }
+
+    info!("Starting Canviz wallpaper daemon v{}", env!("CARGO_PKG_VERSION"));
+
+    // Load configuration
+
     info!("Loading config from: {:?}", config_path);
 
     let config = match config::Config::load(&config_path) {
         Ok(cfg) => cfg,
         Err(e) => {
             error!("Failed to load config: {}", e);
-            error!("Using default configuration");
+            error!("Using default configuration, run `canviz check-config` for details");
             config::Config::default()
         }
     };
 
     // Run the daemon
-    if let Err(e) = daemon::run(config, args.foreground) {
+    if let Err(e) = daemon::run(config, config_path, args.foreground) {
         error!("Daemon error: {:?}", e);
         return Err(e);
     }
@@ -0,0 +1,135 @@
+use crate::hyprland::HyprlandMonitor;
+use smithay_client_toolkit::output::OutputInfo;
+
+/// What identifies a monitor across reconnects
+///
+/// wl_output has no serial number, it comes from Hyprland when available.
+/// Elsewhere compositors usually put it in the description (wlroots uses
+/// "make model serial (connector)"), so a monitor with either is recognized
+/// on any connector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputIdentity {
+    /// Global name of the wl_output, only unique while it is connected
+    pub id: u32,
+    /// Connector name, "DP-1", "HDMI-A-1", ...
+    pub name: Option<String>,
+    pub make: String,
+    pub model: String,
+    pub description: Option<String>,
+    pub serial: Option<String>,
+}
+
+impl OutputIdentity {
+    pub fn from_info(info: &OutputInfo) -> Self {
+        Self {
+            id: info.id,
+            name: info.name.clone(),
+            make: info.make.clone(),
+            model: info.model.clone(),
+            description: info
+                .description
+                .as_deref()
+                .map(|description| without_connector(description, info.name.as_deref())),
+            serial: None,
+        }
+    }
+
+    /// Identity of a monitor as Hyprland reports it, for checking the
+    /// config without a Wayland connection
+    pub fn from_hyprland(monitor: &HyprlandMonitor) -> Self {
+        let non_empty = |s: &str| Some(s.to_string()).filter(|s| !s.is_empty());
+        Self {
+            id: monitor.id as u32,
+            name: Some(monitor.name.clone()),
+            make: monitor.make.clone(),
+            model: monitor.model.clone(),
+            description: non_empty(&monitor.description),
+            serial: non_empty(&monitor.serial),
+        }
+    }
+
+    /// Name used for config lookups, IPC and logs. Compositors without
+    /// wl_output v4 don't name outputs, those get one from their global.
+    pub fn display_name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| format!("output-{}", self.id))
+    }
+
+    /// Whether `other` is the same monitor, possibly on another connector
+    pub fn matches(&self, other: &OutputIdentity) -> bool {
+        if self.make != other.make || self.model != other.model {
+            return false;
+        }
+        if let (Some(a), Some(b)) = (&self.serial, &other.serial) {
+            return a == b;
+        }
+        match (&self.description, &other.description) {
+            (Some(a), Some(b)) => a == b,
+            // Identical monitors can only be told apart by their connector
+            _ => self.name.is_some() && self.name == other.name,
+        }
+    }
+}
+
+/// A description without the " (DP-1)" wlroots appends, which would keep
+/// the monitor from matching itself on another connector
+fn without_connector(description: &str, name: Option<&str>) -> String {
+    name.and_then(|name| description.strip_suffix(&format!(" ({})", name)))
+        .unwrap_or(description)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(id: u32, name: Option<&str>, description: Option<&str>) -> OutputIdentity {
+        OutputIdentity {
+            id,
+            name: name.map(str::to_string),
+            make: "Dell Inc.".to_string(),
+            model: "U2720Q".to_string(),
+            description: description.map(str::to_string),
+            serial: None,
+        }
+    }
+
+    #[test]
+    fn test_display_name() {
+        assert_eq!(identity(3, Some("DP-1"), None).display_name(), "DP-1");
+        assert_eq!(identity(3, None, None).display_name(), "output-3");
+    }
+
+    #[test]
+    fn test_matches_description_on_any_connector() {
+        let before = identity(3, Some("DP-1"), Some("Dell Inc. U2720Q 8XYZ"));
+        assert!(before.matches(&identity(9, Some("DP-2"), Some("Dell Inc. U2720Q 8XYZ"))));
+        // A second monitor of the same model has another serial
+        assert!(!before.matches(&identity(9, Some("DP-1"), Some("Dell Inc. U2720Q 9ABC"))));
+    }
+
+    #[test]
+    fn test_matches_wlroots_description() {
+        let wlroots = |id, name| {
+            let description =
+                without_connector(&format!("Dell Inc. U2720Q 8XYZ ({})", name), Some(name));
+            identity(id, Some(name), Some(&description))
+        };
+        let before = wlroots(3, "DP-1");
+        assert_eq!(before.description.as_deref(), Some("Dell Inc. U2720Q 8XYZ"));
+        assert!(before.matches(&wlroots(9, "DP-2")));
+        // Only the output's own connector is stripped
+        assert_eq!(
+            without_connector("Acme (rev 2)", Some("DP-1")),
+            "Acme (rev 2)"
+        );
+    }
+
+    #[test]
+    fn test_matches_connector_without_description() {
+        let before = identity(3, Some("DP-1"), None);
+        assert!(before.matches(&identity(9, Some("DP-1"), None)));
+        assert!(!before.matches(&identity(9, Some("DP-2"), None)));
+        // Unnamed outputs can't be told apart
+        assert!(!identity(3, None, None).matches(&identity(3, None, None)));
+    }
+}
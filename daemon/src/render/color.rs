@@ -0,0 +1,123 @@
+//! CPU reference of the color math in `shaders/fragment.glsl`, so blending
+//! and dithering can be checked without a GPU. Keep the two in sync.
+
+/// sRGB encoded value to linear light, both 0..1
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c < 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Linear light to sRGB encoded value, both 0..1
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c < 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Interleaved gradient noise at a pixel, -0.5..0.5
+pub fn dither_noise(x: f32, y: f32) -> f32 {
+    let inner = (x * 0.06711056 + y * 0.00583715).fract();
+    (52.98292 * inner).fract() - 0.5
+}
+
+/// Output of a crossfade from `from` to `to` at `progress`, as the shader
+/// writes it to an 8-bit buffer at pixel (x, y)
+pub fn crossfade(
+    from: [u8; 3],
+    to: [u8; 3],
+    progress: f32,
+    linear: bool,
+    dither: bool,
+    pixel: (f32, f32),
+) -> [u8; 3] {
+    let decode = |c: u8| {
+        let c = c as f32 / 255.0;
+        if linear {
+            srgb_to_linear(c)
+        } else {
+            c
+        }
+    };
+    let noise = if dither {
+        dither_noise(pixel.0 + 0.5, pixel.1 + 0.5) / 255.0
+    } else {
+        0.0
+    };
+
+    [0, 1, 2].map(|i| {
+        let a = decode(from[i]);
+        let b = decode(to[i]);
+        let mut c = a + (b - a) * progress;
+        if linear {
+            c = linear_to_srgb(c.clamp(0.0, 1.0));
+        }
+        ((c + noise).clamp(0.0, 1.0) * 255.0).round() as u8
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transfer_roundtrip() {
+        for v in 0..=255u8 {
+            let c = v as f32 / 255.0;
+            let back = linear_to_srgb(srgb_to_linear(c));
+            assert!((back - c).abs() < 1e-4, "{} -> {}", c, back);
+        }
+    }
+
+    #[test]
+    fn test_crossfade_golden() {
+        let black = [0, 0, 0];
+        let white = [255, 255, 255];
+        let origin = (0.0, 0.0);
+
+        // Half way in sRGB space is too dark, in linear light it's 50% light
+        assert_eq!(crossfade(black, white, 0.5, false, false, origin), [128; 3]);
+        assert_eq!(crossfade(black, white, 0.5, true, false, origin), [188; 3]);
+
+        // Dark to bright stays above the straight sRGB mix throughout
+        let dark = [20, 40, 60];
+        let bright = [240, 220, 200];
+        for step in 1..10 {
+            let t = step as f32 / 10.0;
+            let gamma = crossfade(dark, bright, t, false, false, origin);
+            let linear = crossfade(dark, bright, t, true, false, origin);
+            assert!((0..3).all(|i| linear[i] >= gamma[i]), "{:?} < {:?}", linear, gamma);
+        }
+
+        // The ends are untouched
+        assert_eq!(crossfade(dark, bright, 0.0, true, false, origin), dark);
+        assert_eq!(crossfade(dark, bright, 1.0, true, false, origin), bright);
+    }
+
+    #[test]
+    fn test_dither_breaks_up_banding() {
+        // A value between two output steps comes out as a mix of both
+        // whose average matches the value
+        let from = [100, 100, 100];
+        let to = [101, 101, 101];
+        let mut sum = 0u32;
+        let mut levels = std::collections::HashSet::new();
+        for y in 0..64 {
+            for x in 0..64 {
+                let out = crossfade(from, to, 0.25, false, true, (x as f32, y as f32));
+                sum += out[0] as u32;
+                levels.insert(out[0]);
+            }
+        }
+        let mean = sum as f32 / (64.0 * 64.0);
+        assert!((mean - 100.25).abs() < 0.05, "mean {}", mean);
+        assert_eq!(levels.len(), 2);
+
+        // Without dithering it's one flat band
+        assert_eq!(crossfade(from, to, 0.25, false, false, (3.0, 7.0)), [100; 3]);
+    }
+}
@@ -1,6 +1,8 @@
 use super::gl;
 use color_eyre::eyre::{eyre, Result, WrapErr};
 use log::{debug, error, info};
+use std::cell::Cell;
+use std::rc::Rc;
 use wayland_client::protocol::wl_surface::WlSurface;
 use wayland_client::{Connection, Proxy};
 use wayland_egl::WlEglSurface;
@@ -40,24 +42,43 @@ pub fn init_egl_display(conn: &Connection) -> Result<egl::Display> {
     Ok(display)
 }
 
-/// EGL context for OpenGL ES rendering
+/// What a failed EGL call says about the GPU state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpuLoss {
+    /// The context is gone (GPU reset, driver update), everything in it
+    /// has to be rebuilt
+    Context,
+    /// Only the window surface of one output is unusable
+    Surface,
+}
+
+/// Whether an error comes from a lost context or surface, as opposed to a
+/// failure that recreating them wouldn't fix
+pub fn gpu_loss(error: &color_eyre::Report) -> Option<GpuLoss> {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<egl::Error>())
+        .and_then(|error| match error {
+            egl::Error::ContextLost => Some(GpuLoss::Context),
+            egl::Error::BadSurface | egl::Error::BadNativeWindow => Some(GpuLoss::Surface),
+            _ => None,
+        })
+}
+
+/// EGL context for OpenGL ES rendering, shared by the surfaces of all
+/// outputs so GL objects (programs, textures) only exist once
 pub struct EglContext {
     display: egl::Display,
     context: egl::Context,
-    surface: egl::Surface,
-    wl_egl_surface: WlEglSurface,
-    _config: egl::Config,
+    config: egl::Config,
+    /// GL info is logged the first time the context is made current
+    logged: Cell<bool>,
 }
 
 impl EglContext {
-    /// Create a new EGL context for the given Wayland surface
-    pub fn new(
-        egl_display: egl::Display,
-        wl_surface: &WlSurface,
-        width: u32,
-        height: u32,
-    ) -> Result<Self> {
-        info!("Creating EGL context ({}x{})", width, height);
+    /// Create the context and load the OpenGL ES functions
+    pub fn new(egl_display: egl::Display) -> Result<Self> {
+        info!("Creating EGL context");
 
         // Choose EGL config
         let config_attribs = [
@@ -89,35 +110,7 @@ impl EglContext {
 
         debug!("EGL context created");
 
-        // Create Wayland EGL surface
-        let wl_egl_surface = WlEglSurface::new(wl_surface.id(), width as i32, height as i32)
-            .wrap_err("Failed to create Wayland EGL surface")?;
-
-        debug!("Wayland EGL surface created");
-
-        // Create EGL window surface
-        let surface_attribs = [egl::NONE];
-        let surface = unsafe {
-            egl_api
-                .create_window_surface(
-                    egl_display,
-                    config,
-                    wl_egl_surface.ptr() as egl::NativeWindowType,
-                    Some(&surface_attribs),
-                )
-                .wrap_err("Failed to create EGL window surface")?
-        };
-
-        debug!("EGL window surface created");
-
-        // Make context current
-        egl_api
-            .make_current(egl_display, Some(surface), Some(surface), Some(context))
-            .wrap_err("Failed to make EGL context current")?;
-
-        debug!("EGL context made current");
-
-        // Load OpenGL ES functions
+        // Load OpenGL ES functions, once for all outputs
         gl::load_with(|name| {
             egl_api
                 .get_proc_address(name)
@@ -125,7 +118,24 @@ impl EglContext {
                 .unwrap_or(std::ptr::null())
         });
 
-        // Log OpenGL info
+        Ok(Self {
+            display: egl_display,
+            context,
+            config,
+            logged: Cell::new(false),
+        })
+    }
+
+    /// Display the context was created on, to create a replacement
+    pub fn display(&self) -> egl::Display {
+        self.display
+    }
+
+    /// Log the OpenGL implementation, needs the context to be current
+    fn log_gl_info(&self) {
+        if self.logged.replace(true) {
+            return;
+        }
         unsafe {
             let version = gl::GetString(gl::VERSION);
             let vendor = gl::GetString(gl::VENDOR);
@@ -140,26 +150,76 @@ impl EglContext {
                 );
             }
         }
+    }
+}
 
-        info!("EGL context created successfully for surface");
+impl Drop for EglContext {
+    fn drop(&mut self) {
+        info!("Destroying EGL context");
 
-        Ok(Self {
-            display: egl_display,
+        // Make no context current
+        let _ = egl_api.make_current(self.display, None, None, None);
+        let _ = egl_api.destroy_context(self.display, self.context);
+    }
+}
+
+/// EGL window surface of one output, drawn with the shared context
+pub struct EglSurface {
+    context: Rc<EglContext>,
+    surface: egl::Surface,
+    wl_egl_surface: WlEglSurface,
+}
+
+impl EglSurface {
+    /// Create a window surface for the given Wayland surface and make it current
+    pub fn new(
+        context: Rc<EglContext>,
+        wl_surface: &WlSurface,
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        info!("Creating EGL surface ({}x{})", width, height);
+
+        // Create Wayland EGL surface
+        let wl_egl_surface = WlEglSurface::new(wl_surface.id(), width as i32, height as i32)
+            .wrap_err("Failed to create Wayland EGL surface")?;
+
+        debug!("Wayland EGL surface created");
+
+        // Create EGL window surface
+        let surface_attribs = [egl::NONE];
+        let surface = unsafe {
+            egl_api
+                .create_window_surface(
+                    context.display,
+                    context.config,
+                    wl_egl_surface.ptr() as egl::NativeWindowType,
+                    Some(&surface_attribs),
+                )
+                .wrap_err("Failed to create EGL window surface")?
+        };
+
+        debug!("EGL window surface created");
+
+        let egl_surface = Self {
             context,
             surface,
             wl_egl_surface,
-            _config: config,
-        })
+        };
+        egl_surface.make_current()?;
+        egl_surface.context.log_gl_info();
+
+        Ok(egl_surface)
     }
 
-    /// Make this context current
+    /// Make the shared context current on this surface
     pub fn make_current(&self) -> Result<()> {
         egl_api
             .make_current(
-                self.display,
+                self.context.display,
                 Some(self.surface),
                 Some(self.surface),
-                Some(self.context),
+                Some(self.context.context),
             )
             .wrap_err("Failed to make EGL context current")
     }
@@ -167,7 +227,7 @@ impl EglContext {
     /// Swap buffers (present the rendered frame)
     pub fn swap_buffers(&self) -> Result<()> {
         egl_api
-            .swap_buffers(self.display, self.surface)
+            .swap_buffers(self.context.display, self.surface)
             .wrap_err("Failed to swap EGL buffers")
     }
 
@@ -179,15 +239,27 @@ impl EglContext {
     }
 }
 
-impl Drop for EglContext {
+impl Drop for EglSurface {
     fn drop(&mut self) {
-        info!("Destroying EGL context");
+        info!("Destroying EGL surface");
 
-        // Make no context current
-        let _ = egl_api.make_current(self.display, None, None, None);
+        // Keep the context, just detach it from this surface
+        let _ = egl_api.make_current(self.context.display, None, None, None);
+        let _ = egl_api.destroy_surface(self.context.display, self.surface);
+    }
+}
 
-        // Destroy surface and context
-        let _ = egl_api.destroy_surface(self.display, self.surface);
-        let _ = egl_api.destroy_context(self.display, self.context);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gpu_loss() {
+        let report = |error: egl::Error| eyre!(error).wrap_err("Failed to swap buffers");
+        assert_eq!(gpu_loss(&report(egl::Error::ContextLost)), Some(GpuLoss::Context));
+        assert_eq!(gpu_loss(&report(egl::Error::BadSurface)), Some(GpuLoss::Surface));
+        assert_eq!(gpu_loss(&report(egl::Error::BadNativeWindow)), Some(GpuLoss::Surface));
+        assert_eq!(gpu_loss(&report(egl::Error::BadAlloc)), None);
+        assert_eq!(gpu_loss(&eyre!("Framebuffer incomplete")), None);
     }
 }
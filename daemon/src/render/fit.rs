@@ -0,0 +1,215 @@
+use crate::config::BackgroundMode;
+use wayland_client::protocol::wl_output::Transform;
+
+/// Maps buffer coordinates onto surface coordinates (both 0..1 from the
+/// top-left) for a buffer committed with `set_buffer_transform`, so the
+/// wallpaper can be drawn pre-rotated in the output's native orientation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BufferTransform {
+    /// Column-major 2x2 matrix, as GLSL's mat2 expects
+    pub matrix: [f32; 4],
+    pub offset: [f32; 2],
+}
+
+impl BufferTransform {
+    pub fn new(transform: Transform) -> Self {
+        let (matrix, offset) = match transform {
+            Transform::_90 => ([0.0, -1.0, 1.0, 0.0], [0.0, 1.0]),
+            Transform::_180 => ([-1.0, 0.0, 0.0, -1.0], [1.0, 1.0]),
+            Transform::_270 => ([0.0, 1.0, -1.0, 0.0], [1.0, 0.0]),
+            Transform::Flipped => ([-1.0, 0.0, 0.0, 1.0], [1.0, 0.0]),
+            Transform::Flipped90 => ([0.0, -1.0, -1.0, 0.0], [1.0, 1.0]),
+            Transform::Flipped180 => ([1.0, 0.0, 0.0, -1.0], [0.0, 1.0]),
+            Transform::Flipped270 => ([0.0, 1.0, 1.0, 0.0], [0.0, 0.0]),
+            _ => ([1.0, 0.0, 0.0, 1.0], [0.0, 0.0]),
+        };
+        Self { matrix, offset }
+    }
+
+    /// Whether the buffer is rotated a quarter turn against the surface
+    pub fn swaps_axes(transform: Transform) -> bool {
+        matches!(
+            transform,
+            Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270
+        )
+    }
+
+    /// Surface coordinates of a point in the buffer
+    pub fn apply(&self, uv: [f32; 2]) -> [f32; 2] {
+        let m = self.matrix;
+        [
+            m[0] * uv[0] + m[2] * uv[1] + self.offset[0],
+            m[1] * uv[0] + m[3] * uv[1] + self.offset[1],
+        ]
+    }
+}
+
+/// Maps screen coordinates (0..1 from the top-left) onto texture
+/// coordinates as `tex = offset + uv * scale`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UvRect {
+    pub offset: [f32; 2],
+    pub scale: [f32; 2],
+    /// Repeat the texture instead of showing black outside of it
+    pub wrap: bool,
+}
+
+impl UvRect {
+    /// Texture stretched over the whole screen
+    pub const IDENTITY: UvRect = UvRect {
+        offset: [0.0, 0.0],
+        scale: [1.0, 1.0],
+        wrap: false,
+    };
+
+    /// Place an image of the given size on a viewport according to `mode`
+    pub fn fit(mode: BackgroundMode, image: (u32, u32), viewport: (u32, u32)) -> Self {
+        let (iw, ih) = (image.0 as f32, image.1 as f32);
+        let (vw, vh) = (viewport.0 as f32, viewport.1 as f32);
+        if iw <= 0.0 || ih <= 0.0 || vw <= 0.0 || vh <= 0.0 {
+            return Self::IDENTITY;
+        }
+
+        let image_aspect = iw / ih;
+        let screen_aspect = vw / vh;
+
+        let scale = match mode {
+            BackgroundMode::Fill => return Self::IDENTITY,
+            BackgroundMode::Cover if image_aspect > screen_aspect => {
+                [screen_aspect / image_aspect, 1.0]
+            }
+            BackgroundMode::Cover => [1.0, image_aspect / screen_aspect],
+            BackgroundMode::Contain if image_aspect > screen_aspect => {
+                [1.0, image_aspect / screen_aspect]
+            }
+            BackgroundMode::Contain => [screen_aspect / image_aspect, 1.0],
+            BackgroundMode::Center => [vw / iw, vh / ih],
+            BackgroundMode::Tile => {
+                return Self {
+                    offset: [0.0, 0.0],
+                    scale: [vw / iw, vh / ih],
+                    wrap: true,
+                }
+            }
+        };
+
+        Self {
+            offset: [(1.0 - scale[0]) / 2.0, (1.0 - scale[1]) / 2.0],
+            scale,
+            wrap: false,
+        }
+    }
+
+    /// Size in pixels the whole image is drawn at on a `viewport` with this
+    /// rect, at least 1x1
+    pub fn screen_size(&self, viewport: (u32, u32)) -> (u32, u32) {
+        (
+            ((viewport.0 as f32 / self.scale[0]).round() as u32).max(1),
+            ((viewport.1 as f32 / self.scale[1]).round() as u32).max(1),
+        )
+    }
+
+    /// Move the visible part of an image that overflows the screen along
+    /// one axis, `position` 0..1 from the left (or top) edge to the other.
+    /// Images that fit on that axis stay where they are.
+    pub fn scroll(&self, vertical: bool, position: f32) -> Self {
+        let axis = vertical as usize;
+        let mut rect = *self;
+        if !rect.wrap && rect.scale[axis] < 1.0 {
+            rect.offset[axis] = position.clamp(0.0, 1.0) * (1.0 - rect.scale[axis]);
+        }
+        rect
+    }
+
+    /// Narrow the rect to a window magnified by `zoom` around `center`,
+    /// given in fractions of this rect
+    pub fn window(&self, zoom: f32, center: [f32; 2]) -> Self {
+        let zoom = zoom.max(f32::EPSILON);
+        let half = 0.5 / zoom;
+
+        Self {
+            offset: [
+                self.offset[0] + self.scale[0] * (center[0] - half),
+                self.offset[1] + self.scale[1] * (center[1] - half),
+            ],
+            scale: [self.scale[0] / zoom, self.scale[1] / zoom],
+            wrap: self.wrap,
+        }
+    }
+
+    /// Packed as the shader expects it: offset in xy, scale in zw
+    pub fn as_uniform(&self) -> [f32; 4] {
+        [self.offset[0], self.offset[1], self.scale[0], self.scale[1]]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx(a: [f32; 2], b: [f32; 2]) -> bool {
+        (a[0] - b[0]).abs() < 1e-5 && (a[1] - b[1]).abs() < 1e-5
+    }
+
+    #[test]
+    fn test_buffer_transform_corners() {
+        // Rotated 90 degrees counter-clockwise: the buffer's top-left shows
+        // the surface's bottom-left
+        let rotated = BufferTransform::new(Transform::_90);
+        assert_eq!(rotated.apply([0.0, 0.0]), [0.0, 1.0]);
+        assert_eq!(rotated.apply([1.0, 0.0]), [0.0, 0.0]);
+
+        let flipped = BufferTransform::new(Transform::Flipped270);
+        assert_eq!(flipped.apply([1.0, 0.0]), [0.0, 1.0]);
+
+        let normal = BufferTransform::new(Transform::Normal);
+        assert_eq!(normal.apply([0.25, 0.75]), [0.25, 0.75]);
+        assert!(BufferTransform::swaps_axes(Transform::_270));
+        assert!(!BufferTransform::swaps_axes(Transform::Flipped180));
+    }
+
+    #[test]
+    fn test_cover_crops_wide_image() {
+        // 32:9 image on a 16:9 screen shows the middle half
+        let rect = UvRect::fit(BackgroundMode::Cover, (3840, 1080), (1920, 1080));
+        assert!(approx(rect.scale, [0.5, 1.0]));
+        assert!(approx(rect.offset, [0.25, 0.0]));
+    }
+
+    #[test]
+    fn test_contain_letterboxes_wide_image() {
+        let rect = UvRect::fit(BackgroundMode::Contain, (3840, 1080), (1920, 1080));
+        assert!(approx(rect.scale, [1.0, 2.0]));
+        assert!(approx(rect.offset, [0.0, -0.5]));
+    }
+
+    #[test]
+    fn test_screen_size() {
+        let viewport = (1920, 1080);
+        let contain = UvRect::fit(BackgroundMode::Contain, (3840, 1080), viewport);
+        assert_eq!(contain.screen_size(viewport), (1920, 540));
+        let cover = UvRect::fit(BackgroundMode::Cover, (3840, 1080), viewport);
+        assert_eq!(cover.screen_size(viewport), (3840, 1080));
+        let center = UvRect::fit(BackgroundMode::Center, (640, 480), viewport);
+        assert_eq!(center.screen_size(viewport), (640, 480));
+    }
+
+    #[test]
+    fn test_scroll_across_wide_image() {
+        let rect = UvRect::fit(BackgroundMode::Cover, (3840, 1080), (1920, 1080));
+        assert!(approx(rect.scroll(false, 0.0).offset, [0.0, 0.0]));
+        assert!(approx(rect.scroll(false, 1.0).offset, [0.5, 0.0]));
+        // Nothing to scroll over vertically
+        assert_eq!(rect.scroll(true, 1.0), rect);
+    }
+
+    #[test]
+    fn test_window_zooms_into_rect() {
+        let rect = UvRect::fit(BackgroundMode::Cover, (3840, 1080), (1920, 1080));
+        let zoomed = rect.window(2.0, [0.5, 0.5]);
+        assert!(approx(zoomed.scale, [0.25, 0.5]));
+        assert!(approx(zoomed.offset, [0.375, 0.25]));
+
+        assert_eq!(rect.window(1.0, [0.5, 0.5]), rect);
+    }
+}
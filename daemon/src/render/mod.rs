@@ -1,8 +1,19 @@
+// CPU reference of the shader color math, only needed by tests
+#[cfg(test)]
+mod color;
 mod egl;
+mod fit;
+mod motion;
 mod renderer;
+mod resources;
+pub mod software;
+mod transition;
 
-pub use egl::{init_egl_display, EglContext};
+pub use egl::{gpu_loss, init_egl_display, EglContext, EglSurface, GpuLoss};
+pub use fit::{BufferTransform, UvRect};
 pub use renderer::{Renderer, Texture};
+pub use resources::{GlResources, ImageKey};
+pub use transition::TransitionSettings;
 
 // OpenGL bindings generated by build.rs
 #[allow(clippy::all)]
@@ -0,0 +1,107 @@
+use crate::config::{MotionConfig, PanDirection};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// One pass of the Ken Burns effect over a single image
+///
+/// Positions are fractions of the range the view can move in at the
+/// current zoom, so the view never leaves the fitted image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotionPath {
+    start_zoom: f32,
+    end_zoom: f32,
+    start: [f32; 2],
+    end: [f32; 2],
+}
+
+impl MotionPath {
+    /// Plan the pass for a new image
+    pub fn new(config: &MotionConfig) -> Self {
+        let seed = RandomState::new().build_hasher().finish();
+        Self::with_seed(config, seed)
+    }
+
+    fn with_seed(config: &MotionConfig, seed: u64) -> Self {
+        let [mut start_zoom, mut end_zoom] = config.zoom.map(|z| z.max(1.0));
+        let center = [0.5, 0.5];
+
+        let (start, end) = match config.pan {
+            PanDirection::None => (center, center),
+            PanDirection::Left => ([1.0, 0.5], [0.0, 0.5]),
+            PanDirection::Right => ([0.0, 0.5], [1.0, 0.5]),
+            PanDirection::Up => ([0.5, 1.0], [0.5, 0.0]),
+            PanDirection::Down => ([0.5, 0.0], [0.5, 1.0]),
+            PanDirection::Focus => (center, config.focus.map(|f| f.clamp(0.0, 1.0))),
+            PanDirection::Random => {
+                let mut state = seed;
+                let mut next = || {
+                    state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    (state >> 40) as f32 / (1u64 << 24) as f32
+                };
+
+                if next() < 0.5 {
+                    std::mem::swap(&mut start_zoom, &mut end_zoom);
+                }
+                ([next(), next()], [next(), next()])
+            }
+        };
+
+        Self {
+            start_zoom,
+            end_zoom,
+            start,
+            end,
+        }
+    }
+
+    /// Zoom and view center (fractions of the fitted image) at `t` in 0..1
+    pub fn at(&self, t: f32) -> (f32, [f32; 2]) {
+        let t = t.clamp(0.0, 1.0);
+        let zoom = self.start_zoom + (self.end_zoom - self.start_zoom) * t;
+
+        let travel = 1.0 - 1.0 / zoom;
+        let center = [0, 1].map(|i| {
+            let position = self.start[i] + (self.end[i] - self.start[i]) * t;
+            0.5 / zoom + position * travel
+        });
+
+        (zoom, center)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pan_left_stays_inside_image() {
+        let config = MotionConfig {
+            zoom: [1.25, 1.25],
+            pan: PanDirection::Left,
+            ..MotionConfig::default()
+        };
+        let path = MotionPath::new(&config);
+
+        // The view starts at the right edge and ends at the left edge
+        let (zoom, start) = path.at(0.0);
+        assert_eq!(zoom, 1.25);
+        assert!((start[0] - 0.6).abs() < 1e-5);
+        let (_, end) = path.at(1.0);
+        assert!((end[0] - 0.4).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_random_path_stays_inside_image() {
+        let config = MotionConfig::default();
+        for seed in 0..64 {
+            let path = MotionPath::with_seed(&config, seed);
+            for step in 0..=10 {
+                let (zoom, center) = path.at(step as f32 / 10.0);
+                let half = 0.5 / zoom;
+                for c in center {
+                    assert!(c - half >= -1e-5 && c + half <= 1.0 + 1e-5);
+                }
+            }
+        }
+    }
+}
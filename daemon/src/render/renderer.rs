@@ -1,12 +1,22 @@
+use super::fit::{BufferTransform, UvRect};
 use super::gl;
-use crate::config::{BackgroundMode, TransitionType};
-use color_eyre::eyre::{eyre, Result, WrapErr};
-use log::{debug, error, info};
+use super::motion::MotionPath;
+use super::resources::{GlResources, ImageKey};
+use super::transition::{self, TransitionSettings};
+use crate::config::{BackgroundMode, MotionConfig, ParallaxConfig, TextureFilter, TransitionType};
+use color_eyre::eyre::{eyre, Result};
+use image::RgbaImage;
+use log::{debug, info, warn};
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::ffi::CString;
+use std::path::Path;
 use std::ptr;
+use std::rc::Rc;
+use std::sync::Arc;
+use wayland_client::protocol::wl_output::Transform;
 
 const VERTEX_SHADER_SRC: &str = include_str!("shaders/vertex.glsl");
-const FRAGMENT_SHADER_SRC: &str = include_str!("shaders/fragment.glsl");
 
 /// Compiled shader program
 pub struct ShaderProgram {
@@ -16,11 +26,23 @@ pub struct ShaderProgram {
     pub u_texture: i32,
     pub u_texture_prev: i32,
     pub u_progress: i32,
-    pub u_transition_type: i32,
+    pub u_resolution: i32,
+    pub u_origin: i32,
+    pub u_direction: i32,
+    pub u_params: i32,
+    pub u_flip_y: i32,
+    pub u_current_rect: i32,
+    pub u_previous_rect: i32,
+    pub u_wrap: i32,
+    pub u_buffer_matrix: i32,
+    pub u_buffer_offset: i32,
+    pub u_linear: i32,
+    pub u_dither: i32,
 }
 
 impl ShaderProgram {
-    pub fn new() -> Result<Self> {
+    /// Compile the program for a transition effect
+    pub fn new(transition: TransitionType) -> Result<Self> {
         unsafe {
             // Compile vertex shader
             let vertex_shader = gl::CreateShader(gl::VERTEX_SHADER);
@@ -31,7 +53,7 @@ impl ShaderProgram {
 
             // Compile fragment shader
             let fragment_shader = gl::CreateShader(gl::FRAGMENT_SHADER);
-            let fragment_src = CString::new(FRAGMENT_SHADER_SRC).unwrap();
+            let fragment_src = CString::new(transition::fragment_source(transition)).unwrap();
             gl::ShaderSource(fragment_shader, 1, &fragment_src.as_ptr(), ptr::null());
             gl::CompileShader(fragment_shader);
             Self::check_shader_compile(fragment_shader, "fragment")?;
@@ -57,14 +79,36 @@ impl ShaderProgram {
             let u_tex_name = CString::new("u_texture").unwrap();
             let u_tex_prev_name = CString::new("u_texture_prev").unwrap();
             let u_prog_name = CString::new("u_progress").unwrap();
-            let u_trans_name = CString::new("u_transition_type").unwrap();
+            let u_res_name = CString::new("u_resolution").unwrap();
+            let u_origin_name = CString::new("u_origin").unwrap();
+            let u_dir_name = CString::new("u_direction").unwrap();
+            let u_params_name = CString::new("u_params").unwrap();
+            let u_flip_name = CString::new("u_flip_y").unwrap();
+            let u_cur_rect_name = CString::new("u_current_rect").unwrap();
+            let u_prev_rect_name = CString::new("u_previous_rect").unwrap();
+            let u_wrap_name = CString::new("u_wrap").unwrap();
+            let u_buf_matrix_name = CString::new("u_buffer_matrix").unwrap();
+            let u_buf_offset_name = CString::new("u_buffer_offset").unwrap();
+            let u_linear_name = CString::new("u_linear").unwrap();
+            let u_dither_name = CString::new("u_dither").unwrap();
 
             let u_texture = gl::GetUniformLocation(program, u_tex_name.as_ptr());
             let u_texture_prev = gl::GetUniformLocation(program, u_tex_prev_name.as_ptr());
             let u_progress = gl::GetUniformLocation(program, u_prog_name.as_ptr());
-            let u_transition_type = gl::GetUniformLocation(program, u_trans_name.as_ptr());
+            let u_resolution = gl::GetUniformLocation(program, u_res_name.as_ptr());
+            let u_origin = gl::GetUniformLocation(program, u_origin_name.as_ptr());
+            let u_direction = gl::GetUniformLocation(program, u_dir_name.as_ptr());
+            let u_params = gl::GetUniformLocation(program, u_params_name.as_ptr());
+            let u_flip_y = gl::GetUniformLocation(program, u_flip_name.as_ptr());
+            let u_current_rect = gl::GetUniformLocation(program, u_cur_rect_name.as_ptr());
+            let u_previous_rect = gl::GetUniformLocation(program, u_prev_rect_name.as_ptr());
+            let u_wrap = gl::GetUniformLocation(program, u_wrap_name.as_ptr());
+            let u_buffer_matrix = gl::GetUniformLocation(program, u_buf_matrix_name.as_ptr());
+            let u_buffer_offset = gl::GetUniformLocation(program, u_buf_offset_name.as_ptr());
+            let u_linear = gl::GetUniformLocation(program, u_linear_name.as_ptr());
+            let u_dither = gl::GetUniformLocation(program, u_dither_name.as_ptr());
 
-            info!("Shader program compiled successfully");
+            info!("Shader program for {:?} compiled successfully", transition);
 
             Ok(Self {
                 program,
@@ -73,7 +117,18 @@ impl ShaderProgram {
                 u_texture,
                 u_texture_prev,
                 u_progress,
-                u_transition_type,
+                u_resolution,
+                u_origin,
+                u_direction,
+                u_params,
+                u_flip_y,
+                u_current_rect,
+                u_previous_rect,
+                u_wrap,
+                u_buffer_matrix,
+                u_buffer_offset,
+                u_linear,
+                u_dither,
             })
         }
     }
@@ -120,6 +175,10 @@ pub struct Texture {
     pub id: u32,
     pub width: u32,
     pub height: u32,
+    /// All rows are uploaded, see `GlResources::upload`
+    ready: Cell<bool>,
+    /// Mip levels have been generated (`filter = "mipmap"`)
+    mipmapped: Cell<bool>,
 }
 
 impl Texture {
@@ -134,6 +193,7 @@ impl Texture {
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
 
+            clear_gl_errors();
             gl::TexImage2D(
                 gl::TEXTURE_2D,
                 0,
@@ -147,10 +207,146 @@ impl Texture {
             );
 
             gl::BindTexture(gl::TEXTURE_2D, 0);
+            check_upload(id, width, height)?;
         }
 
         debug!("Created texture {} ({}x{})", id, width, height);
-        Ok(Self { id, width, height })
+        Ok(Self {
+            id,
+            width,
+            height,
+            ready: Cell::new(true),
+            mipmapped: Cell::new(false),
+        })
+    }
+
+    /// Allocate an uninitialized texture to render into
+    pub fn empty(width: u32, height: u32) -> Result<Self> {
+        let mut id = 0;
+        unsafe {
+            gl::GenTextures(1, &mut id);
+            gl::BindTexture(gl::TEXTURE_2D, id);
+
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+
+            clear_gl_errors();
+            gl::TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::RGBA as i32,
+                width as i32,
+                height as i32,
+                0,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                ptr::null(),
+            );
+
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            check_upload(id, width, height)?;
+        }
+
+        debug!("Created render target texture {} ({}x{})", id, width, height);
+        Ok(Self {
+            id,
+            width,
+            height,
+            ready: Cell::new(true),
+            mipmapped: Cell::new(false),
+        })
+    }
+
+    /// Allocate a texture to be filled in strips with `upload_rows`
+    pub fn for_upload(width: u32, height: u32) -> Result<Self> {
+        let texture = Self::empty(width, height)?;
+        texture.ready.set(false);
+        Ok(texture)
+    }
+
+    /// Upload `rows` rows of a tightly packed RGBA image starting at `first_row`
+    pub fn upload_rows(&self, image: &RgbaImage, first_row: u32, rows: u32) -> Result<()> {
+        let row_bytes = self.width as usize * 4;
+        let start = first_row as usize * row_bytes;
+        let strip = &image.as_raw()[start..start + rows as usize * row_bytes];
+
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            clear_gl_errors();
+            gl::TexSubImage2D(
+                gl::TEXTURE_2D,
+                0,
+                0,
+                first_row as i32,
+                self.width as i32,
+                rows as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                strip.as_ptr() as *const _,
+            );
+            let error = gl::GetError();
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+
+            if error != gl::NO_ERROR {
+                return Err(eyre!(
+                    "Failed to upload rows {}..{} of texture {}: GL error 0x{:x}",
+                    first_row,
+                    first_row + rows,
+                    self.id,
+                    error
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the image data is complete and the texture can be shown
+    pub fn is_ready(&self) -> bool {
+        self.ready.get()
+    }
+
+    pub(super) fn set_ready(&self) {
+        self.ready.set(true);
+    }
+
+    /// Generate the mip levels of a complete texture, once
+    fn generate_mipmaps(&self) {
+        if self.mipmapped.replace(true) {
+            return;
+        }
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, self.id);
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+        }
+        debug!("Generated mipmaps for texture {}", self.id);
+    }
+
+    /// GLES2 only guarantees mipmaps for power-of-two sizes
+    fn is_power_of_two(&self) -> bool {
+        self.width.is_power_of_two() && self.height.is_power_of_two()
+    }
+
+    /// Bytes of GPU memory, a third more with mip levels
+    pub fn bytes(&self) -> u64 {
+        let base = self.width as u64 * self.height as u64 * 4;
+        if self.mipmapped.get() {
+            base * 4 / 3
+        } else {
+            base
+        }
+    }
+
+    /// Set how the texture bound to the active unit is sampled
+    unsafe fn apply_filter(&self, filter: TextureFilter) {
+        let (min, mag) = match filter {
+            TextureFilter::Nearest => (gl::NEAREST, gl::NEAREST),
+            TextureFilter::Mipmap if self.mipmapped.get() => (gl::LINEAR_MIPMAP_LINEAR, gl::LINEAR),
+            _ => (gl::LINEAR, gl::LINEAR),
+        };
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, mag as i32);
     }
 
     /// Create a solid color texture (for testing/fallback)
@@ -160,6 +356,33 @@ impl Texture {
     }
 }
 
+/// Drop errors left over from earlier calls, so they aren't blamed on the next one
+unsafe fn clear_gl_errors() {
+    while gl::GetError() != gl::NO_ERROR {}
+}
+
+/// Check that a texture upload succeeded, deleting the texture if it didn't
+unsafe fn check_upload(id: u32, width: u32, height: u32) -> Result<()> {
+    let error = gl::GetError();
+    if error == gl::NO_ERROR {
+        return Ok(());
+    }
+
+    gl::DeleteTextures(1, &id);
+    let reason = match error {
+        gl::OUT_OF_MEMORY => "out of GPU memory",
+        gl::INVALID_VALUE => "size not supported",
+        _ => "GL error",
+    };
+    Err(eyre!(
+        "Failed to upload {}x{} texture: {} (0x{:x})",
+        width,
+        height,
+        reason,
+        error
+    ))
+}
+
 impl Drop for Texture {
     fn drop(&mut self) {
         unsafe {
@@ -250,98 +473,545 @@ impl Drop for QuadBuffer {
 
 /// Main renderer that manages wallpaper display and transitions
 pub struct Renderer {
-    shader: ShaderProgram,
-    quad: QuadBuffer,
-    current_texture: Option<Texture>,
-    previous_texture: Option<Texture>,
-    transition_type: TransitionType,
+    resources: Rc<GlResources>,
+    /// Programs of the effects this renderer has used
+    programs: HashMap<TransitionType, Rc<ShaderProgram>>,
+    quad: Rc<QuadBuffer>,
+    current_texture: Option<Rc<Texture>>,
+    previous_texture: Option<Rc<Texture>>,
+    /// Next wallpaper while its texture is uploaded, with the transition
+    /// origin to use once it's complete
+    loading: Option<(ImageKey, Rc<Texture>, Option<[f32; 2]>)>,
+    /// Image the current texture was made from
+    current_key: Option<ImageKey>,
+    transition: TransitionSettings,
+    /// Show the next wallpaper without a transition, see `skip_transition`
+    skip_transition: bool,
+    /// Effect of the transition in progress (resolved from `random`)
+    active_transition: TransitionType,
+    /// Origin of the transition in progress, 0..1 from the top-left
+    transition_origin: [f32; 2],
     transition_progress: f32,
-    transition_time_ms: u32,
     background_mode: BackgroundMode,
+    filter: TextureFilter,
+    /// Blend in linear light rather than on sRGB values
+    linear_blending: bool,
+    dither: bool,
+    /// Mapping of the previous texture, frozen when the transition started
+    previous_rect: UvRect,
+    motion: Option<MotionConfig>,
+    /// Pan and zoom of the current wallpaper when `motion` is set
+    motion_path: Option<MotionPath>,
+    motion_elapsed_ms: u64,
+    /// How long one motion pass over an image takes
+    motion_span_ms: u64,
+    parallax: Option<ParallaxConfig>,
+    /// Scroll position the running parallax animation started from
+    parallax_from: f32,
+    /// Scroll position of the active workspace, unknown until the first one
+    parallax_to: Option<f32>,
+    parallax_progress: f32,
+    /// Image the current one fades into as the sun goes down (`daylight`)
+    night: Option<(ImageKey, Rc<Texture>)>,
+    /// Next night image while its texture is uploaded
+    night_loading: Option<(ImageKey, Rc<Texture>)>,
+    /// Weight of the current image against `night`, 1.0 in full daylight
+    daylight: f32,
+    /// Buffer transform of the surface, the viewport is in buffer pixels
+    transform: Transform,
     viewport_width: u32,
     viewport_height: u32,
 }
 
 impl Renderer {
     pub fn new(
-        transition_type: TransitionType,
-        transition_time_ms: u32,
+        resources: Rc<GlResources>,
+        transition: TransitionSettings,
         background_mode: BackgroundMode,
     ) -> Result<Self> {
-        let shader = ShaderProgram::new()?;
-        let quad = QuadBuffer::new()?;
+        let mut programs = HashMap::new();
+        programs.insert(TransitionType::None, resources.program(TransitionType::None)?);
+        let quad = resources.quad()?;
 
         Ok(Self {
-            shader,
+            resources,
+            programs,
             quad,
             current_texture: None,
             previous_texture: None,
-            transition_type,
+            loading: None,
+            current_key: None,
+            transition,
+            skip_transition: false,
+            active_transition: TransitionType::None,
+            transition_origin: [0.5, 0.5],
             transition_progress: 1.0, // Start with no transition
-            transition_time_ms,
             background_mode,
+            filter: TextureFilter::default(),
+            linear_blending: true,
+            dither: true,
+            previous_rect: UvRect::IDENTITY,
+            motion: None,
+            motion_path: None,
+            motion_elapsed_ms: 0,
+            motion_span_ms: 0,
+            parallax: None,
+            parallax_from: 0.0,
+            parallax_to: None,
+            parallax_progress: 1.0,
+            night: None,
+            night_loading: None,
+            daylight: 1.0,
+            transform: Transform::Normal,
             viewport_width: 0,
             viewport_height: 0,
         })
     }
 
-    /// Set viewport size
+    /// Set viewport size, applied whenever this output draws
     pub fn set_viewport(&mut self, width: u32, height: u32) {
         self.viewport_width = width;
         self.viewport_height = height;
+    }
+
+    /// Point GL at this output's viewport. The viewport is state of the
+    /// context all outputs share, so it's set again for every frame.
+    fn apply_viewport(&self) {
         unsafe {
-            gl::Viewport(0, 0, width as i32, height as i32);
+            gl::Viewport(0, 0, self.viewport_width as i32, self.viewport_height as i32);
+        }
+    }
+
+    /// Sample wallpapers with this filter from now on. Lanczos only applies
+    /// to images loaded afterwards.
+    pub fn set_filter(&mut self, filter: TextureFilter) {
+        self.filter = filter;
+    }
+
+    /// Draw for a buffer committed with this `set_buffer_transform`
+    pub fn set_transform(&mut self, transform: Transform) {
+        self.transform = transform;
+    }
+
+    /// Viewport size as seen on the output, after undoing the transform
+    fn surface_size(&self) -> (u32, u32) {
+        if BufferTransform::swaps_axes(self.transform) {
+            (self.viewport_height, self.viewport_width)
+        } else {
+            (self.viewport_width, self.viewport_height)
         }
     }
 
-    /// Load a new wallpaper from RGBA data
-    pub fn load_wallpaper(&mut self, data: &[u8], width: u32, height: u32) -> Result<()> {
-        let new_texture = Texture::from_rgba(data, width, height)?;
+    /// Replace the transition settings used for subsequent changes
+    pub fn set_transition(&mut self, transition: TransitionSettings) {
+        self.transition = transition;
+    }
+
+    /// Cut to the next wallpaper instead of playing the transition
+    pub fn skip_transition(&mut self) {
+        self.skip_transition = true;
+    }
+
+    /// Change how the wallpaper is fitted to the output
+    pub fn set_background_mode(&mut self, mode: BackgroundMode) {
+        self.background_mode = mode;
+    }
+
+    /// Choose linear light blending and output dithering
+    pub fn set_color_options(&mut self, linear_blending: bool, dither: bool) {
+        self.linear_blending = linear_blending;
+        self.dither = dither;
+    }
+
+    /// Enable or disable the Ken Burns effect. Each image is panned and
+    /// zoomed once over `span`.
+    pub fn set_motion(&mut self, motion: Option<MotionConfig>, span: std::time::Duration) {
+        self.motion_span_ms = span.as_millis().max(1) as u64;
+        if self.motion != motion {
+            self.motion_path = motion.as_ref().map(MotionPath::new);
+            self.motion_elapsed_ms = 0;
+            self.motion = motion;
+        }
+    }
+
+    /// Enable or disable workspace parallax
+    pub fn set_parallax(&mut self, parallax: Option<ParallaxConfig>) {
+        if parallax.is_none() {
+            self.parallax_to = None;
+            self.parallax_progress = 1.0;
+        }
+        self.parallax = parallax;
+    }
+
+    /// Scroll to a new parallax position, 0..1. The first position is taken
+    /// as is, later ones animate from wherever the view is now.
+    pub fn scroll_to(&mut self, position: f32) {
+        if self.parallax.is_none() {
+            return;
+        }
+        if let Some(to) = self.parallax_to {
+            if to == position {
+                return;
+            }
+            self.parallax_from = self.parallax_position();
+            self.parallax_progress = 0.0;
+        } else {
+            self.parallax_from = position;
+        }
+        self.parallax_to = Some(position);
+    }
+
+    /// Scroll position shown right now
+    fn parallax_position(&self) -> f32 {
+        let Some(to) = self.parallax_to else {
+            return 0.5;
+        };
+        // Ease out, so quick successive switches don't stall in between
+        let t = 1.0 - (1.0 - self.parallax_progress).powi(3);
+        self.parallax_from + (to - self.parallax_from) * t
+    }
+
+    /// Screen to texture mapping of the current wallpaper right now
+    fn current_rect(&self) -> UvRect {
+        let Some(current) = &self.current_texture else {
+            return UvRect::IDENTITY;
+        };
+        let mut rect = UvRect::fit(
+            self.background_mode,
+            (current.width, current.height),
+            self.surface_size(),
+        );
+        if let Some(parallax) = &self.parallax {
+            rect = rect.scroll(parallax.vertical, self.parallax_position());
+        }
+
+        match &self.motion_path {
+            Some(path) => {
+                let t = self.motion_elapsed_ms as f32 / self.motion_span_ms as f32;
+                let (zoom, center) = path.at(t);
+                rect.window(zoom, center)
+            }
+            None => rect,
+        }
+    }
+
+    /// Start the transition to an uploaded texture
+    ///
+    /// `origin` overrides the configured transition origin for this change.
+    fn show_texture(
+        &mut self,
+        key: ImageKey,
+        new_texture: Rc<Texture>,
+        origin: Option<[f32; 2]>,
+    ) -> Result<()> {
+        // The same image prescaled for a new size replaces the old one as is
+        let rescaled = self
+            .current_key
+            .as_ref()
+            .is_some_and(|current| current.same_file(&key) && *current != key);
 
         // Move current to previous for transition
-        if self.current_texture.is_some() && self.transition_type != TransitionType::None {
-            self.previous_texture = self.current_texture.take();
+        let effect = if std::mem::take(&mut self.skip_transition) {
+            TransitionType::None
+        } else {
+            self.transition.pick()
+        };
+        if self.current_texture.is_some() && effect != TransitionType::None && !rescaled {
+            if !self.programs.contains_key(&effect) {
+                self.programs.insert(effect, self.resources.program(effect)?);
+            }
+
+            // Continue from whatever is on screen right now, so interrupting
+            // a running transition doesn't jump back to the old wallpaper
+            let snapshot = if self.is_transitioning() || self.is_blending() {
+                match self.capture_frame() {
+                    Ok(texture) => Some(Rc::new(texture)),
+                    Err(e) => {
+                        warn!("Failed to capture transition frame: {}", e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
+            debug!("Starting {:?} transition", effect);
+            // A snapshot already has the old framing baked in
+            self.previous_rect = if snapshot.is_some() {
+                UvRect::IDENTITY
+            } else {
+                self.current_rect()
+            };
+            self.previous_texture = snapshot.or_else(|| self.current_texture.take());
+            self.active_transition = effect;
+            self.transition_origin = origin.unwrap_or_else(|| self.transition.default_origin());
             self.transition_progress = 0.0;
         }
 
+        info!(
+            "Loaded new wallpaper ({}x{})",
+            new_texture.width, new_texture.height
+        );
         self.current_texture = Some(new_texture);
-        info!("Loaded new wallpaper ({}x{})", width, height);
+        self.current_key = Some(key);
+        if !rescaled {
+            self.motion_path = self.motion.as_ref().map(MotionPath::new);
+            self.motion_elapsed_ms = 0;
+        }
+
+        Ok(())
+    }
+
+    /// Registry key of an image, distinct per prescaled size with
+    /// `filter = "lanczos"`
+    fn image_key(&self, path: &Path) -> ImageKey {
+        ImageKey::for_path(path).prescaled_for(self.prescale_for())
+    }
+
+    /// Mode and viewport to prescale images for, see `crate::image::prescale`
+    pub fn prescale_for(&self) -> Option<(BackgroundMode, (u32, u32))> {
+        (self.filter == TextureFilter::Lanczos).then(|| (self.background_mode, self.surface_size()))
+    }
+
+    /// Show an image another output already uploaded (or is uploading).
+    /// Returns false if it has to be decoded and passed to `load_image`.
+    pub fn show_uploaded(&mut self, path: &Path, origin: Option<[f32; 2]>) -> Result<bool> {
+        let key = self.image_key(path);
+        match self.resources.texture(&key) {
+            Some(texture) => {
+                self.show_when_ready(key, texture, origin)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Upload a decoded image in strips across the next frames (see
+    /// `upload_step`), then transition to it
+    pub fn load_image(
+        &mut self,
+        path: &Path,
+        image: Arc<RgbaImage>,
+        origin: Option<[f32; 2]>,
+    ) -> Result<()> {
+        let key = self.image_key(path);
+        let texture = self.resources.upload(key.clone(), image)?;
+        self.show_when_ready(key, texture, origin)
+    }
+
+    fn show_when_ready(
+        &mut self,
+        key: ImageKey,
+        texture: Rc<Texture>,
+        origin: Option<[f32; 2]>,
+    ) -> Result<()> {
+        if texture.is_ready() {
+            self.loading = None;
+            self.show_texture(key, texture, origin)
+        } else {
+            debug!("Waiting for texture {} to finish uploading", texture.id);
+            self.loading = Some((key, texture, origin));
+            Ok(())
+        }
+    }
+
+    /// Upload the next strip of pending textures, whichever output waits
+    /// for them, and start the transition (or the blend with the night
+    /// image) once this renderer's is complete
+    pub fn upload_step(&mut self) -> Result<()> {
+        self.resources.upload_step();
+        if !self.is_uploading() {
+            return Ok(());
+        }
+
+        let night = self.night_upload_step();
+        self.wallpaper_upload_step().and(night)
+    }
+
+    fn wallpaper_upload_step(&mut self) -> Result<()> {
+        let Some((_, texture, _)) = &self.loading else {
+            return Ok(());
+        };
+        if texture.is_ready() {
+            let (key, texture, origin) = self.loading.take().unwrap();
+            self.show_texture(key, texture, origin)
+        } else if !self.resources.is_uploading(texture) {
+            let (_, texture, _) = self.loading.take().unwrap();
+            Err(eyre!("Upload of {}x{} texture failed", texture.width, texture.height))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn night_upload_step(&mut self) -> Result<()> {
+        let Some((_, texture)) = &self.night_loading else {
+            return Ok(());
+        };
+        if texture.is_ready() {
+            let (key, texture) = self.night_loading.take().unwrap();
+            self.set_night(key, texture)
+        } else if !self.resources.is_uploading(texture) {
+            let (_, texture) = self.night_loading.take().unwrap();
+            Err(eyre!("Upload of {}x{} night texture failed", texture.width, texture.height))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether `path` is the night image already, or being uploaded as it
+    pub fn has_night_image(&self, path: &Path) -> bool {
+        let key = self.image_key(path);
+        self.night.iter().chain(&self.night_loading).any(|(night, _)| *night == key)
+    }
+
+    /// Use an image another output already uploaded (or is uploading) as the
+    /// night image. Returns false if it has to be decoded and passed to
+    /// `load_night_image`.
+    pub fn show_uploaded_night(&mut self, path: &Path) -> Result<bool> {
+        let key = self.image_key(path);
+        match self.resources.texture(&key) {
+            Some(texture) => {
+                self.night_when_ready(key, texture)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Upload a decoded night image in strips across the next frames, like
+    /// `load_image`, then blend with it
+    pub fn load_night_image(&mut self, path: &Path, image: Arc<RgbaImage>) -> Result<()> {
+        let key = self.image_key(path);
+        let texture = self.resources.upload(key.clone(), image)?;
+        self.night_when_ready(key, texture)
+    }
+
+    fn night_when_ready(&mut self, key: ImageKey, texture: Rc<Texture>) -> Result<()> {
+        if texture.is_ready() {
+            self.night_loading = None;
+            self.set_night(key, texture)
+        } else {
+            self.night_loading = Some((key, texture));
+            Ok(())
+        }
+    }
 
+    fn set_night(&mut self, key: ImageKey, texture: Rc<Texture>) -> Result<()> {
+        if !self.programs.contains_key(&TransitionType::Fade) {
+            let program = self.resources.program(TransitionType::Fade)?;
+            self.programs.insert(TransitionType::Fade, program);
+        }
+        debug!("Blending with night image ({}x{})", texture.width, texture.height);
+        self.night = Some((key, texture));
         Ok(())
     }
 
-    /// Load wallpaper from image file
-    pub fn load_wallpaper_from_file(&mut self, path: &std::path::Path) -> Result<()> {
-        info!("Loading wallpaper from: {:?}", path);
+    /// Stop blending with a night image
+    pub fn clear_night(&mut self) {
+        self.night = None;
+        self.night_loading = None;
+    }
 
-        let img = image::open(path)
-            .wrap_err_with(|| format!("Failed to open image: {:?}", path))?;
+    /// Weight of the current image against the night image, 0.0 to 1.0
+    pub fn set_daylight(&mut self, daylight: f32) {
+        self.daylight = daylight.clamp(0.0, 1.0);
+    }
 
-        let rgba = img.to_rgba8();
-        let (width, height) = rgba.dimensions();
+    /// Whether the night image shows through the current one
+    fn is_blending(&self) -> bool {
+        self.night.is_some() && self.daylight < 1.0 && self.current_texture.is_some()
+    }
 
-        self.load_wallpaper(rgba.as_raw(), width, height)
+    /// Screen to texture mapping of the night image
+    fn night_rect(&self) -> UvRect {
+        let Some((_, night)) = &self.night else {
+            return UvRect::IDENTITY;
+        };
+        let rect = UvRect::fit(self.background_mode, (night.width, night.height), self.surface_size());
+        match &self.parallax {
+            Some(parallax) => rect.scroll(parallax.vertical, self.parallax_position()),
+            None => rect,
+        }
     }
 
-    /// Update transition progress
+    /// Update transition progress and motion
     pub fn update(&mut self, delta_ms: u32) -> bool {
+        if self.motion_path.is_some() {
+            self.motion_elapsed_ms =
+                (self.motion_elapsed_ms + delta_ms as u64).min(self.motion_span_ms);
+        }
+
+        let scrolling = self.is_scrolling();
+        if let Some(parallax) = self.parallax.as_ref().filter(|_| scrolling) {
+            let step = delta_ms as f32 / parallax.time.max(1) as f32;
+            self.parallax_progress = (self.parallax_progress + step).min(1.0);
+        }
+
         if self.transition_progress < 1.0 {
-            let step = delta_ms as f32 / self.transition_time_ms as f32;
+            let step = delta_ms as f32 / self.transition.duration_ms.max(1) as f32;
             self.transition_progress = (self.transition_progress + step).min(1.0);
 
             // Clean up previous texture when transition completes
             if self.transition_progress >= 1.0 {
                 self.previous_texture = None;
+                self.active_transition = TransitionType::None;
             }
 
             true // Still animating
         } else {
-            false // No animation
+            scrolling || self.is_motion_active()
         }
     }
 
     /// Render the current wallpaper
     pub fn render(&self) {
+        self.apply_viewport();
+        self.draw(false);
+    }
+
+    /// Render the frame as it currently looks into an offscreen framebuffer,
+    /// upright and in surface orientation
+    fn capture_frame(&self) -> Result<Texture> {
+        let (width, height) = self.surface_size();
+        let texture = Texture::empty(width, height)?;
+
+        unsafe {
+            let mut fbo = 0;
+            gl::GenFramebuffers(1, &mut fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+            gl::FramebufferTexture2D(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::TEXTURE_2D,
+                texture.id,
+                0,
+            );
+
+            let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+            if status == gl::FRAMEBUFFER_COMPLETE {
+                gl::Viewport(0, 0, width as i32, height as i32);
+                self.draw(true);
+                self.apply_viewport();
+            }
+
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::DeleteFramebuffers(1, &fbo);
+
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                return Err(eyre!("Framebuffer incomplete: 0x{:x}", status));
+            }
+        }
+
+        debug!(
+            "Captured transition frame at {:.0}%",
+            self.transition_progress * 100.0
+        );
+        Ok(texture)
+    }
+
+    /// Draw the wallpaper into the bound framebuffer. Offscreen targets are
+    /// drawn upside down so their rows match uploaded images.
+    fn draw(&self, offscreen: bool) {
         unsafe {
             gl::ClearColor(0.0, 0.0, 0.0, 1.0);
             gl::Clear(gl::COLOR_BUFFER_BIT);
@@ -351,55 +1021,189 @@ impl Renderer {
                 return;
             };
 
-            gl::UseProgram(self.shader.program);
+            // Between transitions the night image takes the place of the
+            // previous one, faded in by the time of day
+            let blend = self
+                .night
+                .as_ref()
+                .filter(|_| self.previous_texture.is_none() && self.is_blending());
+            let (effect, previous, previous_rect) = match (&self.previous_texture, blend) {
+                (Some(prev), _) => (self.active_transition, Some(prev), self.previous_rect),
+                (None, Some((_, night))) => (TransitionType::Fade, Some(night), self.night_rect()),
+                (None, None) => (TransitionType::None, None, self.previous_rect),
+            };
+            let Some(shader) = self.programs.get(&effect) else {
+                return;
+            };
+
+            gl::UseProgram(shader.program);
+
+            let mipmaps = self.filter == TextureFilter::Mipmap
+                && (current.is_power_of_two() || self.resources.npot_mipmaps());
+            if mipmaps {
+                current.generate_mipmaps();
+            }
 
             // Bind current texture to unit 0
             gl::ActiveTexture(gl::TEXTURE0);
             gl::BindTexture(gl::TEXTURE_2D, current.id);
-            gl::Uniform1i(self.shader.u_texture, 0);
+            current.apply_filter(self.filter);
+            gl::Uniform1i(shader.u_texture, 0);
 
-            // Bind previous texture to unit 1 (if transitioning)
-            if let Some(prev) = &self.previous_texture {
+            // Bind previous texture to unit 1 (if transitioning or blending)
+            if let Some(prev) = previous {
                 gl::ActiveTexture(gl::TEXTURE1);
                 gl::BindTexture(gl::TEXTURE_2D, prev.id);
-                gl::Uniform1i(self.shader.u_texture_prev, 1);
+                prev.apply_filter(self.filter);
+                gl::Uniform1i(shader.u_texture_prev, 1);
             }
 
             // Set uniforms
-            gl::Uniform1f(self.shader.u_progress, self.transition_progress);
-            gl::Uniform1i(
-                self.shader.u_transition_type,
-                self.transition_type_to_int(),
+            let progress = match (&self.previous_texture, previous) {
+                (Some(_), _) => transition::ease(effect, self.transition_progress),
+                (None, Some(_)) => self.daylight,
+                (None, None) => 1.0,
+            };
+            let direction = self.transition.direction(effect);
+            let params = self.transition.params(effect);
+
+            gl::Uniform1f(shader.u_progress, progress);
+            let (width, height) = self.surface_size();
+            gl::Uniform2f(shader.u_resolution, width as f32, height as f32);
+            gl::Uniform2f(
+                shader.u_origin,
+                self.transition_origin[0],
+                self.transition_origin[1],
+            );
+            gl::Uniform2f(shader.u_direction, direction[0], direction[1]);
+            gl::Uniform4f(shader.u_params, params[0], params[1], params[2], params[3]);
+            gl::Uniform1f(shader.u_flip_y, if offscreen { -1.0 } else { 1.0 });
+
+            // Offscreen targets are always upright
+            let buffer = BufferTransform::new(if offscreen {
+                Transform::Normal
+            } else {
+                self.transform
+            });
+            gl::UniformMatrix2fv(shader.u_buffer_matrix, 1, gl::FALSE, buffer.matrix.as_ptr());
+            gl::Uniform2f(shader.u_buffer_offset, buffer.offset[0], buffer.offset[1]);
+
+            // Snapshots are decoded again later, noise would only add up
+            let dither = self.dither && !offscreen;
+            gl::Uniform1f(shader.u_linear, self.linear_blending as i32 as f32);
+            gl::Uniform1f(shader.u_dither, dither as i32 as f32);
+
+            let current_rect = self.current_rect();
+            let rect = current_rect.as_uniform();
+            gl::Uniform4f(shader.u_current_rect, rect[0], rect[1], rect[2], rect[3]);
+            let rect = previous_rect.as_uniform();
+            gl::Uniform4f(shader.u_previous_rect, rect[0], rect[1], rect[2], rect[3]);
+            gl::Uniform2f(
+                shader.u_wrap,
+                current_rect.wrap as i32 as f32,
+                previous_rect.wrap as i32 as f32,
             );
 
             // Draw fullscreen quad
-            self.quad.bind(&self.shader);
+            self.quad.bind(shader);
             self.quad.draw();
 
+            gl::ActiveTexture(gl::TEXTURE1);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            gl::ActiveTexture(gl::TEXTURE0);
             gl::BindTexture(gl::TEXTURE_2D, 0);
             gl::UseProgram(0);
         }
     }
 
-    fn transition_type_to_int(&self) -> i32 {
-        match self.transition_type {
-            TransitionType::None => 0,
-            TransitionType::Fade => 1,
-            TransitionType::Slide => 2, // slide left
-            TransitionType::Wipe => 2,  // same as slide for now
-            TransitionType::Crossfade => 1, // same as fade
-        }
-    }
-
     /// Check if currently in a transition
     pub fn is_transitioning(&self) -> bool {
         self.transition_progress < 1.0
     }
 
+    /// Draw the current frame and read it back as XRGB8888 rows, top-down,
+    /// for handing the output over to a wl_shm buffer
+    pub fn read_frame(&self) -> Vec<u8> {
+        let (width, height) = (self.viewport_width as usize, self.viewport_height as usize);
+        let mut rgba = vec![0u8; width * height * 4];
+
+        self.apply_viewport();
+        self.draw(false);
+        unsafe {
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::ReadPixels(
+                0,
+                0,
+                width as i32,
+                height as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                rgba.as_mut_ptr() as *mut _,
+            );
+        }
+
+        // GL rows start at the bottom, wl_shm is little-endian BGRX
+        let mut xrgb = vec![0u8; rgba.len()];
+        for (y, row) in rgba.chunks_exact(width * 4).enumerate() {
+            let out = &mut xrgb[(height - 1 - y) * width * 4..][..width * 4];
+            for (src, dst) in row.chunks_exact(4).zip(out.chunks_exact_mut(4)) {
+                dst.copy_from_slice(&[src[2], src[1], src[0], 255]);
+            }
+        }
+        xrgb
+    }
+
+    /// Textures held for this output's wallpapers, some possibly shared
+    /// with other outputs
+    pub fn textures(&self) -> impl Iterator<Item = &Texture> {
+        [
+            self.current_texture.as_ref(),
+            self.previous_texture.as_ref(),
+            self.loading.as_ref().map(|(_, texture, _)| texture),
+            self.night.as_ref().map(|(_, texture)| texture),
+            self.night_loading.as_ref().map(|(_, texture)| texture),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|texture| &**texture)
+    }
+
+    /// Bytes of texture memory held for this output's wallpapers
+    pub fn texture_bytes(&self) -> u64 {
+        self.textures().map(Texture::bytes).sum()
+    }
+
+    /// Whether a wallpaper (or solid color) is on screen
+    pub fn has_wallpaper(&self) -> bool {
+        self.current_texture.is_some()
+    }
+
+    /// Check if the next wallpaper is still being uploaded
+    pub fn is_loading(&self) -> bool {
+        self.loading.is_some()
+    }
+
+    /// Check if the next wallpaper or night image is still being uploaded
+    pub fn is_uploading(&self) -> bool {
+        self.loading.is_some() || self.night_loading.is_some()
+    }
+
+    /// Check if a workspace switch is still scrolling the wallpaper
+    pub fn is_scrolling(&self) -> bool {
+        self.parallax_progress < 1.0
+    }
+
+    /// Check if the current wallpaper is still panning or zooming
+    pub fn is_motion_active(&self) -> bool {
+        self.motion_path.is_some() && self.motion_elapsed_ms < self.motion_span_ms
+    }
+
     /// Set a solid color as wallpaper (for testing)
     pub fn set_solid_color(&mut self, r: u8, g: u8, b: u8) -> Result<()> {
         let texture = Texture::solid_color(r, g, b)?;
-        self.current_texture = Some(texture);
+        self.current_texture = Some(Rc::new(texture));
+        self.current_key = None;
+        self.previous_texture = None;
         self.transition_progress = 1.0;
         Ok(())
     }
@@ -0,0 +1,300 @@
+use super::renderer::{QuadBuffer, ShaderProgram, Texture};
+use crate::config::{BackgroundMode, TransitionType};
+use color_eyre::eyre::Result;
+use image::RgbaImage;
+use log::{debug, error, info, warn};
+use super::gl;
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Bytes uploaded per `upload_step`, a few milliseconds of transfer so
+/// frames of other outputs don't stall
+const UPLOAD_STRIP_BYTES: usize = 4 << 20;
+
+/// Identity of an image file: the same file, unchanged since it was uploaded
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ImageKey {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    len: u64,
+    /// Mode and viewport the image was prescaled for (`filter = "lanczos"`)
+    prescaled: Option<(BackgroundMode, (u32, u32))>,
+}
+
+impl ImageKey {
+    pub fn for_path(path: &Path) -> Self {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let metadata = std::fs::metadata(&path).ok();
+
+        Self {
+            modified: metadata.as_ref().and_then(|m| m.modified().ok()),
+            len: metadata.map_or(0, |m| m.len()),
+            path,
+            prescaled: None,
+        }
+    }
+
+    pub fn prescaled_for(self, prescaled: Option<(BackgroundMode, (u32, u32))>) -> Self {
+        Self { prescaled, ..self }
+    }
+
+    /// Whether both keys are the same file, however it was prescaled
+    pub fn same_file(&self, other: &ImageKey) -> bool {
+        self.path == other.path && self.modified == other.modified && self.len == other.len
+    }
+}
+
+/// Objects by the image they were made from, held weakly: the users hold
+/// the strong references, so an object is freed once nobody uses it anymore
+struct Registry<T> {
+    entries: RefCell<HashMap<ImageKey, Weak<T>>>,
+}
+
+impl<T> Default for Registry<T> {
+    fn default() -> Self {
+        Self {
+            entries: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T> Registry<T> {
+    fn get(&self, key: &ImageKey) -> Option<Rc<T>> {
+        self.entries.borrow().get(key).and_then(Weak::upgrade)
+    }
+
+    fn insert(&self, key: ImageKey, value: &Rc<T>) {
+        let mut entries = self.entries.borrow_mut();
+        entries.retain(|_, value| value.strong_count() > 0);
+        entries.insert(key, Rc::downgrade(value));
+    }
+}
+
+/// GL objects shared by the renderers of all outputs. They live in the one
+/// shared EGL context, so any output can draw with them.
+#[derive(Default)]
+pub struct GlResources {
+    /// Compiled programs, one per transition effect, built on first use
+    programs: RefCell<HashMap<TransitionType, Rc<ShaderProgram>>>,
+    quad: RefCell<Option<Rc<QuadBuffer>>>,
+    /// Uploaded wallpapers, freed once no output shows them anymore
+    textures: Registry<Texture>,
+    /// Textures being filled in strips, oldest first
+    uploads: RefCell<Vec<Upload>>,
+    /// GL_MAX_TEXTURE_SIZE, queried on first use
+    max_texture_size: Cell<Option<u32>>,
+    /// Mipmaps work on any texture size, queried on first use
+    npot_mipmaps: Cell<Option<bool>>,
+}
+
+/// A texture and the decoded image it's filled from
+struct Upload {
+    texture: Rc<Texture>,
+    image: Arc<RgbaImage>,
+    next_row: u32,
+}
+
+impl GlResources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Program for a transition effect, compiled on first use
+    pub fn program(&self, transition: TransitionType) -> Result<Rc<ShaderProgram>> {
+        match self.programs.borrow_mut().entry(transition) {
+            Entry::Occupied(entry) => Ok(entry.get().clone()),
+            Entry::Vacant(entry) => Ok(entry.insert(Rc::new(ShaderProgram::new(transition)?)).clone()),
+        }
+    }
+
+    /// Fullscreen quad, created on first use
+    pub fn quad(&self) -> Result<Rc<QuadBuffer>> {
+        let mut quad = self.quad.borrow_mut();
+        if let Some(quad) = quad.as_ref() {
+            return Ok(quad.clone());
+        }
+        Ok(quad.insert(Rc::new(QuadBuffer::new()?)).clone())
+    }
+
+    /// Largest texture width or height the GPU accepts
+    pub fn max_texture_size(&self) -> u32 {
+        if let Some(size) = self.max_texture_size.get() {
+            return size;
+        }
+
+        let mut size = 0;
+        unsafe {
+            gl::GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut size);
+        }
+        // GLES2 guarantees at least 64, anything less means the query failed
+        let size = if size >= 64 {
+            info!("Maximum texture size: {}", size);
+            size as u32
+        } else {
+            warn!("GL_MAX_TEXTURE_SIZE query returned {}, assuming 2048", size);
+            2048
+        };
+        self.max_texture_size.set(Some(size));
+        size
+    }
+
+    /// Whether mipmaps can be generated for textures whose sides aren't
+    /// powers of two (GLES3, or GL_OES_texture_npot on GLES2)
+    pub fn npot_mipmaps(&self) -> bool {
+        if let Some(supported) = self.npot_mipmaps.get() {
+            return supported;
+        }
+
+        let gl_string = |name| unsafe {
+            let value = gl::GetString(name);
+            if value.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(value as *const _).to_string_lossy().into_owned()
+            }
+        };
+        let supported = gl_string(gl::VERSION).starts_with("OpenGL ES 3")
+            || gl_string(gl::EXTENSIONS)
+                .split_whitespace()
+                .any(|extension| extension == "GL_OES_texture_npot");
+        if !supported {
+            warn!(
+                "GPU can't mipmap textures with sides that aren't powers of two, \
+                 filter = \"mipmap\" samples those linearly"
+            );
+        }
+        self.npot_mipmaps.set(Some(supported));
+        supported
+    }
+
+    /// Texture of an image another output already uploaded
+    pub fn texture(&self, key: &ImageKey) -> Option<Rc<Texture>> {
+        let texture = self.textures.get(key);
+        if texture.is_some() {
+            debug!("Reusing uploaded texture for {:?}", key.path);
+        }
+        texture
+    }
+
+    /// Texture for a decoded image. A new one is allocated and registered
+    /// right away, but only filled by `upload_step`, see `Texture::is_ready`.
+    pub fn upload(&self, key: ImageKey, image: Arc<RgbaImage>) -> Result<Rc<Texture>> {
+        if let Some(texture) = self.texture(&key) {
+            return Ok(texture);
+        }
+
+        let (width, height) = image.dimensions();
+        let texture = Rc::new(Texture::for_upload(width, height)?);
+        self.register(key, &texture);
+        self.uploads.borrow_mut().push(Upload {
+            texture: texture.clone(),
+            image,
+            next_row: 0,
+        });
+        Ok(texture)
+    }
+
+    /// Whether textures wait for `upload_step`
+    pub fn has_uploads(&self) -> bool {
+        let mut uploads = self.uploads.borrow_mut();
+        // Nobody waits for textures only the upload still holds
+        uploads.retain(|upload| Rc::strong_count(&upload.texture) > 1);
+        !uploads.is_empty()
+    }
+
+    /// Upload the next strips of pending textures, up to `UPLOAD_STRIP_BYTES`
+    pub fn upload_step(&self) {
+        if !self.has_uploads() {
+            return;
+        }
+
+        let mut uploads = self.uploads.borrow_mut();
+        let mut budget = UPLOAD_STRIP_BYTES;
+        while budget > 0 && !uploads.is_empty() {
+            let upload = &mut uploads[0];
+            let texture = &upload.texture;
+            let row_bytes = texture.width as usize * 4;
+            let rows = ((budget / row_bytes) as u32).clamp(1, texture.height - upload.next_row);
+
+            if let Err(e) = texture.upload_rows(&upload.image, upload.next_row, rows) {
+                error!("{:#}", e);
+                uploads.remove(0);
+                continue;
+            }
+            upload.next_row += rows;
+            budget = budget.saturating_sub(rows as usize * row_bytes);
+
+            if upload.next_row == texture.height {
+                debug!("Texture {} ({}x{}) uploaded", texture.id, texture.width, texture.height);
+                texture.set_ready();
+                uploads.remove(0);
+            }
+        }
+    }
+
+    /// Whether a texture that isn't ready yet is still queued for upload
+    pub fn is_uploading(&self, texture: &Rc<Texture>) -> bool {
+        self.uploads
+            .borrow()
+            .iter()
+            .any(|upload| Rc::ptr_eq(&upload.texture, texture))
+    }
+
+    /// Make an uploaded image available to other outputs
+    pub fn register(&self, key: ImageKey, texture: &Rc<Texture>) {
+        self.textures.insert(key, texture);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Duration;
+
+    #[test]
+    fn test_registry() {
+        let registry = Registry::default();
+        let key = ImageKey::for_path(Path::new("/walls/a.png"));
+        let other = ImageKey::for_path(Path::new("/walls/b.png"));
+
+        let texture = Rc::new(7);
+        registry.insert(key.clone(), &texture);
+        assert!(Rc::ptr_eq(&registry.get(&key).unwrap(), &texture));
+        assert!(registry.get(&other).is_none());
+
+        drop(texture);
+        assert!(registry.get(&key).is_none());
+    }
+
+    #[test]
+    fn test_image_key() {
+        let path = std::env::temp_dir().join(format!("canviz-key-{}.png", std::process::id()));
+        fs::write(&path, "image").unwrap();
+        let key = ImageKey::for_path(&path);
+        let unchanged = ImageKey::for_path(&path);
+
+        fs::write(&path, "longer image").unwrap();
+        let resized = ImageKey::for_path(&path);
+
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60)).unwrap();
+        let touched = ImageKey::for_path(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(key, unchanged);
+        assert!(!key.same_file(&resized));
+        assert_eq!(resized.len, touched.len);
+        assert!(!resized.same_file(&touched));
+        // Prescaling makes a different key for the same file
+        let prescaled = touched.clone().prescaled_for(Some((BackgroundMode::Cover, (1920, 1080))));
+        assert!(prescaled.same_file(&touched));
+        assert_ne!(prescaled, touched);
+    }
+}
@@ -1,54 +1,114 @@
 #version 100
+// Linear light needs more precision than mediump offers in the shadows
+#ifdef GL_FRAGMENT_PRECISION_HIGH
+precision highp float;
+#else
 precision mediump float;
+#endif
+
+// Common part of every wallpaper program. One module from transitions/
+// is appended to this source and provides transition() for the effect
+// selected by TransitionType.
 
 varying vec2 v_texcoord;
 
 uniform sampler2D u_texture;
 uniform sampler2D u_texture_prev;
-uniform float u_progress;      // Transition progress 0.0 to 1.0
-uniform int u_transition_type; // 0=none, 1=fade, 2=slide_left, 3=slide_right, 4=slide_up, 5=slide_down
+uniform float u_progress;     // Transition progress 0.0 to 1.0 (eased)
+uniform vec2 u_resolution;    // Viewport size in pixels
+uniform vec2 u_origin;        // Transition origin, 0..1 from top-left
+uniform vec2 u_direction;     // Unit direction for slide/wipe/blinds
+uniform vec4 u_params;        // Effect specific parameters, see modules
+uniform vec4 u_current_rect;  // Screen to texture mapping: offset in xy, scale in zw
+uniform vec4 u_previous_rect;
+uniform vec2 u_wrap;          // Tile current (x) / previous (y) instead of letterboxing
+uniform float u_linear;       // 1.0 to blend in linear light instead of sRGB
+uniform float u_dither;       // Dither amplitude in output steps, 0.0 = off
 
-void main() {
-    vec4 current_color = texture2D(u_texture, v_texcoord);
+// sRGB transfer functions, mirrored by render/color.rs
+vec3 srgb_to_linear(vec3 c) {
+    vec3 low = c / 12.92;
+    vec3 high = pow((c + 0.055) / 1.055, vec3(2.4));
+    return mix(low, high, step(vec3(0.04045), c));
+}
 
-    // No transition or transition complete
-    if (u_transition_type == 0 || u_progress >= 1.0) {
-        gl_FragColor = current_color;
-        return;
+vec3 linear_to_srgb(vec3 c) {
+    vec3 low = c * 12.92;
+    vec3 high = 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055;
+    return mix(low, high, step(vec3(0.0031308), c));
+}
+
+vec4 decode(vec4 texel) {
+    if (u_linear > 0.5) {
+        return vec4(srgb_to_linear(texel.rgb), texel.a);
     }
+    return texel;
+}
 
-    vec4 prev_color = texture2D(u_texture_prev, v_texcoord);
+// Interleaved gradient noise, spreads quantization error evenly without a
+// noise texture. Returns -0.5..0.5.
+float dither_noise(vec2 pixel) {
+    return fract(52.98292 * fract(dot(pixel, vec2(0.06711056, 0.00583715)))) - 0.5;
+}
 
-    // Fade transition
-    if (u_transition_type == 1) {
-        gl_FragColor = mix(prev_color, current_color, u_progress);
-        return;
+vec4 encode(vec4 color) {
+    vec3 rgb = color.rgb;
+    if (u_linear > 0.5) {
+        rgb = linear_to_srgb(clamp(rgb, 0.0, 1.0));
+    }
+    rgb += dither_noise(gl_FragCoord.xy) * u_dither / 255.0;
+    return vec4(rgb, color.a);
+}
+
+bool in_bounds(vec2 uv) {
+    return uv.x >= 0.0 && uv.x <= 1.0 && uv.y >= 0.0 && uv.y <= 1.0;
+}
+
+// Samplers take screen coordinates and apply the background mode (and any
+// pan/zoom) of their image
+vec4 sample_current(vec2 uv) {
+    vec2 tex = u_current_rect.xy + uv * u_current_rect.zw;
+    if (u_wrap.x > 0.5) {
+        tex = fract(tex);
+    } else if (!in_bounds(uv) || !in_bounds(tex)) {
+        return vec4(0.0, 0.0, 0.0, 1.0);
     }
+    return decode(texture2D(u_texture, tex));
+}
 
-    // Slide transitions
-    vec2 offset = vec2(0.0);
-    float p = 1.0 - u_progress;
-
-    if (u_transition_type == 2) { // slide left
-        offset = vec2(p, 0.0);
-    } else if (u_transition_type == 3) { // slide right
-        offset = vec2(-p, 0.0);
-    } else if (u_transition_type == 4) { // slide up
-        offset = vec2(0.0, -p);
-    } else if (u_transition_type == 5) { // slide down
-        offset = vec2(0.0, p);
+vec4 sample_previous(vec2 uv) {
+    vec2 tex = u_previous_rect.xy + uv * u_previous_rect.zw;
+    if (u_wrap.y > 0.5) {
+        tex = fract(tex);
+    } else if (!in_bounds(uv) || !in_bounds(tex)) {
+        return vec4(0.0, 0.0, 0.0, 1.0);
     }
+    return decode(texture2D(u_texture_prev, tex));
+}
+
+// Cheap hash for noise based effects, returns 0..1
+float hash(vec2 p) {
+    return fract(sin(dot(p, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+// Distance in pixels from the transition origin, aspect corrected
+float origin_distance(vec2 uv) {
+    return length((uv - u_origin) * u_resolution);
+}
 
-    vec2 current_coord = v_texcoord - offset;
-    vec2 prev_coord = v_texcoord + (vec2(1.0) - abs(offset)) * sign(offset);
-
-    if (current_coord.x >= 0.0 && current_coord.x <= 1.0 &&
-        current_coord.y >= 0.0 && current_coord.y <= 1.0) {
-        gl_FragColor = texture2D(u_texture, current_coord);
-    } else if (prev_coord.x >= 0.0 && prev_coord.x <= 1.0 &&
-               prev_coord.y >= 0.0 && prev_coord.y <= 1.0) {
-        gl_FragColor = texture2D(u_texture_prev, prev_coord);
-    } else {
-        gl_FragColor = vec4(0.0, 0.0, 0.0, 1.0);
+// Distance in pixels from the origin to the furthest screen corner
+float max_origin_distance() {
+    vec2 far = max(u_origin, vec2(1.0) - u_origin) * u_resolution;
+    return length(far);
+}
+
+vec4 transition(vec2 uv, float progress);
+
+void main() {
+    if (u_progress >= 1.0) {
+        gl_FragColor = encode(sample_current(v_texcoord));
+        return;
     }
+
+    gl_FragColor = encode(transition(v_texcoord, u_progress));
 }
@@ -0,0 +1,11 @@
+// Venetian blinds opening along u_direction. u_params.x = number of slats
+vec4 transition(vec2 uv, float progress) {
+    float coord = dot(uv, abs(u_direction));
+    if (u_direction.x < 0.0 || u_direction.y < 0.0) {
+        coord = 1.0 - coord;
+    }
+
+    float slat = fract(coord * max(u_params.x, 1.0));
+    float t = step(slat, progress);
+    return mix(sample_previous(uv), sample_current(uv), t);
+}
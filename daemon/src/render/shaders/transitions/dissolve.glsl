@@ -0,0 +1,8 @@
+// Noise dissolve. u_params.x = edge softness, u_params.y = grain in pixels
+vec4 transition(vec2 uv, float progress) {
+    float grain = max(u_params.y, 1.0);
+    float noise = hash(floor(uv * u_resolution / grain));
+    float soft = max(u_params.x, 0.001);
+    float t = smoothstep(noise - soft, noise, progress * (1.0 + soft));
+    return mix(sample_previous(uv), sample_current(uv), t);
+}
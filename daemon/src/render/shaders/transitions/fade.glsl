@@ -0,0 +1,4 @@
+// Crossfade between the two images
+vec4 transition(vec2 uv, float progress) {
+    return mix(sample_previous(uv), sample_current(uv), progress);
+}
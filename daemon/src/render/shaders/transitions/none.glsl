@@ -0,0 +1,4 @@
+// Instant switch, also used to draw a static wallpaper
+vec4 transition(vec2 uv, float progress) {
+    return sample_current(uv);
+}
@@ -0,0 +1,11 @@
+// Old image breaks into blocks, then the new image sharpens out of them.
+// u_params.x = largest block size in pixels
+vec4 transition(vec2 uv, float progress) {
+    float strength = 1.0 - abs(progress * 2.0 - 1.0);
+    float block = max(floor(u_params.x * strength), 1.0);
+    vec2 cells = u_resolution / block;
+    vec2 coord = (floor(uv * cells) + 0.5) / cells;
+
+    float t = step(0.5, progress);
+    return mix(sample_previous(coord), sample_current(coord), t);
+}
@@ -0,0 +1,8 @@
+// Circle of the old image shrinking into the origin.
+// u_params.x = edge softness (fraction of screen)
+vec4 transition(vec2 uv, float progress) {
+    float soft = max(u_params.x * max(u_resolution.x, u_resolution.y), 1.0);
+    float radius = (1.0 - progress) * (max_origin_distance() + soft);
+    float t = 1.0 - smoothstep(radius - soft, radius, origin_distance(uv));
+    return mix(sample_current(uv), sample_previous(uv), t);
+}
@@ -0,0 +1,11 @@
+// New image pushes the old one out along u_direction
+vec4 transition(vec2 uv, float progress) {
+    vec2 offset = u_direction * (1.0 - progress);
+
+    vec2 current_coord = uv - offset;
+    if (in_bounds(current_coord)) {
+        return sample_current(current_coord);
+    }
+
+    return sample_previous(uv + u_direction * progress);
+}
@@ -0,0 +1,10 @@
+// Both images ripple while crossfading, the distortion peaks halfway.
+// u_params.x = amplitude (fraction of screen), u_params.y = periods
+vec4 transition(vec2 uv, float progress) {
+    float envelope = sin(progress * 3.14159265);
+    float phase = (uv.y * u_params.y + progress * 2.0) * 6.2831853;
+    vec2 offset = vec2(sin(phase), cos(phase * 0.7)) * u_params.x * envelope;
+
+    vec2 coord = clamp(uv + offset, 0.0, 1.0);
+    return mix(sample_previous(coord), sample_current(coord), progress);
+}
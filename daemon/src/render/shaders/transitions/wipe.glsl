@@ -0,0 +1,14 @@
+// Band perpendicular to u_direction through the origin widening until it
+// covers the screen. u_params.x = edge softness (fraction of screen)
+vec4 transition(vec2 uv, float progress) {
+    vec2 px = (uv - u_origin) * u_resolution;
+    float dist = abs(dot(px, u_direction));
+
+    vec2 far = max(u_origin, vec2(1.0) - u_origin) * u_resolution;
+    float reach = abs(far.x * u_direction.x) + abs(far.y * u_direction.y);
+    float soft = max(u_params.x * max(u_resolution.x, u_resolution.y), 1.0);
+
+    float edge = progress * (reach + soft);
+    float t = 1.0 - smoothstep(edge - soft, edge, dist);
+    return mix(sample_previous(uv), sample_current(uv), t);
+}
@@ -0,0 +1,6 @@
+// Old image magnifies towards the origin while fading out
+vec4 transition(vec2 uv, float progress) {
+    float scale = 1.0 + progress;
+    vec2 prev_coord = u_origin + (uv - u_origin) / scale;
+    return mix(sample_previous(prev_coord), sample_current(uv), progress);
+}
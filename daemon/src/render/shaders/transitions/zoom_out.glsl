@@ -0,0 +1,6 @@
+// New image starts magnified around the origin and settles while fading in
+vec4 transition(vec2 uv, float progress) {
+    float scale = 2.0 - progress;
+    vec2 current_coord = u_origin + (uv - u_origin) / scale;
+    return mix(sample_previous(uv), sample_current(current_coord), progress);
+}
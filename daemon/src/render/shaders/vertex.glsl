@@ -4,9 +4,13 @@ precision mediump float;
 attribute vec2 a_position;
 attribute vec2 a_texcoord;
 
+uniform float u_flip_y; // -1.0 when rendering into a texture, keeps rows top-down
+uniform mat2 u_buffer_matrix; // Buffer to surface coordinates for rotated outputs
+uniform vec2 u_buffer_offset;
+
 varying vec2 v_texcoord;
 
 void main() {
-    gl_Position = vec4(a_position, 0.0, 1.0);
-    v_texcoord = a_texcoord;
+    gl_Position = vec4(a_position.x, a_position.y * u_flip_y, 0.0, 1.0);
+    v_texcoord = u_buffer_matrix * a_texcoord + u_buffer_offset;
 }
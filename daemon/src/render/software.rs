@@ -0,0 +1,96 @@
+use super::fit::{BufferTransform, UvRect};
+use crate::config::BackgroundMode;
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+use wayland_client::protocol::wl_output::Transform;
+
+/// CPU fallback for outputs whose GPU context can't be recovered: fits the
+/// image like the shader does, without transitions or effects, into an
+/// XRGB8888 buffer of `buffer` size drawn for `transform`
+pub fn render(
+    image: &RgbaImage,
+    mode: BackgroundMode,
+    buffer: (u32, u32),
+    transform: Transform,
+) -> Vec<u8> {
+    let (width, height) = buffer;
+    let surface = if BufferTransform::swaps_axes(transform) {
+        (height, width)
+    } else {
+        (width, height)
+    };
+    let rect = UvRect::fit(mode, image.dimensions(), surface);
+    let buffer_transform = BufferTransform::new(transform);
+
+    // Scale the image to its size on screen once, then sample it 1:1
+    let (scaled_width, scaled_height) = rect.screen_size(surface);
+    let scaled = if (scaled_width, scaled_height) == image.dimensions() {
+        image.clone()
+    } else {
+        imageops::resize(image, scaled_width, scaled_height, FilterType::Triangle)
+    };
+
+    let mut out = vec![0u8; width as usize * height as usize * 4];
+    for (i, pixel) in out.chunks_exact_mut(4).enumerate() {
+        let x = (i % width as usize) as f32 + 0.5;
+        let y = (i / width as usize) as f32 + 0.5;
+        let uv = buffer_transform.apply([x / width as f32, y / height as f32]);
+
+        let mut tex = [0, 1].map(|axis| rect.offset[axis] + uv[axis] * rect.scale[axis]);
+        if rect.wrap {
+            tex = tex.map(f32::fract);
+        }
+
+        pixel[3] = 255;
+        if !(0.0..1.0).contains(&tex[0]) || !(0.0..1.0).contains(&tex[1]) {
+            continue;
+        }
+        let sx = ((tex[0] * scaled_width as f32) as u32).min(scaled_width - 1);
+        let sy = ((tex[1] * scaled_height as f32) as u32).min(scaled_height - 1);
+        let [r, g, b, _] = scaled.get_pixel(sx, sy).0;
+        pixel[..3].copy_from_slice(&[b, g, r]);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+
+    /// 4x2 image, left half red, right half blue
+    fn halves() -> RgbaImage {
+        RgbaImage::from_fn(4, 2, |x, _| if x < 2 { RED } else { BLUE })
+    }
+
+    fn bgrx(out: &[u8], width: u32, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * width + x) * 4) as usize;
+        out[i..i + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn test_software_cover() {
+        let out = render(&halves(), BackgroundMode::Cover, (4, 4), Transform::Normal);
+        assert_eq!(bgrx(&out, 4, 0, 0), [0, 0, 255, 255]);
+        assert_eq!(bgrx(&out, 4, 3, 3), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_software_contain_letterboxes() {
+        let out = render(&halves(), BackgroundMode::Contain, (4, 4), Transform::Normal);
+        assert_eq!(bgrx(&out, 4, 0, 0), [0, 0, 0, 255]);
+        assert_eq!(bgrx(&out, 4, 0, 1), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_software_rotated() {
+        // A quarter turn puts the left half of the image at the buffer's top
+        let out = render(&halves(), BackgroundMode::Fill, (2, 4), Transform::_90);
+        assert_eq!(bgrx(&out, 2, 0, 0), [0, 0, 255, 255]);
+        assert_eq!(bgrx(&out, 2, 1, 0), [0, 0, 255, 255]);
+        assert_eq!(bgrx(&out, 2, 0, 3), [255, 0, 0, 255]);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::config::{TransitionOptions, TransitionPosition, TransitionType};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+const FRAGMENT_COMMON_SRC: &str = include_str!("shaders/fragment.glsl");
+
+const NONE_SRC: &str = include_str!("shaders/transitions/none.glsl");
+const FADE_SRC: &str = include_str!("shaders/transitions/fade.glsl");
+const SLIDE_SRC: &str = include_str!("shaders/transitions/slide.glsl");
+const WIPE_SRC: &str = include_str!("shaders/transitions/wipe.glsl");
+const GROW_SRC: &str = include_str!("shaders/transitions/grow.glsl");
+const SHRINK_SRC: &str = include_str!("shaders/transitions/shrink.glsl");
+const ZOOM_IN_SRC: &str = include_str!("shaders/transitions/zoom_in.glsl");
+const ZOOM_OUT_SRC: &str = include_str!("shaders/transitions/zoom_out.glsl");
+const PIXELATE_SRC: &str = include_str!("shaders/transitions/pixelate.glsl");
+const DISSOLVE_SRC: &str = include_str!("shaders/transitions/dissolve.glsl");
+const BLINDS_SRC: &str = include_str!("shaders/transitions/blinds.glsl");
+const WAVE_SRC: &str = include_str!("shaders/transitions/wave.glsl");
+
+/// Transition settings for a surface, resolved from its monitor config
+#[derive(Debug, Clone)]
+pub struct TransitionSettings {
+    pub kind: TransitionType,
+    pub duration_ms: u32,
+    pub position: TransitionPosition,
+    pub random_pool: Vec<TransitionType>,
+    pub options: TransitionOptions,
+}
+
+impl Default for TransitionSettings {
+    fn default() -> Self {
+        Self {
+            kind: TransitionType::Fade,
+            duration_ms: 300,
+            position: TransitionPosition::default(),
+            random_pool: Vec::new(),
+            options: TransitionOptions::default(),
+        }
+    }
+}
+
+impl TransitionSettings {
+    /// Resolve the effect to play for the next change, picking one from the
+    /// pool when the configured type is `random`
+    pub fn pick(&self) -> TransitionType {
+        if self.kind != TransitionType::Random {
+            return self.kind;
+        }
+
+        let pool: Vec<TransitionType> = self
+            .random_pool
+            .iter()
+            .copied()
+            .filter(|t| !matches!(t, TransitionType::Random | TransitionType::None))
+            .collect();
+        let pool = if pool.is_empty() {
+            TransitionType::EFFECTS
+        } else {
+            &pool[..]
+        };
+
+        let seed = RandomState::new().build_hasher().finish();
+        pool[(seed as usize) % pool.len()]
+    }
+
+    /// Origin configured for this surface, or the center when it has to be
+    /// resolved at transition start (`cursor`) and that is not possible
+    pub fn default_origin(&self) -> [f32; 2] {
+        match self.position {
+            TransitionPosition::Fixed { x, y } => [x, y],
+            TransitionPosition::Cursor => [0.5, 0.5],
+        }
+    }
+
+    /// Effect specific shader parameters (`u_params`)
+    pub fn params(&self, kind: TransitionType) -> [f32; 4] {
+        let o = &self.options;
+        match kind {
+            TransitionType::Wipe | TransitionType::Grow | TransitionType::Shrink => {
+                [o.softness, 0.0, 0.0, 0.0]
+            }
+            TransitionType::Pixelate => [o.pixel_size, 0.0, 0.0, 0.0],
+            TransitionType::Dissolve => [o.softness, o.dissolve_grain, 0.0, 0.0],
+            TransitionType::Blinds => [o.blinds as f32, 0.0, 0.0, 0.0],
+            TransitionType::Wave => [o.wave_amplitude, o.wave_frequency, 0.0, 0.0],
+            _ => [0.0; 4],
+        }
+    }
+
+    /// Direction used by slide, wipe and blinds (`u_direction`)
+    pub fn direction(&self, kind: TransitionType) -> [f32; 2] {
+        match kind {
+            TransitionType::Slide | TransitionType::SlideLeft => [1.0, 0.0],
+            TransitionType::SlideRight => [-1.0, 0.0],
+            TransitionType::SlideUp => [0.0, 1.0],
+            TransitionType::SlideDown => [0.0, -1.0],
+            _ => {
+                let angle = self.options.angle.to_radians();
+                [angle.cos(), angle.sin()]
+            }
+        }
+    }
+}
+
+/// Map linear progress onto the curve used by the effect
+pub fn ease(kind: TransitionType, progress: f32) -> f32 {
+    match kind {
+        TransitionType::Fade | TransitionType::Dissolve => progress,
+        _ => progress * progress * (3.0 - 2.0 * progress),
+    }
+}
+
+/// Full fragment shader source for a transition effect
+pub fn fragment_source(kind: TransitionType) -> String {
+    let module = match kind {
+        TransitionType::None | TransitionType::Random => NONE_SRC,
+        TransitionType::Fade | TransitionType::Crossfade => FADE_SRC,
+        TransitionType::Slide
+        | TransitionType::SlideLeft
+        | TransitionType::SlideRight
+        | TransitionType::SlideUp
+        | TransitionType::SlideDown => SLIDE_SRC,
+        TransitionType::Wipe => WIPE_SRC,
+        TransitionType::Grow => GROW_SRC,
+        TransitionType::Shrink => SHRINK_SRC,
+        TransitionType::ZoomIn => ZOOM_IN_SRC,
+        TransitionType::ZoomOut => ZOOM_OUT_SRC,
+        TransitionType::Pixelate => PIXELATE_SRC,
+        TransitionType::Dissolve => DISSOLVE_SRC,
+        TransitionType::Blinds => BLINDS_SRC,
+        TransitionType::Wave => WAVE_SRC,
+    };
+
+    format!("{}\n{}", FRAGMENT_COMMON_SRC, module)
+}
@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// `$XDG_STATE_HOME/canviz`, usually `~/.local/state/canviz`
+fn state_dir() -> Option<PathBuf> {
+    dirs::state_dir().map(|dir| dir.join("canviz"))
+}
+
+/// Profile picked with `canvizctl profile`. The file is kept for
+/// `--none` too, so it overrides the config's own `profile` as well.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedProfile {
+    /// Profile picked, missing for no profile
+    pub profile: Option<String>,
+    /// The config's `profile` when it was picked
+    pub selected: Option<String>,
+}
+
+impl SavedProfile {
+    /// Profile to run with under a config selecting `selected`, or `None`
+    /// once the config selects another one than when this was picked
+    pub fn for_selected(&self, selected: Option<&str>) -> Option<Option<&str>> {
+        (self.selected.as_deref() == selected).then_some(self.profile.as_deref())
+    }
+}
+
+/// Profile picked with `canvizctl profile`, if one was
+pub fn saved_profile() -> Option<SavedProfile> {
+    let content = fs::read_to_string(state_dir()?.join("profile")).ok()?;
+    toml::from_str(&content).ok()
+}
+
+/// Remember the profile for the next start
+pub fn save_profile(saved: &SavedProfile) -> io::Result<()> {
+    let Some(dir) = state_dir() else {
+        return Ok(());
+    };
+    let content = toml::to_string(saved).map_err(io::Error::other)?;
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join("profile"), content)
+}
+
+/// Forget the picked profile, the config's own applies again
+pub fn forget_profile() -> io::Result<()> {
+    let Some(dir) = state_dir() else {
+        return Ok(());
+    };
+    match fs::remove_file(dir.join("profile")) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_saved_profile() {
+        let none = SavedProfile {
+            profile: None,
+            selected: Some("work".to_string()),
+        };
+        let content = toml::to_string(&none).unwrap();
+        assert_eq!(toml::from_str::<SavedProfile>(&content).unwrap(), none);
+
+        // No profile is a choice of its own, not the config's `profile`
+        assert_eq!(none.for_selected(Some("work")), Some(None));
+        // The config selecting another profile drops the choice
+        assert_eq!(none.for_selected(Some("home")), None);
+        assert_eq!(none.for_selected(None), None);
+
+        let home = SavedProfile {
+            profile: Some("home".to_string()),
+            selected: None,
+        };
+        assert_eq!(home.for_selected(None), Some(Some("home")));
+    }
+}
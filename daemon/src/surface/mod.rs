@@ -1,57 +1,291 @@
-use crate::config::{BackgroundMode, MonitorConfig, TransitionType};
+use crate::config::{LocalTime, MonitorSettings, QueuePolicy, TransitionPosition, TransitionType};
 use crate::daemon::Canviz;
-use crate::render::{EglContext, Renderer};
-use color_eyre::eyre::{Result, WrapErr};
+use crate::ipc::MonitorStats;
+use crate::image::gnome::{self, Timeline};
+use crate::image::{self, kde, DecodedImage, ImageLoader};
+use crate::output::OutputIdentity;
+use crate::render::{
+    gpu_loss, software, BufferTransform, EglContext, EglSurface, GlResources, GpuLoss, Renderer,
+    Texture, TransitionSettings,
+};
+use color_eyre::eyre::{eyre, Result, WrapErr};
 use log::{debug, error, info, warn};
+use smithay_client_toolkit::reexports::calloop::timer::{TimeoutAction, Timer};
+use smithay_client_toolkit::reexports::calloop::{LoopHandle, RegistrationToken};
 use smithay_client_toolkit::shell::wlr_layer::{LayerSurface, LayerSurfaceConfigure};
-use std::path::PathBuf;
-use std::time::Instant;
-use wayland_client::{protocol::wl_output::WlOutput, protocol::wl_surface::WlSurface, QueueHandle};
+use smithay_client_toolkit::shm::slot::{Buffer, SlotPool};
+use smithay_client_toolkit::shm::Shm;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use wayland_client::{
+    protocol::wl_output::{Transform, WlOutput},
+    protocol::wl_shm,
+    protocol::wl_surface::WlSurface,
+    QueueHandle,
+};
+use wayland_protocols::wp::fractional_scale::v1::client::wp_fractional_scale_v1::WpFractionalScaleV1;
+use wayland_protocols::wp::presentation_time::client::wp_presentation::WpPresentation;
+use wayland_protocols::wp::viewporter::client::wp_viewport::WpViewport;
 
-extern crate khronos_egl as egl;
+mod stats;
+
+use stats::FrameStats;
+
+/// Attempts at rebuilding lost GPU state before drawing in software instead
+const MAX_GPU_RECOVERIES: u32 = 3;
+
+/// Longest transition of a GNOME slideshow. Some fade over an hour, which
+/// would animate at full frame rate all along.
+const MAX_TIMELINE_TRANSITION: Duration = Duration::from_secs(30);
 
 /// Represents a wallpaper surface for a single output/monitor
 pub struct WallpaperSurface {
     wl_surface: WlSurface,
     layer_surface: LayerSurface,
     output: WlOutput,
+    identity: OutputIdentity,
     output_name: String,
-    config: MonitorConfig,
-    egl_display: egl::Display,
-    egl_context: Option<EglContext>,
+    /// Size of the output's current mode, a change recreates the surface
+    mode: Option<(i32, i32)>,
+    config: MonitorSettings,
+    /// Key and path of the schedule entry in effect, which replaces the
+    /// config's `path`
+    scheduled: Option<(String, PathBuf)>,
+    /// Weight of the day image against the night one (`daylight`), 1.0 in
+    /// full daylight
+    daylight: f32,
+    /// GNOME slideshow the wallpaper source points at, parsed once per
+    /// config
+    timeline: Option<(PathBuf, Result<Timeline, String>)>,
+    /// Image of the slideshow last shown
+    timeline_shown: Option<PathBuf>,
+    /// Effect and length of the slideshow's transition to that image,
+    /// replacing the configured transition
+    timeline_transition: Option<(TransitionType, Duration)>,
+    egl_context: Rc<EglContext>,
+    gl_resources: Rc<GlResources>,
+    egl_surface: Option<EglSurface>,
     renderer: Option<Renderer>,
+    /// Last frame in shared memory while the GPU resources are released,
+    /// kept until EGL attaches a buffer again
+    released: Option<(SlotPool, Buffer)>,
     width: u32,
     height: u32,
     scale_factor: i32,
+    /// wp_fractional_scale_v1 and wp_viewport of the surface, when the
+    /// compositor supports both
+    fractional_scale: Option<(WpFractionalScaleV1, WpViewport)>,
+    /// Preferred fractional scale in 120ths, replaces `scale_factor` once known
+    preferred_scale: Option<u32>,
+    /// Output transform, buffers are drawn pre-rotated to match it
+    transform: Transform,
     configured: bool,
+    /// A frame callback is outstanding and will trigger the next draw
+    frame_pending: bool,
+    /// Asks for the next pan and zoom frame once the frame rate cap allows
+    /// it, see `schedule_motion_frame`
+    motion_timer: Option<RegistrationToken>,
+    loop_handle: LoopHandle<'static, Canviz>,
+    /// The user is idle, so continuous animations are paused
+    idle: bool,
     last_frame_time: Option<Instant>,
     current_wallpaper_path: Option<PathBuf>,
+    /// Why the last wallpaper couldn't be shown, reported over IPC
+    last_error: Option<String>,
+    /// Wallpaper picked from the monitor config (as opposed to a workspace
+    /// or IPC override), restored when a workspace has no wallpaper of its own
+    config_wallpaper_path: Option<PathBuf>,
+    /// Changes waiting for the running transition (`transition_queue = "queue"`)
+    pending: VecDeque<(PathBuf, Option<[f32; 2]>)>,
+    loader: ImageLoader,
+    /// Wallpaper being decoded off-thread, see `image_decoded`
+    decoding: Option<(PathBuf, Option<[f32; 2]>)>,
+    /// Night image being decoded off-thread
+    decoding_night: Option<PathBuf>,
+    /// EGL reported the context or surface lost, the daemon rebuilds it
+    /// after the current dispatch (see `recover_gpu`)
+    gpu_lost: Option<GpuLoss>,
+    /// Recoveries since the last frame that made it to the screen
+    recoveries: u32,
+    /// The GPU couldn't be recovered, frames are drawn on the CPU into
+    /// `released` instead
+    software: bool,
+    /// The software frame is out of date
+    software_dirty: bool,
+    /// Image of the last software frame, decoded once for all redraws
+    software_image: Option<(PathBuf, Arc<::image::RgbaImage>)>,
+    /// Image being decoded off-thread for a software frame
+    decoding_software: Option<PathBuf>,
+    /// wp_presentation, for exact presentation times in `stats`
+    presentation: Option<WpPresentation>,
+    stats: FrameStats,
+    /// Clock of frame times when there's no presentation feedback
+    stats_epoch: Instant,
+    /// The last frame asked for another one, so the next continues its animation
+    animating: bool,
+    transition_started: Option<Instant>,
+    upload_started: Option<Instant>,
+    /// State of a previous surface for the same monitor, shown on the first
+    /// configure instead of the config's wallpaper
+    restore: Option<SavedState>,
+}
+
+/// What a monitor was showing, kept while it is disconnected or its surface
+/// is recreated
+#[derive(Debug, Clone)]
+pub struct SavedState {
+    /// Wallpaper path of the monitor config (or its schedule), the state is
+    /// stale once it changes
+    config_path: PathBuf,
+    /// Wallpaper on screen (or being loaded), which is also the position in
+    /// a wallpaper directory
+    wallpaper: Option<PathBuf>,
+    config_wallpaper: Option<PathBuf>,
+}
+
+/// Key and path of the entry of a monitor's schedule showing at `now`
+fn scheduled_entry(config: &MonitorSettings, now: &LocalTime) -> Option<(String, PathBuf)> {
+    let entry = config.schedule.as_ref()?.active(now)?;
+    Some((entry.key.clone(), entry.path.clone()))
+}
+
+/// Weight of the day image of a monitor's `daylight` at `now`
+fn daylight_at(config: &MonitorSettings, now: SystemTime) -> f32 {
+    config.daylight.as_ref().map_or(1.0, |daylight| daylight.blend(now))
+}
+
+/// Apply a monitor's `transition_queue` policy to a wallpaper change while
+/// another one may be `changing`. Returns whether to show it right away,
+/// otherwise it was queued in `pending` or dropped. Repeats of the last
+/// queued change coalesce, keeping the newest origin.
+fn queue_change(
+    pending: &mut VecDeque<(PathBuf, Option<[f32; 2]>)>,
+    policy: QueuePolicy,
+    changing: bool,
+    path: &Path,
+    origin: Option<[f32; 2]>,
+) -> bool {
+    match policy {
+        QueuePolicy::Interrupt => true,
+        QueuePolicy::Drop => !changing,
+        // Nothing may overtake the changes already waiting
+        QueuePolicy::Queue if !changing && pending.is_empty() => true,
+        QueuePolicy::Queue => {
+            match pending.back_mut() {
+                Some((last, last_origin)) if last == path => *last_origin = origin,
+                _ => pending.push_back((path.to_path_buf(), origin)),
+            }
+            false
+        }
+    }
+}
+
+/// Buffer size in pixels of a surface of `size` logical pixels, scaled by
+/// the preferred fractional scale in 120ths (or else the integer scale)
+/// and turned by the transform
+fn buffer_size(
+    (width, height): (u32, u32),
+    preferred_scale: Option<u32>,
+    scale_factor: i32,
+    transform: Transform,
+) -> (u32, u32) {
+    let (width, height) = match preferred_scale {
+        // Rounded half away from zero, as fractional-scale-v1 asks
+        Some(scale) => ((width * scale + 60) / 120, (height * scale + 60) / 120),
+        None => (width * scale_factor as u32, height * scale_factor as u32),
+    };
+    if BufferTransform::swaps_axes(transform) {
+        (height, width)
+    } else {
+        (width, height)
+    }
+}
+
+/// Number of the next attempt at rebuilding lost GPU state after
+/// `recoveries` in a row, or None once it's time to draw in software
+fn next_recovery(recoveries: u32) -> Option<u32> {
+    (recoveries < MAX_GPU_RECOVERIES).then_some(recoveries + 1)
+}
+
+/// What a wp_presentation_feedback was requested for
+pub struct FrameFeedback {
+    pub output: WlOutput,
+    /// The frame continues the animation of the previous one
+    pub continues: bool,
+    /// Intended time since the previous frame, beyond one refresh
+    pub expected: Duration,
 }
 
 impl WallpaperSurface {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         wl_surface: WlSurface,
         layer_surface: LayerSurface,
         output: WlOutput,
-        output_name: String,
-        config: MonitorConfig,
-        egl_display: egl::Display,
+        identity: OutputIdentity,
+        config: MonitorSettings,
+        egl_context: Rc<EglContext>,
+        gl_resources: Rc<GlResources>,
+        fractional_scale: Option<(WpFractionalScaleV1, WpViewport)>,
+        loader: ImageLoader,
+        presentation: Option<WpPresentation>,
+        loop_handle: LoopHandle<'static, Canviz>,
     ) -> Result<Self> {
+        let mut stats = FrameStats::default();
+        stats.set_presentation(presentation.is_some());
         Ok(Self {
             wl_surface,
             layer_surface,
             output,
-            output_name,
+            output_name: identity.display_name(),
+            identity,
+            mode: None,
+            scheduled: scheduled_entry(&config, &LocalTime::now()),
+            daylight: daylight_at(&config, SystemTime::now()),
+            timeline: None,
+            timeline_shown: None,
+            timeline_transition: None,
             config,
-            egl_display,
-            egl_context: None,
+            egl_context,
+            gl_resources,
+            egl_surface: None,
             renderer: None,
+            released: None,
             width: 0,
             height: 0,
             scale_factor: 1,
+            fractional_scale,
+            preferred_scale: None,
+            transform: Transform::Normal,
             configured: false,
+            frame_pending: false,
+            motion_timer: None,
+            loop_handle,
+            idle: false,
             last_frame_time: None,
             current_wallpaper_path: None,
+            last_error: None,
+            config_wallpaper_path: None,
+            pending: VecDeque::new(),
+            loader,
+            decoding: None,
+            decoding_night: None,
+            gpu_lost: None,
+            recoveries: 0,
+            software: false,
+            software_dirty: false,
+            software_image: None,
+            decoding_software: None,
+            presentation,
+            stats,
+            stats_epoch: Instant::now(),
+            animating: false,
+            transition_started: None,
+            upload_started: None,
+            restore: None,
         })
     }
 
@@ -63,7 +297,6 @@ impl WallpaperSurface {
         &self.layer_surface
     }
 
-    #[allow(dead_code)]
     pub fn output(&self) -> &WlOutput {
         &self.output
     }
@@ -72,11 +305,298 @@ impl WallpaperSurface {
         &self.output_name
     }
 
-    #[allow(dead_code)]
-    pub fn config(&self) -> &MonitorConfig {
+    pub fn identity(&self) -> &OutputIdentity {
+        &self.identity
+    }
+
+    /// Record the size of the output's current mode, returning whether it
+    /// changed from a previously known one
+    pub fn set_mode(&mut self, dimensions: (i32, i32)) -> bool {
+        self.mode.replace(dimensions).is_some_and(|old| old != dimensions)
+    }
+
+    /// Wallpaper state for a later surface on the same monitor
+    pub fn save_state(&self) -> SavedState {
+        let wallpaper = match &self.decoding {
+            Some((path, _)) => Some(path.clone()),
+            None => self.current_wallpaper_path.clone(),
+        };
+        SavedState {
+            config_path: self.wallpaper_source().to_path_buf(),
+            wallpaper,
+            config_wallpaper: self.config_wallpaper_path.clone(),
+        }
+    }
+
+    /// Show `state` on the first configure, unless the config has moved on
+    pub fn restore_state(&mut self, state: SavedState) {
+        self.restore = Some(state).filter(|state| state.config_path == self.wallpaper_source());
+    }
+
+    pub fn config(&self) -> &MonitorSettings {
         &self.config
     }
 
+    /// File or directory the wallpaper comes from: the `daylight` day
+    /// image, the active schedule entry's, or the config's `path`
+    fn wallpaper_source(&self) -> &Path {
+        if let Some(daylight) = &self.config.daylight {
+            return &daylight.day;
+        }
+        match &self.scheduled {
+            Some((_, path)) => path,
+            None => &self.config.path,
+        }
+    }
+
+    /// Key of the schedule entry in effect
+    pub fn schedule_entry(&self) -> Option<&str> {
+        self.scheduled.as_ref().map(|(key, _)| key.as_str())
+    }
+
+    /// Follow the schedule and any GNOME slideshow to `now`, returning
+    /// whether the wallpaper changed. The new one is shown by
+    /// `load_config_wallpaper`.
+    pub fn update_schedule(&mut self, now: SystemTime) -> bool {
+        let old = self.wallpaper_source().to_path_buf();
+        self.scheduled = scheduled_entry(&self.config, &LocalTime::at(now));
+        if old != self.wallpaper_source() {
+            return true;
+        }
+
+        let output = self.output_pixels();
+        match self.timeline() {
+            Some(Ok(timeline)) => Some(timeline.frame(now, output).path) != self.timeline_shown,
+            _ => false,
+        }
+    }
+
+    /// Time until the schedule or the slideshow moves on
+    pub fn until_schedule_change(&self, now: SystemTime) -> Option<Duration> {
+        let schedule = self
+            .config
+            .schedule
+            .as_ref()
+            .and_then(|schedule| schedule.until_next_change(&LocalTime::at(now)));
+        let timeline = match &self.timeline {
+            Some((path, Ok(timeline))) if path == self.wallpaper_source() => {
+                Some(timeline.until_next_change(now))
+            }
+            _ => None,
+        };
+        schedule.into_iter().chain(timeline).min()
+    }
+
+    /// The GNOME slideshow the wallpaper source is, if it is one
+    fn timeline(&mut self) -> Option<&Result<Timeline, String>> {
+        let source = self.wallpaper_source();
+        if !gnome::is_timeline(source) {
+            return None;
+        }
+        if self.timeline.as_ref().is_none_or(|(path, _)| path != source) {
+            let source = source.to_path_buf();
+            let timeline = Timeline::load(&source).map_err(|e| format!("{:#}", e));
+            self.timeline = Some((source, timeline));
+        }
+        self.timeline.as_ref().map(|(_, timeline)| timeline)
+    }
+
+    /// The image a wallpaper source stands for: the variant of a KDE package
+    /// for this output, or what a GNOME slideshow shows right now
+    fn resolve_pack(&mut self, source: PathBuf) -> Result<PathBuf, String> {
+        let output = self.output_pixels();
+        if kde::is_package(&source) {
+            self.set_timeline_transition(None);
+            return kde::pick(&source, output)
+                .ok_or_else(|| format!("No images in KDE package {}", source.display()));
+        }
+
+        let frame = match self.timeline() {
+            None => {
+                self.set_timeline_transition(None);
+                return Ok(source);
+            }
+            Some(Err(e)) => return Err(e.clone()),
+            Some(Ok(timeline)) => timeline.frame(SystemTime::now(), output),
+        };
+        self.timeline_shown = Some(frame.path.clone());
+        self.set_timeline_transition(frame.transition);
+        Ok(frame.path)
+    }
+
+    /// Play the next change with a slideshow's transition, or the
+    /// configured one for `None`
+    fn set_timeline_transition(&mut self, transition: Option<(TransitionType, Duration)>) {
+        self.timeline_transition = transition;
+        let transition = self.transition_settings();
+        if let Some(ref mut renderer) = self.renderer {
+            renderer.set_transition(transition);
+        }
+    }
+
+    /// Weight of the day image, when the monitor blends by `daylight`
+    pub fn daylight(&self) -> Option<f32> {
+        self.config.daylight.as_ref().map(|_| self.daylight)
+    }
+
+    /// Follow the sun to `now`, returning whether the blend changed enough
+    /// to redraw for
+    pub fn update_daylight(&mut self, now: SystemTime) -> bool {
+        let daylight = daylight_at(&self.config, now);
+        // One step of an 8-bit channel at most, finer changes don't show
+        let visible = (daylight * 255.0).round() != (self.daylight * 255.0).round();
+        self.daylight = daylight;
+        if let Some(ref mut renderer) = self.renderer {
+            renderer.set_daylight(daylight);
+        }
+        visible && self.night_image().is_some()
+    }
+
+    /// Night image to blend with, while the `daylight` day image shows
+    fn night_image(&self) -> Option<&Path> {
+        let daylight = self.config.daylight.as_ref()?;
+        (self.current_wallpaper_path.as_ref() == Some(&daylight.day)).then_some(daylight.night.as_path())
+    }
+
+    /// Hand the renderer the night image the current wallpaper blends
+    /// with, decoding it first if no output has it uploaded yet
+    fn sync_night_image(&mut self) -> Result<()> {
+        let night = self.night_image().map(Path::to_path_buf);
+        let (Some(ctx), Some(renderer)) = (&self.egl_surface, &mut self.renderer) else {
+            return Ok(());
+        };
+        renderer.set_daylight(self.daylight);
+        let Some(path) = night else {
+            renderer.clear_night();
+            self.decoding_night = None;
+            return Ok(());
+        };
+        if renderer.has_night_image(&path) || self.decoding_night.as_ref() == Some(&path) {
+            return Ok(());
+        }
+
+        ctx.make_current()?;
+        if renderer.show_uploaded_night(&path)? {
+            return Ok(());
+        }
+        std::fs::metadata(&path).wrap_err_with(|| format!("Failed to open image: {:?}", path))?;
+        debug!("Decoding night image {:?} for {}", path, self.output_name);
+        self.loader
+            .request(&path, self.gl_resources.max_texture_size(), renderer.prescale_for());
+        self.decoding_night = Some(path);
+        Ok(())
+    }
+
+    /// Upload a night image decoded by the `ImageLoader`
+    fn night_decoded(&mut self, decoded: &DecodedImage, qh: &QueueHandle<Canviz>) -> Result<()> {
+        let image = match &decoded.image {
+            Ok(image) => image.clone(),
+            Err(e) => {
+                error!("Failed to load night image {:?}: {}", decoded.path, e);
+                self.last_error = Some(e.clone());
+                return Ok(());
+            }
+        };
+        // Without a renderer `restore_wallpaper` loads it again
+        let (Some(ctx), Some(renderer)) = (&self.egl_surface, &mut self.renderer) else {
+            return Ok(());
+        };
+        let result = ctx
+            .make_current()
+            .and_then(|()| renderer.load_night_image(&decoded.path, image));
+        if let Err(e) = result {
+            match gpu_loss(&e) {
+                Some(loss) => self.lose_gpu(loss, &e),
+                None => return Err(e),
+            }
+            return Ok(());
+        }
+        self.request_redraw(qh)
+    }
+
+    /// Show the wallpaper of the config (or schedule) again, replacing a
+    /// workspace or IPC one
+    pub fn load_config_wallpaper(&mut self) {
+        if self.renderer.is_some() {
+            self.load_initial_wallpaper();
+        }
+    }
+
+    /// Show the next wallpaper right away, without its transition
+    pub fn skip_transition(&mut self) {
+        if let Some(ref mut renderer) = self.renderer {
+            renderer.skip_transition();
+        }
+    }
+
+    /// Where transitions on this surface start from
+    pub fn transition_position(&self) -> TransitionPosition {
+        self.config.transition_pos
+    }
+
+    /// Replace the monitor config, reloading the wallpaper if its path changed
+    pub fn apply_config(&mut self, config: MonitorSettings) {
+        let old_source = self.wallpaper_source().to_path_buf();
+        self.config = config;
+        self.scheduled = scheduled_entry(&self.config, &LocalTime::now());
+        self.daylight = daylight_at(&self.config, SystemTime::now());
+        // Read slideshows again, they may have changed along with the config
+        self.timeline = None;
+        let path_changed = old_source != self.wallpaper_source();
+
+        let transition = self.transition_settings();
+        let background_mode = self.config.mode;
+        let motion_span = self.motion_span();
+        let (linear_blending, dither) = self.color_options();
+        if let Some(ref mut renderer) = self.renderer {
+            renderer.set_transition(transition);
+            renderer.set_background_mode(background_mode);
+            renderer.set_motion(self.config.motion.clone(), motion_span);
+            renderer.set_parallax(self.config.parallax.clone());
+            renderer.set_color_options(linear_blending, dither);
+            renderer.set_filter(self.config.filter);
+        }
+
+        if path_changed && self.renderer.is_some() {
+            self.load_initial_wallpaper();
+        } else if let Err(e) = self.sync_night_image() {
+            error!("Failed to load night image on {}: {:#}", self.output_name, e);
+        }
+    }
+
+    /// Transition settings resolved from the monitor config
+    fn transition_settings(&self) -> TransitionSettings {
+        let (kind, duration_ms) = match self.timeline_transition {
+            Some((kind, duration)) => (kind, duration.min(MAX_TIMELINE_TRANSITION).as_millis() as u32),
+            None => (self.config.transition, self.config.transition_time),
+        };
+        TransitionSettings {
+            kind,
+            duration_ms,
+            position: self.config.transition_pos,
+            random_pool: self.config.transition_random.clone(),
+            options: self.config.transition_options.clone(),
+        }
+    }
+
+    /// How long the Ken Burns effect takes over one image: the slideshow
+    /// duration if there is one, otherwise the motion period
+    fn motion_span(&self) -> Duration {
+        let period = self.config.motion.as_ref().map(|m| m.period).unwrap_or_default();
+        self.config.duration.unwrap_or(period)
+    }
+
+    /// Linear light blending and dithering
+    fn color_options(&self) -> (bool, bool) {
+        (self.config.linear_blending, self.config.dither)
+    }
+
+    /// Minimum time between frames that only advance the Ken Burns effect
+    fn motion_frame_interval(&self) -> Duration {
+        let fps = self.config.motion.as_ref().map_or(30, |m| m.fps);
+        Duration::from_secs(1) / fps.max(1)
+    }
+
     /// Handle configure event from the compositor
     pub fn configure(
         &mut self,
@@ -95,21 +615,36 @@ impl WallpaperSurface {
         );
 
         let size_changed = self.width != width || self.height != height;
+        let was_portrait = self.is_portrait();
         self.width = width;
         self.height = height;
         self.configured = true;
+        self.update_viewport();
 
         // Note: Don't call set_size() here - the compositor already told us the size
         // in the configure event. Calling set_size() would trigger another configure.
 
         // Initialize or resize EGL context
-        let first_configure = self.egl_context.is_none();
+        let first_configure =
+            self.egl_surface.is_none() && self.released.is_none() && !self.software;
         if first_configure {
-            self.init_rendering()?;
+            if let Err(e) = self.init_rendering() {
+                self.last_error = Some(format!("{:#}", e));
+                return Err(e);
+            }
             // Load initial wallpaper only on first configure
-            self.load_initial_wallpaper();
+            match self.restore.take() {
+                Some(state) => self.load_saved_wallpaper(state),
+                None => self.load_initial_wallpaper(),
+            }
         } else if size_changed {
             self.resize_rendering()?;
+            // A rotated output may want a different image from the directory
+            if self.is_portrait() != was_portrait && self.wallpaper_source().is_dir() {
+                self.load_initial_wallpaper();
+            } else {
+                self.rescale_wallpaper();
+            }
         }
 
         // Do the first draw immediately - this will commit
@@ -119,34 +654,38 @@ impl WallpaperSurface {
         Ok(())
     }
 
-    /// Initialize EGL context and renderer
+    /// Initialize EGL surface and renderer
     fn init_rendering(&mut self) -> Result<()> {
         info!("Initializing rendering for {}", self.output_name);
 
-        // Calculate buffer size with scale factor
-        let buffer_width = self.width * self.scale_factor as u32;
-        let buffer_height = self.height * self.scale_factor as u32;
+        let (buffer_width, buffer_height) = self.buffer_size();
 
-        // Create EGL context
-        let egl_context = EglContext::new(
-            self.egl_display,
+        // Create EGL surface on the shared context
+        let egl_surface = EglSurface::new(
+            self.egl_context.clone(),
             &self.wl_surface,
             buffer_width,
             buffer_height,
         )
-        .wrap_err_with(|| format!("Failed to create EGL context for {}", self.output_name))?;
+        .wrap_err_with(|| format!("Failed to create EGL surface for {}", self.output_name))?;
 
         // Create renderer
-        let transition_type = self.config.transition.unwrap_or(TransitionType::Fade);
-        let transition_time = self.config.transition_time.unwrap_or(300);
-        let background_mode = self.config.mode.unwrap_or(BackgroundMode::Cover);
+        let transition = self.transition_settings();
+        let background_mode = self.config.mode;
 
-        let mut renderer = Renderer::new(transition_type, transition_time, background_mode)
+        let mut renderer = Renderer::new(self.gl_resources.clone(), transition, background_mode)
             .wrap_err("Failed to create renderer")?;
 
         renderer.set_viewport(buffer_width, buffer_height);
+        renderer.set_transform(self.transform);
+        renderer.set_motion(self.config.motion.clone(), self.motion_span());
+        renderer.set_parallax(self.config.parallax.clone());
+        let (linear_blending, dither) = self.color_options();
+        renderer.set_color_options(linear_blending, dither);
+        renderer.set_filter(self.config.filter);
+        renderer.set_daylight(self.daylight);
 
-        self.egl_context = Some(egl_context);
+        self.egl_surface = Some(egl_surface);
         self.renderer = Some(renderer);
 
         info!("Rendering initialized for {} ({}x{})", self.output_name, buffer_width, buffer_height);
@@ -156,23 +695,94 @@ impl WallpaperSurface {
 
     /// Resize the rendering context
     fn resize_rendering(&mut self) -> Result<()> {
-        let buffer_width = self.width * self.scale_factor as u32;
-        let buffer_height = self.height * self.scale_factor as u32;
+        let (buffer_width, buffer_height) = self.buffer_size();
 
-        if let Some(ref mut ctx) = self.egl_context {
+        if let Some(ref mut ctx) = self.egl_surface {
             ctx.resize(buffer_width, buffer_height)?;
         }
 
         if let Some(ref mut renderer) = self.renderer {
             renderer.set_viewport(buffer_width, buffer_height);
+            renderer.set_transform(self.transform);
         }
 
         Ok(())
     }
 
+    /// Prescale the wallpaper again for a new buffer size (`filter = "lanczos"`),
+    /// or switch to a better sized image of a KDE package
+    fn rescale_wallpaper(&mut self) {
+        let source = self.wallpaper_source();
+        let showing_config = self.current_wallpaper_path.is_some()
+            && self.current_wallpaper_path == self.config_wallpaper_path;
+        if showing_config && kde::is_package(source) && !self.is_changing() {
+            let best = kde::pick(source, self.output_pixels());
+            if best.is_some() && best != self.current_wallpaper_path {
+                debug!("Switching to {:?} for the new size of {}", best, self.output_name);
+                self.load_initial_wallpaper();
+                return;
+            }
+        }
+
+        let prescaled = self.renderer.as_ref().is_some_and(|r| r.prescale_for().is_some());
+        if !prescaled || self.is_changing() {
+            return;
+        }
+        if let Some(path) = self.current_wallpaper_path.clone() {
+            debug!("Prescaling {:?} again for the new size of {}", path, self.output_name);
+            if let Err(e) = self.show_wallpaper(&path, None) {
+                error!("Failed to prescale wallpaper {:?}: {}", path, e);
+            }
+        }
+    }
+
+    /// Buffer size in pixels, with scale and transform applied
+    fn buffer_size(&self) -> (u32, u32) {
+        buffer_size(
+            (self.width, self.height),
+            self.preferred_scale,
+            self.scale_factor,
+            self.transform,
+        )
+    }
+
+    /// Buffer size in pixels as the user sees it, before the transform
+    fn output_pixels(&self) -> (u32, u32) {
+        let (width, height) = self.buffer_size();
+        if BufferTransform::swaps_axes(self.transform) {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// Whether the output is taller than it is wide, as the user sees it
+    fn is_portrait(&self) -> bool {
+        self.height > self.width
+    }
+
+    /// Show what a previous surface of this monitor was showing
+    fn load_saved_wallpaper(&mut self, state: SavedState) {
+        let Some(path) = state.wallpaper.filter(|path| path.is_file()) else {
+            self.load_initial_wallpaper();
+            return;
+        };
+
+        info!("Restoring {:?} on {}", path, self.output_name);
+        self.config_wallpaper_path = state.config_wallpaper;
+        // A slideshow that moved on meanwhile changes at the next check
+        self.timeline_shown = Some(path.clone());
+        if let Err(e) = self.load_wallpaper(&path, None) {
+            error!("Failed to restore wallpaper {:?}: {}", path, e);
+            self.load_initial_wallpaper();
+        }
+    }
+
     /// Load initial wallpaper from config
     fn load_initial_wallpaper(&mut self) {
-        let path = &self.config.path;
+        self.last_error = None;
+        // Expanded when the config was loaded, see `config::expand_path`
+        let path = self.wallpaper_source().to_path_buf();
 
         if path.as_os_str().is_empty() {
             warn!("No wallpaper path configured for {}", self.output_name);
@@ -185,48 +795,68 @@ impl WallpaperSurface {
             return;
         }
 
-        // Expand ~ to home directory
-        let expanded_path = if path.starts_with("~") {
-            if let Some(home) = dirs::home_dir() {
-                home.join(path.strip_prefix("~").unwrap_or(path))
-            } else {
-                path.clone()
+        // Desktop wallpaper packs stand for one of their images
+        let path = match self.resolve_pack(path) {
+            Ok(path) => path,
+            Err(e) => {
+                warn!("{}", e);
+                self.last_error = Some(e);
+                if let Some(ref mut renderer) = self.renderer {
+                    let _ = renderer.set_solid_color(30, 30, 40);
+                }
+                return;
             }
-        } else {
-            path.clone()
         };
 
-        if expanded_path.is_file() {
-            if let Err(e) = self.load_wallpaper(&expanded_path) {
-                error!("Failed to load wallpaper {:?}: {}", expanded_path, e);
+        if path.is_file() {
+            self.config_wallpaper_path = Some(path.clone());
+            if let Err(e) = self.load_wallpaper(&path, None) {
+                error!("Failed to load wallpaper {:?}: {}", path, e);
                 // Fallback to solid color
                 if let Some(ref mut renderer) = self.renderer {
                     let _ = renderer.set_solid_color(30, 30, 40);
                 }
             }
-        } else if expanded_path.is_dir() {
-            // For directories, pick the first image (slideshow logic will come later)
-            if let Ok(entries) = std::fs::read_dir(&expanded_path) {
+        } else if path.is_dir() {
+            // For directories, pick the first image (slideshow logic will come later),
+            // preferring ones that match the orientation of the output
+            if let Ok(entries) = std::fs::read_dir(&path) {
                 let extensions = ["jpg", "jpeg", "png", "bmp", "gif", "webp"];
-                for entry in entries.flatten() {
-                    let entry_path = entry.path();
-                    if let Some(ext) = entry_path.extension() {
-                        if extensions.contains(&ext.to_string_lossy().to_lowercase().as_str()) {
-                            if let Err(e) = self.load_wallpaper(&entry_path) {
-                                error!("Failed to load wallpaper {:?}: {}", entry_path, e);
-                            } else {
-                                return;
-                            }
-                        }
+                let mut images: Vec<PathBuf> = entries
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| {
+                        path.extension().is_some_and(|ext| {
+                            extensions.contains(&ext.to_string_lossy().to_lowercase().as_str())
+                        })
+                    })
+                    .collect();
+                let portrait = self.is_portrait();
+                images.sort_by_key(|path| image::is_portrait(path) != Some(portrait));
+
+                for entry_path in images {
+                    if let Err(e) = self.load_wallpaper(&entry_path, None) {
+                        error!("Failed to load wallpaper {:?}: {}", entry_path, e);
+                    } else {
+                        self.config_wallpaper_path = Some(entry_path);
+                        return;
                     }
                 }
             }
-            warn!("No images found in directory {:?}", expanded_path);
+            warn!("No images found in directory {:?}", path);
+            // Keep the reason images failed to load, if there were any
+            if self.last_error.is_none() {
+                self.last_error = Some(format!("No images found in {}", path.display()));
+            }
             if let Some(ref mut renderer) = self.renderer {
                 let _ = renderer.set_solid_color(30, 30, 40);
             }
         } else {
-            warn!("Wallpaper path does not exist: {:?}", expanded_path);
+            warn!("Wallpaper path does not exist: {:?}", path);
+            self.last_error = Some(format!(
+                "Wallpaper path does not exist: {}",
+                path.display()
+            ));
             if let Some(ref mut renderer) = self.renderer {
                 let _ = renderer.set_solid_color(30, 30, 40);
             }
@@ -234,20 +864,167 @@ impl WallpaperSurface {
     }
 
     /// Load a wallpaper from a file path
-    pub fn load_wallpaper(&mut self, path: &std::path::Path) -> Result<()> {
-        if let Some(ref mut ctx) = self.egl_context {
-            ctx.make_current()?;
+    ///
+    /// `origin` overrides the configured transition origin, in fractions of
+    /// this output from its top-left corner. Changes arriving mid-transition
+    /// are handled according to the monitor's `transition_queue` policy.
+    /// A KDE package shows its image for this output, a GNOME slideshow
+    /// only plays as the monitor's `path` (see `resolve_pack`).
+    pub fn load_wallpaper(&mut self, path: &std::path::Path, origin: Option<[f32; 2]>) -> Result<()> {
+        let picked;
+        let path = if kde::is_package(path) {
+            picked = kde::pick(path, self.output_pixels())
+                .ok_or_else(|| eyre!("No images in KDE package {}", path.display()))?;
+            picked.as_path()
+        } else if gnome::is_timeline(path) {
+            return Err(eyre!(
+                "{} is a GNOME slideshow, which only plays as a monitor's `path`",
+                path.display()
+            ));
+        } else {
+            path
+        };
+
+        let policy = self.config.transition_queue;
+        let changing = self.is_changing();
+        if !queue_change(&mut self.pending, policy, changing, path, origin) {
+            match policy {
+                QueuePolicy::Drop => {
+                    debug!("Dropping {:?} on {}, transition in progress", path, self.output_name)
+                }
+                _ => debug!("Queueing {:?} on {} until the transition ends", path, self.output_name),
+            }
+            return Ok(());
         }
 
-        if let Some(ref mut renderer) = self.renderer {
-            renderer.load_wallpaper_from_file(path)?;
+        self.show_wallpaper(path, origin)
+    }
+
+    /// Whether a wallpaper change is being decoded, uploaded or transitioned to
+    fn is_changing(&self) -> bool {
+        self.decoding.is_some()
+            || self
+                .renderer
+                .as_ref()
+                .is_some_and(|r| r.is_transitioning() || r.is_loading())
+    }
+
+    /// Upload a wallpaper and start the transition to it
+    fn show_wallpaper(&mut self, path: &std::path::Path, origin: Option<[f32; 2]>) -> Result<()> {
+        let result = self.upload_wallpaper(path, origin);
+        self.record_result(path, result)
+    }
+
+    /// Keep the outcome of showing `path` for status, turning lost EGL
+    /// state into a recovery instead of an error
+    fn record_result(&mut self, path: &std::path::Path, result: Result<()>) -> Result<()> {
+        self.last_error = result.as_ref().err().map(|e| format!("{:#}", e));
+        if let Err(e) = &result {
+            if let Some(loss) = gpu_loss(e) {
+                // Shown once the GPU state is rebuilt
+                self.current_wallpaper_path = Some(path.to_path_buf());
+                self.lose_gpu(loss, e);
+                return Ok(());
+            }
+        }
+        result
+    }
+
+    fn upload_wallpaper(&mut self, path: &std::path::Path, origin: Option<[f32; 2]>) -> Result<()> {
+        if self.software || self.gpu_lost.is_some() {
+            // Drawn by `draw_software` or restored by `recover_gpu`
             self.current_wallpaper_path = Some(path.to_path_buf());
-            info!("Loaded wallpaper: {:?}", path);
+            self.software_dirty = true;
+            return Ok(());
+        }
+        self.acquire_gpu()?;
+        if let Some(ref mut ctx) = self.egl_surface {
+            ctx.make_current()?;
+        }
+
+        let Some(ref mut renderer) = self.renderer else {
+            return Ok(());
+        };
+        if renderer.show_uploaded(path, origin)? {
+            self.decoding = None;
+            self.wallpaper_shown(path);
+            return Ok(());
+        }
+
+        // Fail early on missing files, decoding errors come later
+        std::fs::metadata(path).wrap_err_with(|| format!("Failed to open image: {:?}", path))?;
+        debug!("Decoding {:?} for {}", path, self.output_name);
+        self.loader
+            .request(path, self.gl_resources.max_texture_size(), renderer.prescale_for());
+        self.decoding = Some((path.to_path_buf(), origin));
+        Ok(())
+    }
+
+    /// Upload a wallpaper decoded by the `ImageLoader`, if this output still
+    /// wants it
+    pub fn image_decoded(&mut self, decoded: &DecodedImage, qh: &QueueHandle<Canviz>) -> Result<()> {
+        if self.decoding_night.as_ref() == Some(&decoded.path) {
+            self.decoding_night = None;
+            self.night_decoded(decoded, qh)?;
+        }
+        if self.decoding_software.as_ref() == Some(&decoded.path) {
+            self.decoding_software = None;
+            self.software_decoded(decoded);
         }
+        if self.decoding.as_ref().is_none_or(|(path, _)| *path != decoded.path) {
+            return Ok(());
+        }
+        let (path, origin) = self.decoding.take().unwrap();
+        self.stats.decodes.record(decoded.elapsed);
+
+        let image = match &decoded.image {
+            Ok(image) => image.clone(),
+            Err(e) => {
+                error!("Failed to load wallpaper {:?}: {}", path, e);
+                self.last_error = Some(e.clone());
+                if let Some(ref mut renderer) = self.renderer {
+                    if !renderer.is_loading() && !renderer.has_wallpaper() {
+                        renderer.set_solid_color(30, 30, 40)?;
+                    }
+                }
+                return self.request_redraw(qh);
+            }
+        };
+
+        let result = self.upload_image(&path, image, origin);
+        self.record_result(&path, result)?;
+        self.request_redraw(qh)
+    }
 
+    fn upload_image(
+        &mut self,
+        path: &std::path::Path,
+        image: Arc<::image::RgbaImage>,
+        origin: Option<[f32; 2]>,
+    ) -> Result<()> {
+        // The GPU went away while decoding
+        let (Some(ctx), Some(renderer)) = (&self.egl_surface, &mut self.renderer) else {
+            self.current_wallpaper_path = Some(path.to_path_buf());
+            self.software_dirty = true;
+            return Ok(());
+        };
+        ctx.make_current()?;
+        renderer.load_image(path, image, origin)?;
+        self.upload_started = renderer.is_loading().then(Instant::now);
+        self.wallpaper_shown(path);
         Ok(())
     }
 
+    fn wallpaper_shown(&mut self, path: &std::path::Path) {
+        self.current_wallpaper_path = Some(path.to_path_buf());
+        // Don't count the idle time before this change as transition time
+        self.last_frame_time = None;
+        info!("Loaded wallpaper: {:?}", path);
+        if let Err(e) = self.sync_night_image() {
+            error!("Failed to load night image on {}: {:#}", self.output_name, e);
+        }
+    }
+
     /// Set scale factor for HiDPI support
     pub fn set_scale_factor(&mut self, factor: i32, qh: &QueueHandle<Canviz>) -> Result<()> {
         if factor != self.scale_factor {
@@ -256,22 +1033,102 @@ impl WallpaperSurface {
                 self.output_name, self.scale_factor, factor
             );
             self.scale_factor = factor;
+            // The viewport sizes fractionally scaled buffers instead
+            if self.preferred_scale.is_some() {
+                return Ok(());
+            }
             self.wl_surface.set_buffer_scale(factor);
 
             // Resize rendering
             if self.configured {
                 self.resize_rendering()?;
+                self.rescale_wallpaper();
             }
 
-            // Request redraw
-            self.wl_surface.frame(qh, self.wl_surface.clone());
-            self.wl_surface.commit();
+            self.request_redraw(qh)?;
         }
         Ok(())
     }
 
+    /// Render at the exact fractional scale (in 120ths) and let the viewport
+    /// map the buffer back to the logical size
+    pub fn set_preferred_scale(&mut self, scale: u32, qh: &QueueHandle<Canviz>) -> Result<()> {
+        if self.fractional_scale.is_none() || self.preferred_scale == Some(scale) {
+            return Ok(());
+        }
+        info!(
+            "Fractional scale for {}: {:.3}",
+            self.output_name,
+            scale as f64 / 120.0
+        );
+        self.preferred_scale = Some(scale);
+        self.wl_surface.set_buffer_scale(1);
+        self.update_viewport();
+
+        if self.configured {
+            self.resize_rendering()?;
+            self.rescale_wallpaper();
+        }
+        self.request_redraw(qh)
+    }
+
+    /// Size the viewport destination to the logical surface size
+    fn update_viewport(&self) {
+        if self.preferred_scale.is_none() || self.width == 0 {
+            return;
+        }
+        if let Some((_, viewport)) = &self.fractional_scale {
+            viewport.set_destination(self.width as i32, self.height as i32);
+        }
+    }
+
+    /// The surface's wp_fractional_scale_v1 object, if any
+    pub fn fractional_scale(&self) -> Option<&WpFractionalScaleV1> {
+        self.fractional_scale.as_ref().map(|(scale, _)| scale)
+    }
+
+    /// Follow the output's transform by committing pre-rotated buffers
+    pub fn set_transform(&mut self, transform: Transform, qh: &QueueHandle<Canviz>) -> Result<()> {
+        if transform == self.transform {
+            return Ok(());
+        }
+        info!(
+            "Transform changed for {}: {:?} -> {:?}",
+            self.output_name, self.transform, transform
+        );
+        self.transform = transform;
+        self.wl_surface.set_buffer_transform(transform);
+
+        if self.configured {
+            self.resize_rendering()?;
+            self.rescale_wallpaper();
+        }
+        self.request_redraw(qh)
+    }
+
     /// Internal method to render a frame without checking configured state
     fn draw_frame(&mut self, qh: &QueueHandle<Canviz>) -> Result<()> {
+        if self.software {
+            self.software_dirty = true;
+            return Ok(());
+        }
+        if self.gpu_lost.is_some() {
+            return Ok(());
+        }
+
+        match self.render_frame(qh) {
+            Err(e) => match gpu_loss(&e) {
+                Some(loss) => {
+                    self.lose_gpu(loss, &e);
+                    Ok(())
+                }
+                None => Err(e),
+            },
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn render_frame(&mut self, qh: &QueueHandle<Canviz>) -> Result<()> {
         // Calculate delta time for transitions
         let now = Instant::now();
         let delta_ms = if let Some(last) = self.last_frame_time {
@@ -282,68 +1139,626 @@ impl WallpaperSurface {
         self.last_frame_time = Some(now);
 
         // Make EGL context current
-        if let Some(ref ctx) = self.egl_context {
+        self.acquire_gpu()?;
+        if let Some(ref ctx) = self.egl_surface {
             ctx.make_current()?;
         } else {
             return Ok(());
         }
 
-        // Update and render
-        let needs_redraw = if let Some(ref mut renderer) = self.renderer {
-            let animating = renderer.update(delta_ms);
-            renderer.render();
-            animating
+        // Start the next queued change once the running transition is done
+        if self.renderer.is_some() && !self.is_changing() {
+            if let Some((path, origin)) = self.pending.pop_front() {
+                if let Err(e) = self.show_wallpaper(&path, origin) {
+                    error!("Failed to load queued wallpaper {:?}: {}", path, e);
+                }
+            }
+        }
+
+        // A renderer reacquired for a redraw rather than a change shows the
+        // same wallpaper again
+        let empty = self
+            .renderer
+            .as_ref()
+            .is_some_and(|r| !r.has_wallpaper() && !r.is_loading());
+        if empty && self.decoding.is_none() {
+            self.restore_wallpaper()?;
+        }
+
+        // Update and render. Pan and zoom keep going only while the user
+        // is around, transitions always run to the end.
+        let Some(ref mut renderer) = self.renderer else {
+            return Ok(());
+        };
+
+        // Frames of all outputs carry the upload along strip by strip
+        let was_loading = renderer.is_loading();
+        if let Err(e) = renderer.upload_step() {
+            error!("Failed to upload wallpaper on {}: {:#}", self.output_name, e);
+            self.last_error = Some(format!("{:#}", e));
+        }
+        if was_loading && !renderer.is_loading() {
+            if let Some(started) = self.upload_started.take() {
+                self.stats.uploads.record(started.elapsed());
+            }
+        }
+
+        // The released frame stays on screen until the reacquired renderer
+        // has a wallpaper to draw instead of black
+        if self.released.is_some() && !renderer.has_wallpaper() {
+            if renderer.is_uploading() && !self.frame_pending {
+                self.wl_surface.frame(qh, self.wl_surface.clone());
+                self.frame_pending = true;
+                self.wl_surface.commit();
+            }
+            return Ok(());
+        }
+
+        renderer.update(delta_ms);
+        renderer.render();
+
+        let transitioning = renderer.is_transitioning();
+        match (self.transition_started, transitioning) {
+            (None, true) => self.transition_started = Some(now),
+            (Some(started), false) => {
+                self.stats.transitions.record(now.duration_since(started));
+                self.transition_started = None;
+            }
+            _ => {}
+        }
+
+        let motion = renderer.is_motion_active() && !self.idle;
+        let busy = renderer.is_uploading() || transitioning || renderer.is_scrolling();
+        let motion_only = motion && !busy && self.pending.is_empty();
+        let needs_redraw = busy || motion || !self.pending.is_empty();
+
+        // Capped pan and zoom frames aren't late for skipping refreshes
+        let expected = if motion_only {
+            self.motion_frame_interval()
         } else {
-            false
+            Duration::ZERO
         };
+        match &self.presentation {
+            Some(presentation) => {
+                let feedback = FrameFeedback {
+                    output: self.output.clone(),
+                    continues: self.animating,
+                    expected,
+                };
+                presentation.feedback(&self.wl_surface, qh, feedback);
+            }
+            None => {
+                let at = now.duration_since(self.stats_epoch);
+                self.stats.record_frame(at, self.animating, expected);
+            }
+        }
+        self.animating = needs_redraw;
 
         // Swap buffers
-        if let Some(ref ctx) = self.egl_context {
+        if let Some(ref ctx) = self.egl_surface {
             ctx.swap_buffers()?;
         }
+        // EGL's buffer replaces the shared memory one with this commit
+        self.released = None;
+        self.recoveries = 0;
+
+        // Request next frame if still animating. The callback belongs to
+        // the next commit, so it has to be requested before committing.
+        if needs_redraw && !motion_only && !self.frame_pending {
+            self.wl_surface.frame(qh, self.wl_surface.clone());
+            self.frame_pending = true;
+        }
 
         // Mark surface as damaged
-        self.wl_surface.damage_buffer(
-            0,
-            0,
-            (self.width * self.scale_factor as u32) as i32,
-            (self.height * self.scale_factor as u32) as i32,
-        );
+        let (buffer_width, buffer_height) = self.buffer_size();
+        self.wl_surface
+            .damage_buffer(0, 0, buffer_width as i32, buffer_height as i32);
         self.wl_surface.commit();
 
-        // Request next frame if still animating
-        if needs_redraw {
-            self.wl_surface.frame(qh, self.wl_surface.clone());
+        if motion_only {
+            self.schedule_motion_frame(self.motion_frame_interval());
         }
-
         Ok(())
     }
 
+    /// Ask for a frame callback once `wait` has passed. Pan and zoom alone
+    /// don't need every frame the compositor offers, and waiting for the
+    /// callback keeps hidden outputs from drawing.
+    fn schedule_motion_frame(&mut self, wait: Duration) {
+        if self.motion_timer.is_some() {
+            return;
+        }
+
+        let output = self.output.clone();
+        let timer = self
+            .loop_handle
+            .insert_source(Timer::from_duration(wait), move |_, _, canviz| {
+                let qh = canviz.qh.clone();
+                if let Some(surface) = canviz.surfaces.get_mut(&output) {
+                    surface.motion_timer = None;
+                    if !surface.frame_pending && surface.configured {
+                        surface.wl_surface.frame(&qh, surface.wl_surface.clone());
+                        surface.frame_pending = true;
+                        surface.wl_surface.commit();
+                    }
+                }
+                TimeoutAction::Drop
+            });
+        match timer {
+            Ok(token) => self.motion_timer = Some(token),
+            Err(e) => error!("Failed to schedule the next frame of {}: {}", self.output_name, e),
+        }
+    }
+
     /// Draw the wallpaper (called from frame callback)
     pub fn draw(&mut self, qh: &QueueHandle<Canviz>) -> Result<()> {
+        self.frame_pending = false;
+
         if !self.configured {
             debug!("Surface {} not yet configured, skipping draw", self.output_name);
             return Ok(());
         }
 
+        // A callback asked for by something else may come before the frame
+        // rate cap allows the next pan and zoom frame
+        let motion_only = self.renderer.as_ref().is_some_and(|r| {
+            !r.is_transitioning()
+                && !r.is_uploading()
+                && !r.is_scrolling()
+                && r.is_motion_active()
+                && self.pending.is_empty()
+        });
+        if motion_only {
+            if self.idle {
+                return Ok(());
+            }
+            let wait = self.last_frame_time.map_or(Duration::ZERO, |last| {
+                self.motion_frame_interval().saturating_sub(last.elapsed())
+            });
+            if !wait.is_zero() {
+                self.schedule_motion_frame(wait);
+                return Ok(());
+            }
+        }
+
+        self.draw_frame(qh)
+    }
+
+    /// Scroll the wallpaper to the position of a workspace (`parallax`)
+    pub fn set_workspace(&mut self, workspace: i32, qh: &QueueHandle<Canviz>) -> Result<()> {
+        let Some(parallax) = &self.config.parallax else {
+            return Ok(());
+        };
+        let position = parallax.position(workspace);
+
+        if let Some(ref mut renderer) = self.renderer {
+            renderer.scroll_to(position);
+            self.request_redraw(qh)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the output shows a still frame that could do without the GPU
+    pub fn can_release_gpu(&self) -> bool {
+        let Some(renderer) = &self.renderer else {
+            return false;
+        };
+
+        // Parallax scrolls on every workspace switch, not worth the churn
+        self.configured
+            && !self.frame_pending
+            && self.pending.is_empty()
+            && self.decoding.is_none()
+            && self.decoding_night.is_none()
+            && !renderer.is_uploading()
+            && self.config.parallax.is_none()
+            && !renderer.is_transitioning()
+            && !renderer.is_scrolling()
+            && !renderer.is_motion_active()
+    }
+
+    /// Show the current frame from a wl_shm buffer and free the EGL surface
+    /// and textures. They come back on the next change (see `acquire_gpu`).
+    pub fn release_gpu(&mut self, shm: &Shm) -> Result<()> {
+        let (Some(ctx), Some(renderer)) = (&self.egl_surface, &self.renderer) else {
+            return Ok(());
+        };
+        ctx.make_current()?;
+
+        let (width, height) = self.buffer_size();
+        let pixels = renderer.read_frame();
+        self.present_shm(shm, &pixels)?;
+
+        // Textures have to go while the context is still current
+        self.renderer = None;
+        self.egl_surface = None;
+
+        info!(
+            "Released GPU resources for {}, holding a {}x{} frame in shared memory",
+            self.output_name, width, height
+        );
+        Ok(())
+    }
+
+    /// Commit an XRGB8888 frame of buffer size from shared memory, kept in
+    /// `released` until EGL attaches a buffer again
+    fn present_shm(&mut self, shm: &Shm, pixels: &[u8]) -> Result<()> {
+        let (width, height) = self.buffer_size();
+        let stride = width as i32 * 4;
+
+        let mut pool = SlotPool::new(pixels.len(), shm).wrap_err("Failed to create shm pool")?;
+        let (buffer, canvas) = pool
+            .create_buffer(width as i32, height as i32, stride, wl_shm::Format::Xrgb8888)
+            .wrap_err("Failed to create shm buffer")?;
+        canvas.copy_from_slice(pixels);
+
+        buffer
+            .attach_to(&self.wl_surface)
+            .wrap_err("Failed to attach shm buffer")?;
+        self.wl_surface
+            .damage_buffer(0, 0, width as i32, height as i32);
+        self.wl_surface.commit();
+
+        self.released = Some((pool, buffer));
+        Ok(())
+    }
+
+    /// Recreate the EGL surface and renderer after `release_gpu`. They start
+    /// out empty: a change uploads its own image, and `render_frame` only
+    /// restores the previous one when redrawing without a change.
+    fn acquire_gpu(&mut self) -> Result<()> {
+        if self.renderer.is_some() || self.released.is_none() || self.software {
+            return Ok(());
+        }
+
+        debug!("Reacquiring GPU resources for {}", self.output_name);
+        self.init_rendering()
+    }
+
+    /// Show the wallpaper that was on screen in a fresh renderer. It's
+    /// decoded and uploaded like any change unless another output has it,
+    /// and brings its night image along (see `wallpaper_shown`).
+    fn restore_wallpaper(&mut self) -> Result<()> {
+        self.decoding_night = None;
+        // A change still being decoded replaces it anyway
+        if self.decoding.is_some() {
+            return Ok(());
+        }
+        match self.current_wallpaper_path.clone() {
+            Some(path) => self.upload_wallpaper(&path, None),
+            None => match self.renderer {
+                Some(ref mut renderer) => renderer.set_solid_color(30, 30, 40),
+                None => Ok(()),
+            },
+        }
+    }
+
+    /// Remember that EGL lost this output's surface or the shared context
+    fn lose_gpu(&mut self, loss: GpuLoss, error: &color_eyre::Report) {
+        warn!("Lost GPU {:?} on {}: {:#}", loss, self.output_name, error);
+        // A lost context covers a lost surface
+        if self.gpu_lost != Some(GpuLoss::Context) {
+            self.gpu_lost = Some(loss);
+        }
+        self.last_error = Some(format!("{:#}", error));
+    }
+
+    /// What EGL reported lost since the last recovery
+    pub fn gpu_loss(&self) -> Option<GpuLoss> {
+        self.gpu_lost
+    }
+
+    /// Drop the renderer and EGL surface, and switch to a new shared context
+    /// after the old one was lost. Nothing is drawn until `recover_gpu`.
+    pub fn discard_gpu(&mut self, egl_context: Rc<EglContext>, gl_resources: Rc<GlResources>) {
+        self.renderer = None;
+        self.egl_surface = None;
+        self.egl_context = egl_context;
+        self.gl_resources = gl_resources;
+    }
+
+    /// Rebuild the EGL surface, renderer and textures after a loss, or give
+    /// up on the GPU after `MAX_GPU_RECOVERIES` attempts in a row
+    pub fn recover_gpu(&mut self, qh: &QueueHandle<Canviz>) {
+        self.gpu_lost = None;
+        if self.software || !self.configured {
+            return;
+        }
+
+        let Some(attempt) = next_recovery(self.recoveries) else {
+            let error = color_eyre::eyre::eyre!(
+                "GPU state lost {} times in a row",
+                MAX_GPU_RECOVERIES
+            );
+            self.use_software(&error);
+            return;
+        };
+        self.recoveries = attempt;
+
+        info!(
+            "Rebuilding GPU state for {} (attempt {}/{})",
+            self.output_name, self.recoveries, MAX_GPU_RECOVERIES
+        );
+        self.renderer = None;
+        self.egl_surface = None;
+
+        let result = self.init_rendering().and_then(|()| self.restore_wallpaper());
+        match result {
+            Ok(()) => {
+                self.last_error = None;
+                self.last_frame_time = None;
+                if let Err(e) = self.draw_frame(qh) {
+                    error!("Failed to draw {} after recovery: {:#}", self.output_name, e);
+                }
+            }
+            // Still lost, try again after the next dispatch
+            Err(e) if gpu_loss(&e).is_some() => self.lose_gpu(GpuLoss::Context, &e),
+            Err(e) => self.use_software(&e),
+        }
+    }
+
+    /// Stop using the GPU for this output and draw still frames on the CPU
+    pub fn use_software(&mut self, error: &color_eyre::Report) {
+        error!(
+            "GPU rendering failed on {}, falling back to software: {:#}",
+            self.output_name, error
+        );
+        self.renderer = None;
+        self.egl_surface = None;
+        self.gpu_lost = None;
+        self.software = true;
+        self.software_dirty = true;
+        // No transitions to wait for anymore, only the latest change counts
+        if let Some((path, _)) = self.pending.drain(..).next_back() {
+            self.current_wallpaper_path = Some(path);
+        }
+        self.last_error = Some(format!("Drawing without the GPU: {:#}", error));
+    }
+
+    /// Whether a software frame is due (see `draw_software`)
+    pub fn needs_software_frame(&self) -> bool {
+        self.software && self.software_dirty && self.configured
+    }
+
+    /// Draw the current wallpaper on the CPU, without transitions or effects.
+    /// `daylight` switches to the night image at dusk instead of blending.
+    pub fn draw_software(&mut self, shm: &Shm) -> Result<()> {
+        self.software_dirty = false;
+
+        let (width, height) = self.buffer_size();
+        let path = match self.night_image() {
+            Some(night) if self.daylight < 0.5 => Some(night),
+            _ => self.current_wallpaper_path.as_deref(),
+        };
+        let pixels = match path.map(Path::to_path_buf) {
+            Some(path) => {
+                let cached = self.software_image.as_ref().filter(|(cached, _)| *cached == path);
+                let Some((_, image)) = cached else {
+                    return self.decode_software_image(path);
+                };
+                software::render(image, self.config.mode, (width, height), self.transform)
+            }
+            None => [40, 30, 30, 255].repeat(width as usize * height as usize),
+        };
+        self.present_shm(shm, &pixels)
+    }
+
+    /// Decode the image of a software frame off-thread, the frame is drawn
+    /// once it arrives (see `software_decoded`)
+    fn decode_software_image(&mut self, path: PathBuf) -> Result<()> {
+        if self.decoding_software.as_ref() == Some(&path) {
+            return Ok(());
+        }
+        std::fs::metadata(&path).wrap_err_with(|| format!("Failed to open image: {:?}", path))?;
+        debug!("Decoding {:?} for a software frame on {}", path, self.output_name);
+        // No texture size limit to shrink it for without the GPU
+        self.loader.request(&path, u32::MAX, None);
+        self.decoding_software = Some(path);
+        Ok(())
+    }
+
+    /// Keep an image decoded by the `ImageLoader` for software frames
+    fn software_decoded(&mut self, decoded: &DecodedImage) {
+        match &decoded.image {
+            Ok(image) => {
+                self.software_image = Some((decoded.path.clone(), image.clone()));
+                self.software_dirty = true;
+            }
+            Err(e) => {
+                error!("Failed to load wallpaper {:?}: {}", decoded.path, e);
+                self.last_error = Some(e.clone());
+            }
+        }
+    }
+
+    /// Record the presentation of a frame, from wp_presentation_feedback
+    pub fn frame_presented(&mut self, at: Duration, refresh: Duration, feedback: &FrameFeedback) {
+        self.stats.set_refresh(refresh);
+        self.stats.record_frame(at, feedback.continues, feedback.expected);
+    }
+
+    /// Record a frame the compositor never showed
+    pub fn frame_discarded(&mut self) {
+        self.stats.record_discarded();
+    }
+
+    /// Refresh rate of the output in mHz, as wl_output reports it
+    pub fn set_refresh_rate(&mut self, millihertz: i32) {
+        if millihertz > 0 {
+            self.stats
+                .set_refresh(Duration::from_secs(1000) / millihertz as u32);
+        }
+    }
+
+    /// Frame timing statistics for IPC
+    pub fn stats(&self) -> MonitorStats {
+        self.stats.summary(&self.output_name)
+    }
+
+    /// Approximate GPU and CPU memory held for this output, in bytes.
+    /// Textures shared with other outputs count in full.
+    pub fn memory_usage(&self) -> (u64, u64) {
+        let mut gpu = self.surface_memory();
+        if let Some(ref renderer) = self.renderer {
+            gpu += renderer.texture_bytes();
+        }
+
+        let cpu = self.released.as_ref().map_or(0, |(pool, _)| pool.len() as u64);
+        (gpu, cpu)
+    }
+
+    /// GPU memory of the window surface itself, in bytes
+    pub fn surface_memory(&self) -> u64 {
+        if self.egl_surface.is_none() {
+            return 0;
+        }
+        // Double buffered window surface
+        let (width, height) = self.buffer_size();
+        width as u64 * height as u64 * 4 * 2
+    }
+
+    /// Textures this output draws from, see `Renderer::textures`
+    pub fn textures(&self) -> impl Iterator<Item = &Texture> {
+        self.renderer.iter().flat_map(Renderer::textures)
+    }
+
+    /// Pause or resume continuous animations while the user is idle
+    pub fn set_idle(&mut self, idle: bool, qh: &QueueHandle<Canviz>) -> Result<()> {
+        if idle == self.idle {
+            return Ok(());
+        }
+        self.idle = idle;
+
+        if !idle {
+            debug!("Resuming animations on {}", self.output_name);
+            // Don't count the idle period as animation time
+            self.last_frame_time = None;
+            self.request_redraw(qh)?;
+        }
+        Ok(())
+    }
+
+    /// Make the shared context current on this output, for GL work of all
+    /// outputs
+    pub fn make_current(&self) -> bool {
+        self.egl_surface.as_ref().is_some_and(|ctx| ctx.make_current().is_ok())
+    }
+
+    /// Whether the next wallpaper or night image is still being uploaded
+    pub fn is_uploading(&self) -> bool {
+        self.renderer.as_ref().is_some_and(Renderer::is_uploading)
+    }
+
+    /// Draw now unless a frame callback is already on its way
+    pub fn request_redraw(&mut self, qh: &QueueHandle<Canviz>) -> Result<()> {
+        if self.frame_pending || !self.configured {
+            return Ok(());
+        }
+
         self.draw_frame(qh)
     }
 
     /// Check if surface is configured and ready
-    #[allow(dead_code)]
     pub fn is_configured(&self) -> bool {
         self.configured
     }
 
     /// Get current wallpaper path
-    #[allow(dead_code)]
     pub fn current_wallpaper(&self) -> Option<&PathBuf> {
         self.current_wallpaper_path.as_ref()
     }
+
+    /// Why the wallpaper couldn't be shown, if the last attempt failed
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    /// Wallpaper picked from the monitor config
+    pub fn config_wallpaper(&self) -> Option<&PathBuf> {
+        self.config_wallpaper_path.as_ref()
+    }
 }
 
 impl Drop for WallpaperSurface {
     fn drop(&mut self) {
         info!("Destroying wallpaper surface for {}", self.output_name);
+        // GL objects can only be released with the context current
+        if let Some(ref ctx) = self.egl_surface {
+            let _ = ctx.make_current();
+        }
+        self.renderer = None;
+        self.egl_surface = None;
+        if let Some(timer) = self.motion_timer.take() {
+            self.loop_handle.remove(timer);
+        }
+        if let Some((fractional_scale, viewport)) = self.fractional_scale.take() {
+            fractional_scale.destroy();
+            viewport.destroy();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_change() {
+        let mut pending = VecDeque::new();
+        let (a, b) = (Path::new("a.png"), Path::new("b.png"));
+
+        // Without a change in progress every policy shows it right away
+        for policy in [QueuePolicy::Interrupt, QueuePolicy::Queue, QueuePolicy::Drop] {
+            assert!(queue_change(&mut pending, policy, false, a, None));
+        }
+        assert!(pending.is_empty());
+
+        assert!(queue_change(&mut pending, QueuePolicy::Interrupt, true, a, None));
+        assert!(!queue_change(&mut pending, QueuePolicy::Drop, true, a, None));
+        assert!(pending.is_empty());
+
+        // Repeats of the last queued change coalesce into one
+        assert!(!queue_change(&mut pending, QueuePolicy::Queue, true, a, None));
+        assert!(!queue_change(&mut pending, QueuePolicy::Queue, true, a, Some([0.0, 1.0])));
+        assert!(!queue_change(&mut pending, QueuePolicy::Queue, true, b, None));
+        assert!(!queue_change(&mut pending, QueuePolicy::Queue, true, a, None));
+        let queued: Vec<_> = pending.iter().cloned().collect();
+        assert_eq!(
+            queued,
+            [
+                (a.to_path_buf(), Some([0.0, 1.0])),
+                (b.to_path_buf(), None),
+                (a.to_path_buf(), None),
+            ]
+        );
+
+        // Queued changes go first, even once the transition is over
+        assert!(!queue_change(&mut pending, QueuePolicy::Queue, false, b, None));
+        assert_eq!(pending.len(), 4);
+    }
+
+    #[test]
+    fn test_buffer_size() {
+        let size = |logical, scale, transform| buffer_size(logical, Some(scale), 1, transform);
+
+        assert_eq!(size((1920, 1080), 150, Transform::Normal), (2400, 1350));
+        assert_eq!(size((1920, 1080), 150, Transform::_90), (1350, 2400));
+        assert_eq!(size((1920, 1080), 180, Transform::_270), (1620, 2880));
+        assert_eq!(size((1920, 1080), 180, Transform::Flipped180), (2880, 1620));
+        // 1707.5 and 961.25 pixels, rounded half away from zero
+        assert_eq!(size((1366, 769), 150, Transform::Normal), (1708, 961));
+        assert_eq!(size((1366, 769), 150, Transform::_270), (961, 1708));
+        assert_eq!(size((1366, 769), 180, Transform::_90), (1154, 2049));
+
+        // Integer scale without fractional-scale-v1
+        assert_eq!(buffer_size((1280, 800), None, 2, Transform::_90), (1600, 2560));
+    }
+
+    #[test]
+    fn test_next_recovery() {
+        let attempts: Vec<_> = std::iter::successors(next_recovery(0), |&n| next_recovery(n)).collect();
+        assert_eq!(attempts, [1, 2, 3]);
+        // The attempt after the last one falls back to software
+        assert_eq!(next_recovery(MAX_GPU_RECOVERIES), None);
     }
 }
@@ -0,0 +1,177 @@
+use crate::ipc::{MonitorStats, TimingStats};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Samples kept per measurement for averages and percentiles
+const WINDOW: usize = 240;
+
+/// A frame is late when it took this many refresh intervals or more
+const LATE_FACTOR: f64 = 1.5;
+
+/// Durations of one kind of event, the most recent ones kept
+#[derive(Debug, Default)]
+pub struct Timing {
+    samples: VecDeque<Duration>,
+    count: u64,
+}
+
+impl Timing {
+    pub fn record(&mut self, sample: Duration) {
+        if self.samples.len() == WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+        self.count += 1;
+    }
+
+    pub fn summary(&self) -> TimingStats {
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let mut sorted: Vec<Duration> = self.samples.iter().copied().collect();
+        sorted.sort();
+
+        let Some(max) = sorted.last().copied() else {
+            return TimingStats::default();
+        };
+        let p95 = sorted[(sorted.len() * 95).div_ceil(100) - 1];
+        let total: Duration = sorted.iter().sum();
+
+        TimingStats {
+            count: self.count,
+            last_ms: self.samples.back().copied().map_or(0.0, ms),
+            avg_ms: ms(total) / sorted.len() as f64,
+            p95_ms: ms(p95),
+            max_ms: ms(max),
+        }
+    }
+}
+
+/// Frame timing of one output
+#[derive(Debug, Default)]
+pub struct FrameStats {
+    frames: u64,
+    late: u64,
+    dropped: u64,
+    frame_times: Timing,
+    /// When the previous frame of the running animation was shown
+    last_frame: Option<Duration>,
+    /// Refresh interval of the output, from its mode or presentation feedback
+    refresh: Option<Duration>,
+    presentation: bool,
+    pub transitions: Timing,
+    pub uploads: Timing,
+    pub decodes: Timing,
+}
+
+impl FrameStats {
+    /// Record a frame shown at `at` (on any monotonic clock). Only frames
+    /// that `continue` an animation are timed against the previous one, and
+    /// they should follow it within `expected` (at least one refresh).
+    pub fn record_frame(&mut self, at: Duration, continues: bool, expected: Duration) {
+        self.frames += 1;
+        let previous = self.last_frame.replace(at);
+        let Some(previous) = previous.filter(|_| continues) else {
+            return;
+        };
+
+        let interval = at.saturating_sub(previous);
+        self.frame_times.record(interval);
+
+        let expected = expected.max(self.refresh.unwrap_or_default());
+        if expected.is_zero() {
+            return;
+        }
+        let refreshes = interval.as_secs_f64() / expected.as_secs_f64();
+        if refreshes >= LATE_FACTOR {
+            self.late += 1;
+            self.dropped += (refreshes.round() as u64).saturating_sub(1);
+        }
+    }
+
+    /// A frame the compositor never showed
+    pub fn record_discarded(&mut self) {
+        self.dropped += 1;
+    }
+
+    pub fn set_refresh(&mut self, refresh: Duration) {
+        if !refresh.is_zero() {
+            self.refresh = Some(refresh);
+        }
+    }
+
+    /// Frame times come from wp_presentation feedback
+    pub fn set_presentation(&mut self, presentation: bool) {
+        self.presentation = presentation;
+    }
+
+    pub fn summary(&self, name: &str) -> MonitorStats {
+        MonitorStats {
+            name: name.to_string(),
+            frames: self.frames,
+            late_frames: self.late,
+            dropped_frames: self.dropped,
+            refresh_ms: self.refresh.map(|refresh| refresh.as_secs_f64() * 1000.0),
+            presentation_feedback: self.presentation,
+            frame_time: self.frame_times.summary(),
+            transitions: self.transitions.summary(),
+            uploads: self.uploads.summary(),
+            decodes: self.decodes.summary(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REFRESH: Duration = Duration::from_micros(16_667);
+
+    #[test]
+    fn test_timing_summary() {
+        let mut timing = Timing::default();
+        assert_eq!(timing.summary().count, 0);
+
+        for ms in 1..=100 {
+            timing.record(Duration::from_millis(ms));
+        }
+        let summary = timing.summary();
+        assert_eq!(summary.count, 100);
+        assert_eq!(summary.last_ms, 100.0);
+        assert_eq!(summary.max_ms, 100.0);
+        assert_eq!(summary.p95_ms, 95.0);
+        assert!((summary.avg_ms - 50.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_late_and_dropped_frames() {
+        let mut stats = FrameStats::default();
+        stats.set_refresh(REFRESH);
+
+        // Smooth frames, then one that missed two refreshes
+        for frame in 0..10 {
+            stats.record_frame(REFRESH * frame, frame > 0, Duration::ZERO);
+        }
+        stats.record_frame(REFRESH * 12, true, Duration::ZERO);
+
+        let summary = stats.summary("DP-1");
+        assert_eq!(summary.frames, 11);
+        assert_eq!(summary.frame_time.count, 10);
+        assert_eq!(summary.late_frames, 1);
+        assert_eq!(summary.dropped_frames, 2);
+    }
+
+    #[test]
+    fn test_pauses_between_animations_are_not_late() {
+        let mut stats = FrameStats::default();
+        stats.set_refresh(REFRESH);
+
+        stats.record_frame(Duration::ZERO, false, Duration::ZERO);
+        stats.record_frame(Duration::from_secs(60), false, Duration::ZERO);
+        // Capped pan and zoom frames are expected every other refresh
+        stats.record_frame(Duration::from_secs(60) + REFRESH * 2, true, REFRESH * 2);
+
+        let summary = stats.summary("DP-1");
+        assert_eq!(summary.frame_time.count, 1);
+        assert_eq!(summary.late_frames, 0);
+        assert_eq!(summary.dropped_frames, 0);
+    }
+}